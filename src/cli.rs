@@ -0,0 +1,410 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::config::{Families, NodeConfig};
+use crate::crypto::keystore::{Keystore, KeystoreError};
+use crate::crypto::{generate_keypair, keypair_from_secret, Keypair};
+use crate::discovery::client::ClientError;
+use crate::discovery::dns::{DnsError, TreeUrl};
+use crate::discovery::enr::{Enr, EnrError, TEXT_PREFIX};
+use crate::discovery::table::PeerFilter;
+use crate::discovery::service::ServiceError;
+use crate::spec::{ChainSpec, SpecError};
+
+pub const USAGE: &str = "\
+usage: EthNetLite <command> [options]
+
+commands:
+  run                      start a node
+    --listen <ip:port>       address to listen on (default 127.0.0.1:30303);
+                             [::]:<port> serves IPv4 and IPv6
+    --ipv6-only              with an IPv6 --listen address, refuse IPv4
+    --advertise-ip <ip>      address to publish in our ENR, one per family;
+                             repeat or comma-separate (default: --listen)
+    --bootnode <enr|host:port|enrtree://<key>@<domain>>
+                             node to join through, or a signed DNS tree of
+                             them (EIP-1459); repeat or comma-separate. geth's
+                             EIP-778 records are dialed on their tcp port
+    --topic <name>           advertise membership of a topic (e.g. shard/3)
+                             and look for peers in it; repeat or comma-separate
+    --discv5-port <port>     also run discv5 over UDP on this port, pinging
+                             and querying peers that advertise one
+    --chain dev|<spec.json>  chain to follow (default dev)
+    --fork-choice longest|heaviest|ghost
+                             override the spec's fork-choice rule
+    --checkpoint <number>:<hash> [--backfill]
+                             start from a trusted header instead of genesis
+    --data-dir <path>        persist the chain, node key and known peers here
+                             (default: in memory, with a fresh key)
+    --password-file <file>   encrypt a new node key with this password, or
+                             unlock an encrypted one
+    --produce                seal a block every slot with a fresh key
+    --producer-key <file>    seal blocks with the key in <file> (see keygen);
+                             a keystore needs --password-file
+    --log-level <level>      error|warn|info|debug|trace (default info)
+  keygen [--out <file>] [--password-file <file>]
+                           create a key; the secret goes to <file>, as an
+                           encrypted keystore if a password is given
+  enr show <host:port> [--chain ...]
+                           ask a running node for its record
+  enr decode <enr>         print the fields of a record, ours or EIP-778
+  peers <host:port> [--chain ...] [--cap <protocol>] [--topic <name>]
+                           list the peers a running node knows about, only
+                           those serving <protocol> or in <name> if given
+  help                     print this message";
+
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("{0}")]
+    Usage(String),
+
+    #[error(transparent)]
+    Spec(#[from] SpecError),
+
+    #[error("invalid node record: {0}")]
+    Enr(#[from] EnrError),
+
+    #[error("bootnode {0:?} does not resolve to an address")]
+    Bootnode(String),
+
+    #[error(transparent)]
+    Dns(#[from] DnsError),
+
+    #[error("cannot read {0}: {1}")]
+    Read(PathBuf, io::Error),
+
+    #[error("{0} does not hold a hex secret key")]
+    BadKey(PathBuf),
+
+    #[error(transparent)]
+    Keystore(#[from] KeystoreError),
+
+    #[error("cannot write {0}: {1}")]
+    Write(PathBuf, io::Error),
+
+    #[error("cannot listen on {0}: {1}")]
+    Listen(SocketAddr, io::Error),
+
+    #[error(transparent)]
+    Service(#[from] ServiceError),
+
+    #[error(transparent)]
+    Client(#[from] ClientError),
+}
+
+impl CliError {
+    /// Whether the command line itself was wrong, so usage is worth showing.
+    pub fn is_usage(&self) -> bool {
+        matches!(self, CliError::Usage(_))
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Run(Box<RunArgs>),
+    Keygen { out: Option<PathBuf>, password: Option<String> },
+    EnrShow { node: SocketAddr, chain: ChainSpec },
+    EnrDecode { enr: Enr },
+    Peers { node: SocketAddr, chain: ChainSpec, filter: PeerFilter },
+    Help,
+}
+
+#[derive(Debug)]
+pub struct RunArgs {
+    pub bootnodes: Vec<SocketAddr>,
+    // resolved once the node is up
+    pub trees: Vec<TreeUrl>,
+    pub config: NodeConfig,
+    // unlocks (or encrypts) the node key in the data dir
+    pub password: Option<String>,
+    pub log_level: tracing::Level,
+}
+
+/// Parse the arguments after the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, CliError> {
+    let mut args = Args(args.into_iter().collect());
+    let Some(cmd) = args.next() else {
+        return Err(usage("no command given"));
+    };
+    match cmd.as_str() {
+        "run" => parse_run(args).map(|run| Command::Run(Box::new(run))),
+        "keygen" => {
+            let (mut out, mut password) = (None, None);
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--out" => out = Some(args.value(&flag)?.into()),
+                    "--password-file" => password = Some(read_password(&args.value(&flag)?)?),
+                    _ => return Err(unknown(&flag)),
+                }
+            }
+            if password.is_some() && out.is_none() {
+                return Err(usage("--password-file needs --out"));
+            }
+            Ok(Command::Keygen { out, password })
+        }
+        "enr" => match args.next().as_deref() {
+            Some("show") => {
+                let (node, chain) = parse_query(args, "enr show", None)?;
+                Ok(Command::EnrShow { node, chain })
+            }
+            Some("decode") => {
+                let enr = args.positional("enr decode", "<enr>")?.parse()?;
+                args.finish()?;
+                Ok(Command::EnrDecode { enr })
+            }
+            Some(other) => Err(usage(format!("unknown enr subcommand {other:?}"))),
+            None => Err(usage("enr needs a subcommand: show or decode")),
+        },
+        "peers" => {
+            let mut filter = PeerFilter::default();
+            let (node, chain) = parse_query(args, "peers", Some(&mut filter))?;
+            Ok(Command::Peers { node, chain, filter })
+        }
+        "help" | "--help" | "-h" => Ok(Command::Help),
+        other => Err(usage(format!("unknown command {other:?}"))),
+    }
+}
+
+fn parse_run(mut args: Args) -> Result<RunArgs, CliError> {
+    let mut config = NodeConfig::default();
+    let mut bootnodes: Vec<String> = vec![];
+    let mut password = None;
+    let mut producer_key = None;
+    let mut chain = None;
+    let mut fork_choice = None;
+    let mut checkpoint = None;
+    let mut backfill = false;
+    let mut log_level = tracing::Level::INFO;
+
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--listen" => config.listen = parse_value(&flag, &args.value(&flag)?)?,
+            "--ipv6-only" => config.ipv6_only = true,
+            "--advertise-ip" => {
+                for ip in split_list(&args.value(&flag)?) {
+                    config.advertise.push(parse_value::<IpAddr>(&flag, &ip)?);
+                }
+            }
+            "--bootnode" | "--bootnodes" => bootnodes.extend(split_list(&args.value(&flag)?)),
+            "--discv5-port" => config.discv5 = Some(parse_value(&flag, &args.value(&flag)?)?),
+            "--topic" | "--topics" => config.topics.extend(split_list(&args.value(&flag)?)),
+            "--chain" => chain = Some(ChainSpec::load(&args.value(&flag)?)?),
+            "--fork-choice" => fork_choice = Some(parse_value(&flag, &args.value(&flag)?)?),
+            "--checkpoint" => checkpoint = Some(args.value(&flag)?.parse()?),
+            "--backfill" => backfill = true,
+            "--data-dir" => config.data_dir = Some(PathBuf::from(args.value(&flag)?)),
+            "--produce" => {
+                if config.producer.is_none() {
+                    config.producer = Some(generate_keypair().expect("producer keygen"));
+                }
+            }
+            "--producer-key" => producer_key = Some(PathBuf::from(args.value(&flag)?)),
+            "--password-file" => password = Some(read_password(&args.value(&flag)?)?),
+            "--log-level" => log_level = parse_value(&flag, &args.value(&flag)?)?,
+            _ => return Err(unknown(&flag)),
+        }
+    }
+
+    if config.ipv6_only && config.listen.is_ipv4() {
+        return Err(usage("--ipv6-only needs an IPv6 --listen address"));
+    }
+    let families = config.families();
+    if let Some(ip) = config.advertise.iter().find(|ip| !families.reaches(&(**ip, 0).into())) {
+        return Err(usage(format!("cannot advertise {ip}: the socket does not serve its family")));
+    }
+
+    // read last: it may need the password
+    if let Some(path) = producer_key {
+        config.producer = Some(load_key(&path, password.as_deref())?);
+    }
+    if let Some(chain) = chain {
+        config.chain = chain;
+    }
+    // flags override the spec
+    if let Some(rule) = fork_choice {
+        config.chain.fork_choice = rule;
+    }
+    if checkpoint.is_some() {
+        config.chain.checkpoint = checkpoint;
+    }
+    if backfill {
+        let Some(cp) = config.chain.checkpoint.as_mut() else {
+            return Err(usage("--backfill needs a checkpoint"));
+        };
+        cp.backfill = true;
+    }
+    bootnodes.extend(config.chain.bootnodes.iter().cloned());
+    let (trees, bootnodes): (Vec<String>, Vec<String>) =
+        bootnodes.into_iter().partition(|b| b.starts_with("enrtree://"));
+    let trees = trees.iter().map(|t| t.parse()).collect::<Result<_, _>>()?;
+    let bootnodes = bootnodes
+        .iter()
+        .map(|b| resolve_bootnode(b, families))
+        .collect::<Result<_, _>>()?;
+
+    Ok(RunArgs {
+        bootnodes,
+        trees,
+        config,
+        password,
+        log_level,
+    })
+}
+
+// `<host:port> [--chain ...]`, shared by the commands that query a running
+// node; those taking a `filter` also accept `--cap` and `--topic`
+fn parse_query(
+    mut args: Args,
+    cmd: &str,
+    mut filter: Option<&mut PeerFilter>,
+) -> Result<(SocketAddr, ChainSpec), CliError> {
+    let node = args.positional(cmd, "<host:port>")?;
+    let node = resolve_bootnode(&node, Families::ANY)?;
+    let mut chain = ChainSpec::dev();
+    while let Some(flag) = args.next() {
+        match (flag.as_str(), filter.as_deref_mut()) {
+            ("--chain", _) => chain = ChainSpec::load(&args.value(&flag)?)?,
+            ("--cap", Some(f)) => f.caps.push(args.value(&flag)?),
+            ("--topic", Some(f)) => f.topic = Some(args.value(&flag)?),
+            _ => return Err(unknown(&flag)),
+        }
+    }
+    Ok((node, chain))
+}
+
+/// A bootnode is either a node record or anything `host:port` resolves
+/// to; either way, the first address a socket serving `families` can reach.
+pub fn resolve_bootnode(s: &str, families: Families) -> Result<SocketAddr, CliError> {
+    if s.starts_with("enr:") || s.starts_with(TEXT_PREFIX) {
+        return Ok(s.parse::<Enr>()?.dial_addr(families)?);
+    }
+    s.to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.find(|a| families.reaches(a)))
+        .ok_or_else(|| CliError::Bootnode(s.to_string()))
+}
+
+// the first line of the file, so passwords never show up in `ps`
+fn read_password(path: &str) -> Result<String, CliError> {
+    let text = std::fs::read_to_string(path).map_err(|e| CliError::Read(path.into(), e))?;
+    Ok(text.lines().next().unwrap_or_default().to_string())
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',').filter(|s| !s.is_empty()).map(String::from).collect()
+}
+
+/// Read a secret key written by `keygen`: plain hex, or a keystore
+/// unlocked with `password`.
+pub fn load_key(path: &Path, password: Option<&str>) -> Result<Keypair, CliError> {
+    let text = std::fs::read_to_string(path).map_err(|e| CliError::Read(path.into(), e))?;
+    if text.trim_start().starts_with('{') {
+        let Some(password) = password else {
+            return Err(KeystoreError::NeedPassword(path.display().to_string()).into());
+        };
+        let ks: Keystore = serde_json::from_str(&text).map_err(KeystoreError::from)?;
+        return Ok(ks.decrypt(password)?);
+    }
+    let secret = hex::decode(text.trim().trim_start_matches("0x"))
+        .map_err(|_| CliError::BadKey(path.into()))?;
+    keypair_from_secret(&secret).map_err(|_| CliError::BadKey(path.into()))
+}
+
+struct Args(VecDeque<String>);
+
+impl Args {
+    fn next(&mut self) -> Option<String> {
+        self.0.pop_front()
+    }
+
+    fn value(&mut self, flag: &str) -> Result<String, CliError> {
+        match self.0.front() {
+            Some(v) if !v.starts_with("--") => Ok(self.next().unwrap()),
+            _ => Err(usage(format!("{flag} needs a value"))),
+        }
+    }
+
+    fn positional(&mut self, cmd: &str, what: &str) -> Result<String, CliError> {
+        match self.0.front() {
+            Some(v) if !v.starts_with("--") => Ok(self.next().unwrap()),
+            _ => Err(usage(format!("{cmd} needs {what}"))),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), CliError> {
+        match self.next() {
+            Some(extra) => Err(unknown(&extra)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: &str) -> Result<T, CliError>
+where
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| usage(format!("invalid value {value:?} for {flag}: {e}")))
+}
+
+fn usage(msg: impl Into<String>) -> CliError {
+    CliError::Usage(msg.into())
+}
+
+fn unknown(arg: &str) -> CliError {
+    usage(format!("unexpected argument {arg:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(line: &str) -> Result<Command, CliError> {
+        parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn run_takes_flags_in_any_order() {
+        let key = generate_keypair().unwrap();
+        let enr = Enr::new_local(&key, &["127.0.0.1".parse().unwrap()], 9002).to_text();
+        let line = format!(
+            "run --fork-choice ghost --bootnode 127.0.0.1:9001,{enr} --listen 127.0.0.1:9003 \
+             --checkpoint 80:0xab --backfill --log-level debug"
+        );
+        let Command::Run(run) = parse_str(&line).unwrap() else { panic!("expected run") };
+        assert_eq!(run.config.listen, "127.0.0.1:9003".parse().unwrap());
+        assert_eq!(
+            run.bootnodes,
+            vec!["127.0.0.1:9001".parse().unwrap(), "127.0.0.1:9002".parse().unwrap()]
+        );
+        assert_eq!(run.config.chain.fork_choice.to_string(), "ghost");
+        assert!(run.config.chain.checkpoint.unwrap().backfill);
+        assert_eq!(run.log_level, tracing::Level::DEBUG);
+        assert!(run.config.producer.is_none());
+    }
+
+    #[test]
+    fn bad_input_is_reported_not_panicked() {
+        let err = |line| parse_str(line).unwrap_err().to_string();
+        assert_eq!(err(""), "no command given");
+        assert_eq!(err("run --listen"), "--listen needs a value");
+        assert!(err("run --listen 9001").starts_with("invalid value \"9001\" for --listen"));
+        assert_eq!(err("run --backfill"), "--backfill needs a checkpoint");
+        assert_eq!(err("run --bogus"), "unexpected argument \"--bogus\"");
+        assert_eq!(err("peers"), "peers needs <host:port>");
+        assert_eq!(err("enr show 127.0.0.1:9001 --topic x"), "unexpected argument \"--topic\"");
+        assert!(err("enr decode 1234").contains("enr:"));
+        assert!(parse_str("run --fork-choice fastest").unwrap_err().is_usage());
+        assert_eq!(err("run --ipv6-only"), "--ipv6-only needs an IPv6 --listen address");
+        assert!(err("run --advertise-ip ::1").starts_with("cannot advertise ::1"));
+        assert_eq!(err("run --bootnode [::1]:9001"), "bootnode \"[::1]:9001\" does not resolve to an address");
+        assert!(parse_str("run --listen [::]:9001 --bootnode [::1]:9001,127.0.0.1:9002").is_ok());
+    }
+}
@@ -0,0 +1,85 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+
+use crate::crypto::Keypair;
+use crate::spec::ChainSpec;
+
+/// Runtime knobs for a single node.
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    pub chain: ChainSpec,
+    // where the chain is persisted; None keeps everything in memory
+    pub data_dir: Option<PathBuf>,
+    // seals a block every slot with this key; None only follows the chain
+    pub producer: Option<Keypair>,
+    pub listen: SocketAddr,
+    // with an IPv6 `listen` address, refuse IPv4 instead of serving both
+    pub ipv6_only: bool,
+    // what goes into our ENR; empty derives it from `listen`
+    pub advertise: Vec<IpAddr>,
+    // application topics we advertise and look for peers in
+    pub topics: Vec<String>,
+    // UDP port for discv5 on the `listen` address; None leaves it off
+    pub discv5: Option<u16>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            chain: ChainSpec::dev(),
+            data_dir: None,
+            producer: None,
+            listen: (Ipv4Addr::LOCALHOST, 30303).into(),
+            ipv6_only: false,
+            advertise: vec![],
+            topics: vec![],
+            discv5: None,
+        }
+    }
+}
+
+/// The address families a socket can exchange packets with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Families {
+    pub v4: bool,
+    pub v6: bool,
+}
+
+impl Families {
+    pub const ANY: Families = Families { v4: true, v6: true };
+
+    pub fn reaches(&self, addr: &SocketAddr) -> bool {
+        match addr {
+            SocketAddr::V4(_) => self.v4,
+            SocketAddr::V6(_) => self.v6,
+        }
+    }
+}
+
+impl NodeConfig {
+    /// An IPv6 socket also carries IPv4 unless it is made IPv6-only.
+    pub fn families(&self) -> Families {
+        match self.listen {
+            SocketAddr::V4(_) => Families { v4: true, v6: false },
+            SocketAddr::V6(_) => Families {
+                v4: !self.ipv6_only,
+                v6: true,
+            },
+        }
+    }
+
+    /// Addresses to put in our ENR. Unless set explicitly, that is the
+    /// bind address, or loopback for each family a wildcard bind serves.
+    pub fn advertised(&self) -> Vec<IpAddr> {
+        if !self.advertise.is_empty() {
+            return self.advertise.clone();
+        }
+        if !self.listen.ip().is_unspecified() {
+            return vec![self.listen.ip()];
+        }
+        let families = self.families();
+        let v4 = families.v4.then_some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let v6 = families.v6.then_some(IpAddr::V6(Ipv6Addr::LOCALHOST));
+        v4.into_iter().chain(v6).collect()
+    }
+}
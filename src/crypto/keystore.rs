@@ -0,0 +1,228 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::crypto::{generate_keypair, keccak256, keypair_from_secret, node_id_from_pubkey, Keypair};
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+// geth's "light" scrypt cost: fine for a key that is read once per start
+const SCRYPT_LOG_N: u8 = 12;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 6;
+
+#[derive(Debug, Error)]
+pub enum KeystoreError {
+    #[error("key file: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("keystore is not valid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("keystore uses unsupported {0}")]
+    Unsupported(String),
+
+    #[error("wrong password for the keystore")]
+    WrongPassword,
+
+    #[error("{0} is encrypted; a password is needed")]
+    NeedPassword(String),
+
+    #[error("key file does not hold a valid secret key")]
+    BadKey,
+}
+
+/// An encrypted secret key in the Web3 Secret Storage (v3) format that
+/// Ethereum wallets and clients use: scrypt stretches the password, AES-128-CTR
+/// encrypts the key and a keccak MAC detects a wrong password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    pub id: String,
+    pub address: String,
+    pub crypto: CryptoSection,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CryptoSection {
+    pub cipher: String,
+    pub ciphertext: String,
+    pub cipherparams: CipherParams,
+    pub kdf: String,
+    pub kdfparams: KdfParams,
+    pub mac: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CipherParams {
+    pub iv: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub dklen: usize,
+    pub n: u64,
+    pub r: u32,
+    pub p: u32,
+    pub salt: String,
+}
+
+impl Keystore {
+    pub fn encrypt(keypair: &Keypair, password: &str) -> Self {
+        let mut salt = [0u8; 32];
+        let mut iv = [0u8; 16];
+        let mut id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut iv);
+        rand::thread_rng().fill_bytes(&mut id);
+
+        let kdfparams = KdfParams {
+            dklen: 32,
+            n: 1 << SCRYPT_LOG_N,
+            r: SCRYPT_R,
+            p: SCRYPT_P,
+            salt: hex::encode(salt),
+        };
+        let dk = derive_key(password, &kdfparams).expect("valid scrypt params");
+        let mut ciphertext = keypair.signing_key.to_bytes().to_vec();
+        Aes128Ctr::new(dk[..16].into(), &iv.into()).apply_keystream(&mut ciphertext);
+
+        Self {
+            version: 3,
+            id: uuid(id),
+            address: hex::encode(&node_id_from_pubkey(&keypair.verifying_key)[12..]),
+            crypto: CryptoSection {
+                cipher: "aes-128-ctr".into(),
+                mac: hex::encode(mac(&dk, &ciphertext)),
+                ciphertext: hex::encode(ciphertext),
+                cipherparams: CipherParams { iv: hex::encode(iv) },
+                kdf: "scrypt".into(),
+                kdfparams,
+            },
+        }
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<Keypair, KeystoreError> {
+        let c = &self.crypto;
+        if c.cipher != "aes-128-ctr" {
+            return Err(KeystoreError::Unsupported(format!("cipher {}", c.cipher)));
+        }
+        if c.kdf != "scrypt" {
+            return Err(KeystoreError::Unsupported(format!("kdf {}", c.kdf)));
+        }
+        let unhex = |s: &str| hex::decode(s).map_err(|_| KeystoreError::BadKey);
+        let mut secret = unhex(&c.ciphertext)?;
+        let iv: [u8; 16] = unhex(&c.cipherparams.iv)?
+            .try_into()
+            .map_err(|_| KeystoreError::BadKey)?;
+
+        let dk = derive_key(password, &c.kdfparams)?;
+        if hex::encode(mac(&dk, &secret)) != c.mac.to_lowercase() {
+            return Err(KeystoreError::WrongPassword);
+        }
+        Aes128Ctr::new(dk[..16].into(), &iv.into()).apply_keystream(&mut secret);
+        keypair_from_secret(&secret).map_err(|_| KeystoreError::BadKey)
+    }
+}
+
+/// The node's identity key in `dir`, created on first start. With a
+/// password it lives in `nodekey.json` as a keystore, otherwise in
+/// `nodekey` as plain hex.
+pub fn load_or_create_node_key(dir: &Path, password: Option<&str>) -> Result<Keypair, KeystoreError> {
+    let encrypted = dir.join("nodekey.json");
+    let plain = dir.join("nodekey");
+
+    if encrypted.exists() {
+        let Some(password) = password else {
+            return Err(KeystoreError::NeedPassword(encrypted.display().to_string()));
+        };
+        let ks: Keystore = serde_json::from_slice(&fs::read(&encrypted)?)?;
+        return ks.decrypt(password);
+    }
+    if plain.exists() {
+        let secret = hex::decode(fs::read_to_string(&plain)?.trim()).map_err(|_| KeystoreError::BadKey)?;
+        return keypair_from_secret(&secret).map_err(|_| KeystoreError::BadKey);
+    }
+
+    let keypair = generate_keypair().expect("node keygen");
+    fs::create_dir_all(dir)?;
+    match password {
+        Some(password) => {
+            let ks = Keystore::encrypt(&keypair, password);
+            write_secret(&encrypted, &serde_json::to_vec_pretty(&ks)?)?;
+        }
+        None => write_secret(&plain, hex::encode(keypair.signing_key.to_bytes()).as_bytes())?,
+    }
+    Ok(keypair)
+}
+
+/// Create `path` readable by its owner only; never overwrites.
+pub fn write_secret(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut opts = OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+    let mut f = opts.open(path)?;
+    f.write_all(contents)?;
+    f.write_all(b"\n")
+}
+
+fn derive_key(password: &str, p: &KdfParams) -> Result<Vec<u8>, KeystoreError> {
+    let bad = || KeystoreError::Unsupported("scrypt parameters".into());
+    if !p.n.is_power_of_two() || p.dklen < 32 {
+        return Err(bad());
+    }
+    let params = scrypt::Params::new(p.n.trailing_zeros() as u8, p.r, p.p, p.dklen).map_err(|_| bad())?;
+    let salt = hex::decode(&p.salt).map_err(|_| KeystoreError::BadKey)?;
+    let mut dk = vec![0u8; p.dklen];
+    scrypt::scrypt(password.as_bytes(), &salt, &params, &mut dk).map_err(|_| bad())?;
+    Ok(dk)
+}
+
+fn mac(dk: &[u8], ciphertext: &[u8]) -> [u8; 32] {
+    keccak256(&[&dk[16..32], ciphertext].concat())
+}
+
+// random (version 4) UUID
+fn uuid(mut b: [u8; 16]) -> String {
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let h = hex::encode(b);
+    format!("{}-{}-{}-{}-{}", &h[..8], &h[8..12], &h[12..16], &h[16..20], &h[20..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_key_survives_restarts_encrypted_or_not() {
+        let dir = std::env::temp_dir().join(format!("ethnetlite-key-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let plain = load_or_create_node_key(&dir.join("a"), None).unwrap();
+        let again = load_or_create_node_key(&dir.join("a"), None).unwrap();
+        assert_eq!(plain.verifying_key, again.verifying_key);
+
+        let locked = load_or_create_node_key(&dir.join("b"), Some("hunter2")).unwrap();
+        let text = fs::read_to_string(dir.join("b/nodekey.json")).unwrap();
+        assert!(!text.contains(&hex::encode(locked.signing_key.to_bytes())));
+        let again = load_or_create_node_key(&dir.join("b"), Some("hunter2")).unwrap();
+        assert_eq!(locked.verifying_key, again.verifying_key);
+
+        assert!(matches!(
+            load_or_create_node_key(&dir.join("b"), Some("hunter3")),
+            Err(KeystoreError::WrongPassword)
+        ));
+        assert!(matches!(
+            load_or_create_node_key(&dir.join("b"), None),
+            Err(KeystoreError::NeedPassword(_))
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,5 +1,7 @@
+pub mod keystore;
+
 use k256::ecdsa::{
-    signature::{Signer, Verifier},
+    signature::hazmat::{PrehashSigner, PrehashVerifier},
     Signature, SigningKey, VerifyingKey,
 };
 
@@ -8,7 +10,7 @@ use sha3::{Digest, Keccak256};
 use thiserror::Error;
 
 // NodeId is keccak256(uncompressed_public_key[1..]) -> 32 bytes)
-pub type NodeId - [u8; 32];
+pub type NodeId = [u8; 32];
 
 
 // Wrapper keypair type for convenience.
@@ -28,12 +30,25 @@ pub enum CryptoError {
 
    #[error("invalid signature")]
    InvalidSignature,
+
+   #[error("invalid secret key")]
+   InvalidSecretKey,
 }
 
 // Generate a fresh secp256k1 keypair using OS RNG
 pub fn generate_keypair() -> Result<Keypair, CryptoError> {
     let signing_key = SigningKey::random(&mut OsRng);
-    let verifying_key = signing_key.verifying_key();
+    let verifying_key = *signing_key.verifying_key();
+    Ok(Keypair {
+        signing_key,
+        verifying_key,
+    })
+}
+
+// Rebuild a keypair from a 32-byte secret key, e.g. one written by `keygen`
+pub fn keypair_from_secret(secret: &[u8]) -> Result<Keypair, CryptoError> {
+    let signing_key = SigningKey::from_slice(secret).map_err(|_| CryptoError::InvalidSecretKey)?;
+    let verifying_key = *signing_key.verifying_key();
     Ok(Keypair {
         signing_key,
         verifying_key,
@@ -59,21 +74,21 @@ pub fn node_id_from_pubkey(verifying_key: &VerifyingKey) -> NodeId {
 
     // bytes[0] is 0x04, skip it -> [x||y] = 64 bytes
     keccak256(&bytes[1..])
-})
+}
 
 // signing a 32 byte message hash with the given signbing key
 // NOTE: the input MUST already be hashed ( e.g. keccak 256)
 pub fn sign_message(
     signing_key: &SigningKey,
     msg_hash: &[u8;32],
- -> result<Signature, CryptoError> {
+) -> Result<Signature, CryptoError> {
      // k256 expects arbitrary byte slices, but for Ethereum-like usage
     // we pass the 32-byte digest directly.
     let signature: Signature  =signing_key
-    .try_sogn(msg_hash)
+    .sign_prehash(msg_hash)
     .map_err(|e| CryptoError::SignError(e.to_string()))?;
     Ok(signature)
- })
+ }
 
  // verify a signature over  32-byte message hash
  pub fn verify_signature(
@@ -81,7 +96,7 @@ pub fn sign_message(
     msg_hash: &[u8; 32],
     signature: &Signature,
  ) -> bool {
-    verifying_key.verify(msh_hash, signature).is_ok()
+    verifying_key.verify_prehash(msg_hash, signature).is_ok()
  }
 #[cfg(test)]
 mod tests {
@@ -95,7 +110,7 @@ mod tests {
         println!("node_id = 0x{}", hex_encode(node_id));
 
         let msg = b"test message";
-        let msg_hash = keccal(msg);
+        let msg_hash = keccak256(msg);
 
         let sig = sign_message(&kp.signing_key, &msg_hash).expect("sign failed");
         assert!(verify_signature(&kp.verifying_key, &msg_hash, &sig));
@@ -0,0 +1,101 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use quinn::Connection;
+use thiserror::Error;
+use tokio::io::AsyncReadExt;
+use tokio::time::timeout;
+
+use crate::crypto::generate_keypair;
+use crate::discovery::enr::Enr;
+use crate::discovery::message::DiscoveryMessage;
+use crate::discovery::service::{send_enveloped, DISC_PROTO};
+use crate::discovery::table::PeerFilter;
+use crate::fork_id::ForkFilter;
+use crate::protocol::envelope::Envelope;
+use crate::session::handshake::outbound_handshake;
+use crate::session::message::ChainInfo;
+use crate::spec::ChainSpec;
+use crate::transport::quic::endpoint::client_endpoint;
+
+// covers dialing, the handshake and the reply
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("cannot open a local socket: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("cannot reach {0}: {1}")]
+    Connect(SocketAddr, String),
+
+    #[error("{0} refused the session (wrong chain or fork?)")]
+    Handshake(SocketAddr),
+
+    #[error("{0} did not answer in time")]
+    Timeout(SocketAddr),
+}
+
+/// Ask the node at `addr` for its own record and the peers it knows that
+/// match `filter`.
+pub async fn find_nodes(
+    addr: SocketAddr,
+    spec: &ChainSpec,
+    filter: PeerFilter,
+) -> Result<(Enr, Vec<Enr>), ClientError> {
+    let endpoint = client_endpoint(addr)?;
+    let query = async {
+        let conn = endpoint
+            .connect(addr, "localhost")
+            .map_err(|e| ClientError::Connect(addr, e.to_string()))?
+            .await
+            .map_err(|e| ClientError::Connect(addr, e.to_string()))?;
+
+        let mut local = Enr::new_local(&generate_keypair().expect("keygen"), &[], 0);
+        let chain = chain_info(spec);
+        local.fork_id = Some(chain.fork_id);
+        let caps = vec![DISC_PROTO.to_string()];
+        outbound_handshake(&conn, &local.node_id, &caps, &chain, |_| Ok(()))
+            .await
+            .map_err(|_| ClientError::Handshake(addr))?;
+
+        let ask = DiscoveryMessage::FindNodes { from: local, filter };
+        send_enveloped(&conn, DISC_PROTO, &ask.to_bytes())
+            .await
+            .map_err(|_| ClientError::Connect(addr, "stream closed".into()))?;
+        let reply = wait_for_nodes(&conn).await;
+        conn.close(0u32.into(), b"done");
+        reply.ok_or(ClientError::Handshake(addr))
+    };
+    timeout(QUERY_TIMEOUT, query)
+        .await
+        .map_err(|_| ClientError::Timeout(addr))?
+}
+
+/// What a freshly started node on `spec` would claim in its Hello.
+fn chain_info(spec: &ChainSpec) -> ChainInfo {
+    ChainInfo {
+        network_id: spec.network_id,
+        genesis: spec.genesis_hash(),
+        head_height: 0,
+        fork_id: ForkFilter::from_spec(spec).fork_id(0),
+    }
+}
+
+// the node may greet us with gossip or tx announcements first; skip those
+async fn wait_for_nodes(conn: &Connection) -> Option<(Enr, Vec<Enr>)> {
+    loop {
+        let (_s, mut recv) = conn.accept_bi().await.ok()?;
+        let len = recv.read_u32().await.ok()?;
+        let mut buf = vec![0u8; len as usize];
+        recv.read_exact(&mut buf).await.ok()?;
+
+        let Some(env) = Envelope::from_bytes(&buf) else { continue };
+        if env.proto != DISC_PROTO {
+            continue;
+        }
+        if let Some(DiscoveryMessage::Nodes { from, peers }) = DiscoveryMessage::from_bytes(&env.data) {
+            return Some((from, peers));
+        }
+    }
+}
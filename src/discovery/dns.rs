@@ -0,0 +1,466 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::{SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use k256::ecdsa::{Signature, VerifyingKey};
+use thiserror::Error;
+
+use crate::crypto::{keccak256, verify_signature, Keypair};
+use crate::discovery::enr::Enr;
+
+const ROOT_PREFIX: &str = "enrtree-root:v1";
+const BRANCH_PREFIX: &str = "enrtree-branch:";
+const LINK_PREFIX: &str = "enrtree://";
+// children per branch record, so each one fits a single TXT string
+const MAX_BRANCH_CHILDREN: usize = 13;
+// records followed per tree, so a hostile zone can't keep us resolving forever
+const MAX_ENTRIES: usize = 4096;
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Error)]
+pub enum DnsError {
+    #[error("not an enrtree://<key>@<domain> URL: {0:?}")]
+    BadUrl(String),
+
+    #[error("no TXT record at {0}")]
+    NotFound(String),
+
+    #[error("TXT lookup of {0} failed: {1}")]
+    Lookup(String, String),
+
+    #[error("malformed tree entry at {0}")]
+    BadEntry(String),
+
+    #[error("tree root at {0} is not signed by the key in its URL")]
+    BadSignature(String),
+
+    #[error("entry at {0} does not match its hash")]
+    HashMismatch(String),
+
+    #[error("tree at {0} has more than {MAX_ENTRIES} entries")]
+    TooLarge(String),
+}
+
+/// Where TXT records come from: the network, or a fixed zone in tests.
+pub trait Resolver {
+    /// Every TXT record at `name`, each with its strings joined.
+    fn txt(&self, name: &str) -> Result<Vec<String>, DnsError>;
+}
+
+/// Records held in memory, keyed by fully qualified name.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryZone {
+    records: HashMap<String, Vec<String>>,
+}
+
+impl MemoryZone {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: &str, txt: &str) {
+        self.records
+            .entry(name.to_lowercase())
+            .or_default()
+            .push(txt.to_string());
+    }
+
+    /// Publish `tree` under `domain`.
+    pub fn publish(&mut self, domain: &str, tree: &Tree) {
+        for (name, txt) in tree.records(domain) {
+            self.insert(&name, &txt);
+        }
+    }
+}
+
+impl Resolver for MemoryZone {
+    fn txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        self.records
+            .get(&name.to_lowercase())
+            .cloned()
+            .ok_or_else(|| DnsError::NotFound(name.to_string()))
+    }
+}
+
+/// Plain DNS over UDP to the first nameserver in /etc/resolv.conf.
+#[derive(Debug, Clone)]
+pub struct SystemResolver {
+    server: SocketAddr,
+}
+
+impl SystemResolver {
+    pub fn new(server: SocketAddr) -> Self {
+        Self { server }
+    }
+
+    pub fn from_resolv_conf() -> Result<Self, DnsError> {
+        let conf = std::fs::read_to_string("/etc/resolv.conf")
+            .map_err(|e| DnsError::Lookup("/etc/resolv.conf".into(), e.to_string()))?;
+        conf.lines()
+            .filter_map(|l| l.trim().strip_prefix("nameserver"))
+            .find_map(|ip| ip.trim().parse().ok())
+            .map(|ip| Self::new(SocketAddr::new(ip, 53)))
+            .ok_or_else(|| DnsError::Lookup("/etc/resolv.conf".into(), "no nameserver".into()))
+    }
+}
+
+impl Resolver for SystemResolver {
+    fn txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        let fail = |e: &dyn fmt::Display| DnsError::Lookup(name.to_string(), e.to_string());
+        let local: SocketAddr = if self.server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }
+            .parse()
+            .unwrap();
+        let sock = UdpSocket::bind(local).map_err(|e| fail(&e))?;
+        sock.set_read_timeout(Some(LOOKUP_TIMEOUT)).map_err(|e| fail(&e))?;
+
+        let id = rand::random::<u16>();
+        sock.send_to(&txt_query(id, name), self.server).map_err(|e| fail(&e))?;
+        let mut buf = [0u8; 4096];
+        loop {
+            let (n, from) = sock.recv_from(&mut buf).map_err(|e| fail(&e))?;
+            if from != self.server {
+                continue;
+            }
+            return match parse_txt_answer(id, &buf[..n]) {
+                Some(Some(txts)) if !txts.is_empty() => Ok(txts),
+                Some(_) => Err(DnsError::NotFound(name.to_string())),
+                // someone else's answer, or garbage
+                None => continue,
+            };
+        }
+    }
+}
+
+fn txt_query(id: u16, name: &str) -> Vec<u8> {
+    let mut q = Vec::with_capacity(name.len() + 18);
+    q.extend_from_slice(&id.to_be_bytes());
+    q.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]); // recursion desired, one question
+    for label in name.trim_end_matches('.').split('.') {
+        q.push(label.len() as u8);
+        q.extend_from_slice(label.as_bytes());
+    }
+    q.extend_from_slice(&[0, 0, 16, 0, 1]); // root, TXT, IN
+    q
+}
+
+// None if the packet is not an answer to `id`; Some(None) for NXDOMAIN
+fn parse_txt_answer(id: u16, p: &[u8]) -> Option<Option<Vec<String>>> {
+    if p.len() < 12 || u16::from_be_bytes([p[0], p[1]]) != id || p[2] & 0x80 == 0 {
+        return None;
+    }
+    if p[3] & 0x0f == 3 {
+        return Some(None);
+    }
+    let questions = u16::from_be_bytes([p[4], p[5]]);
+    let answers = u16::from_be_bytes([p[6], p[7]]);
+
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(p, at)? + 4;
+    }
+    let mut txts = vec![];
+    for _ in 0..answers {
+        at = skip_name(p, at)?;
+        let rtype = u16::from_be_bytes([*p.get(at)?, *p.get(at + 1)?]);
+        let len = u16::from_be_bytes([*p.get(at + 8)?, *p.get(at + 9)?]) as usize;
+        at += 10;
+        let rdata = p.get(at..at + len)?;
+        at += len;
+        if rtype != 16 {
+            continue;
+        }
+        // a TXT record is a run of length-prefixed strings
+        let mut txt = Vec::new();
+        let mut i = 0;
+        while i < rdata.len() {
+            let n = rdata[i] as usize;
+            txt.extend_from_slice(rdata.get(i + 1..i + 1 + n)?);
+            i += 1 + n;
+        }
+        txts.push(String::from_utf8(txt).ok()?);
+    }
+    Some(Some(txts))
+}
+
+fn skip_name(p: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let len = *p.get(at)?;
+        match len {
+            0 => return Some(at + 1),
+            // compression pointer: two bytes and the name ends
+            l if l & 0xc0 == 0xc0 => return Some(at + 2),
+            l => at += 1 + l as usize,
+        }
+    }
+}
+
+/// `enrtree://<base32 compressed pubkey>@<domain>`: where a tree lives and
+/// who must have signed it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeUrl {
+    pub key: VerifyingKey,
+    pub domain: String,
+}
+
+impl FromStr for TreeUrl {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || DnsError::BadUrl(s.to_string());
+        let (key, domain) = s.strip_prefix(LINK_PREFIX).and_then(|r| r.split_once('@')).ok_or_else(bad)?;
+        let key = base32_decode(key).ok_or_else(bad)?;
+        let key = VerifyingKey::from_sec1_bytes(&key).map_err(|_| bad())?;
+        if domain.is_empty() {
+            return Err(bad());
+        }
+        Ok(Self {
+            key,
+            domain: domain.trim_end_matches('.').to_lowercase(),
+        })
+    }
+}
+
+impl fmt::Display for TreeUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let key = self.key.to_encoded_point(true);
+        write!(f, "{LINK_PREFIX}{}@{}", base32_encode(key.as_bytes()), self.domain)
+    }
+}
+
+/// A signed EIP-1459 tree: a root naming the hash of a subtree of node
+/// records and the hash of a subtree of links to other trees.
+#[derive(Debug, Clone)]
+pub struct Tree {
+    root: String,
+    entries: Vec<String>,
+}
+
+impl Tree {
+    pub fn sign(key: &Keypair, seq: u64, enrs: &[Enr], links: &[TreeUrl]) -> Self {
+        let mut entries = vec![];
+        let enr_root = subtree(enrs.iter().map(Enr::to_text).collect(), &mut entries);
+        let link_root = subtree(links.iter().map(TreeUrl::to_string).collect(), &mut entries);
+
+        let unsigned = format!("{ROOT_PREFIX} e={enr_root} l={link_root} seq={seq}");
+        let (sig, recid) = key
+            .signing_key
+            .sign_prehash_recoverable(&keccak256(unsigned.as_bytes()))
+            .expect("sign tree root");
+        let mut sig = sig.to_bytes().to_vec();
+        sig.push(recid.to_byte());
+        Self {
+            root: format!("{unsigned} sig={}", URL_SAFE_NO_PAD.encode(sig)),
+            entries,
+        }
+    }
+
+    /// The TXT records to publish under `domain`.
+    pub fn records(&self, domain: &str) -> Vec<(String, String)> {
+        let mut out = vec![(domain.to_string(), self.root.clone())];
+        for e in &self.entries {
+            out.push((format!("{}.{domain}", entry_hash(e)), e.clone()));
+        }
+        out
+    }
+}
+
+// Adds `leaves` and the branches over them to `entries`; returns the hash
+// of the topmost branch.
+fn subtree(leaves: Vec<String>, entries: &mut Vec<String>) -> String {
+    let mut hashes: Vec<String> = leaves.iter().map(|l| entry_hash(l)).collect();
+    entries.extend(leaves);
+    loop {
+        let branches: Vec<String> = hashes
+            .chunks(MAX_BRANCH_CHILDREN)
+            .map(|c| format!("{BRANCH_PREFIX}{}", c.join(",")))
+            .collect();
+        hashes = branches.iter().map(|b| entry_hash(b)).collect();
+        entries.extend(branches);
+        if hashes.len() <= 1 {
+            // no leaves at all is an empty branch
+            return hashes.pop().unwrap_or_else(|| {
+                entries.push(BRANCH_PREFIX.to_string());
+                entry_hash(BRANCH_PREFIX)
+            });
+        }
+    }
+}
+
+// base32 of the first 16 bytes of keccak256(entry): the entry's subdomain
+fn entry_hash(entry: &str) -> String {
+    base32_encode(&keccak256(entry.as_bytes())[..16])
+}
+
+/// Resolves trees through a `Resolver`, checking every record on the way.
+pub struct TreeClient<R> {
+    resolver: R,
+}
+
+impl<R: Resolver> TreeClient<R> {
+    pub fn new(resolver: R) -> Self {
+        Self { resolver }
+    }
+
+    /// All node records in the tree at `url` and the trees it links to.
+    pub fn resolve(&self, url: &TreeUrl) -> Result<Vec<Enr>, DnsError> {
+        let mut enrs = vec![];
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([url.clone()]);
+
+        while let Some(url) = queue.pop_front() {
+            if !seen.insert(url.domain.clone()) {
+                continue;
+            }
+            let (enr_root, link_root) = self.root(&url)?;
+            let mut budget = MAX_ENTRIES;
+            for leaf in self.leaves(&url.domain, &enr_root, &mut budget)? {
+                let enr = leaf
+                    .parse()
+                    .map_err(|_| DnsError::BadEntry(format!("{enr_root}.{}", url.domain)))?;
+                enrs.push(enr);
+            }
+            for leaf in self.leaves(&url.domain, &link_root, &mut budget)? {
+                queue.push_back(leaf.parse()?);
+            }
+        }
+        Ok(enrs)
+    }
+
+    fn root(&self, url: &TreeUrl) -> Result<(String, String), DnsError> {
+        let bad = || DnsError::BadEntry(url.domain.clone());
+        let txts = self.resolver.txt(&url.domain)?;
+        let root = txts
+            .iter()
+            .find(|t| t.starts_with(ROOT_PREFIX))
+            .ok_or_else(|| DnsError::NotFound(url.domain.clone()))?;
+        let (unsigned, sig) = root.rsplit_once(" sig=").ok_or_else(bad)?;
+
+        let mut fields = HashMap::new();
+        for kv in unsigned[ROOT_PREFIX.len()..].split_whitespace() {
+            let (k, v) = kv.split_once('=').ok_or_else(bad)?;
+            fields.insert(k, v.to_string());
+        }
+        let (Some(e), Some(l)) = (fields.remove("e"), fields.remove("l")) else {
+            return Err(bad());
+        };
+
+        // 65 bytes r || s || v; the key is known, so v is not needed
+        let sig = URL_SAFE_NO_PAD.decode(sig).map_err(|_| bad())?;
+        let sig = sig.get(..64).and_then(|s| Signature::from_slice(s).ok());
+        let signed = sig.is_some_and(|s| verify_signature(&url.key, &keccak256(unsigned.as_bytes()), &s));
+        if !signed {
+            return Err(DnsError::BadSignature(url.domain.clone()));
+        }
+        Ok((e, l))
+    }
+
+    // Walk down from the entry at `hash`; leaves come back in tree order.
+    fn leaves(&self, domain: &str, hash: &str, budget: &mut usize) -> Result<Vec<String>, DnsError> {
+        let mut out = vec![];
+        let mut stack = vec![hash.to_string()];
+        while let Some(hash) = stack.pop() {
+            *budget = budget.checked_sub(1).ok_or_else(|| DnsError::TooLarge(domain.to_string()))?;
+            let name = format!("{hash}.{domain}");
+            let entry = self
+                .resolver
+                .txt(&name)?
+                .into_iter()
+                .find(|t| entry_hash(t).eq_ignore_ascii_case(&hash))
+                .ok_or_else(|| DnsError::HashMismatch(name.clone()))?;
+
+            if let Some(children) = entry.strip_prefix(BRANCH_PREFIX) {
+                let children = children.split(',').filter(|c| !c.is_empty());
+                stack.extend(children.rev().map(String::from));
+            } else {
+                out.push(entry);
+            }
+        }
+        Ok(out)
+    }
+}
+
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// RFC 4648 base32 without padding, as EIP-1459 uses it
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let (mut acc, mut bits) = (0u32, 0);
+    for &b in data {
+        acc = (acc << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(acc >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[(acc << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in s.trim_end_matches('=').bytes() {
+        let v = BASE32.iter().position(|&b| b == c.to_ascii_uppercase())? as u32;
+        acc = (acc << 5) | v;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_keypair;
+    use crate::discovery::enr::TEXT_PREFIX;
+
+    fn url(key: &Keypair, domain: &str) -> TreeUrl {
+        TreeUrl {
+            key: key.verifying_key,
+            domain: domain.into(),
+        }
+    }
+
+    #[test]
+    fn resolves_signed_trees_and_their_links() {
+        let (key, other_key) = (generate_keypair().unwrap(), generate_keypair().unwrap());
+        let ip = ["127.0.0.1".parse().unwrap()];
+        let nodes: Vec<Enr> = (0..30).map(|i| Enr::new_local(&generate_keypair().unwrap(), &ip, 9000 + i)).collect();
+
+        // enough records to need two levels of branches, plus a linked tree
+        let mut zone = MemoryZone::new();
+        let links = [url(&other_key, "more.example.org")];
+        zone.publish("nodes.example.org", &Tree::sign(&key, 3, &nodes[..28], &links));
+        zone.publish("more.example.org", &Tree::sign(&other_key, 1, &nodes[28..], &[]));
+
+        let home = url(&key, "nodes.example.org");
+        assert_eq!(home.to_string().parse::<TreeUrl>().unwrap(), home);
+        let client = TreeClient::new(zone.clone());
+        assert_eq!(client.resolve(&home).unwrap(), nodes);
+
+        // signed by someone else than the URL says
+        let forged = url(&other_key, "nodes.example.org");
+        assert!(matches!(client.resolve(&forged), Err(DnsError::BadSignature(_))));
+
+        // an entry swapped for a different record
+        let mut zone = MemoryZone::new();
+        let tree = Tree::sign(&key, 4, &nodes[..2], &[]);
+        let (name, _) = tree.records("x.org").into_iter().find(|(_, t)| t.starts_with(TEXT_PREFIX)).unwrap();
+        for (n, t) in tree.records("x.org") {
+            zone.insert(&n, if n == name { "enr-lite:e30" } else { &t });
+        }
+        let client = TreeClient::new(zone);
+        assert!(matches!(client.resolve(&url(&key, "x.org")), Err(DnsError::HashMismatch(_))));
+        assert!(matches!(client.resolve(&url(&key, "y.org")), Err(DnsError::NotFound(_))));
+    }
+}
@@ -1,22 +1,321 @@
-use rand::RngCore;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use k256::ecdsa::VerifyingKey;
+use rlp::RlpStream;
 use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use crate::config::Families;
+use crate::crypto::{node_id_from_pubkey, Keypair, NodeId};
+use crate::enr::{EnrBuilder, EnrRecord};
+use crate::fork_id::ForkId;
+
+/// Starts our JSON text form, so it can't be taken for an EIP-778 `enr:`.
+pub const TEXT_PREFIX: &str = "enr-lite:";
+
+#[derive(Debug, Error)]
+pub enum EnrError {
+    #[error("node record must start with \"enr:\" or \"{TEXT_PREFIX}\"")]
+    MissingPrefix,
+
+    #[error("node record is not base64url: {0}")]
+    Base64(#[from] base64::DecodeError),
+
+    #[error("node record does not decode: {0}")]
+    Decode(#[from] serde_json::Error),
+
+    #[error("node record has no address we can reach")]
+    Unreachable,
+
+    #[error(transparent)]
+    Record(#[from] crate::enr::error::EnrError),
+}
 
 #[derive(Debug, Clone, Serialize,Deserialize, PartialEq, Eq, Hash)]
 pub struct Enr {
     pub node_id: String,
-    pub ip: String,
-    pub port: u16,
+    /// bumped on every change so peers can tell which copy is newer
+    #[serde(default)]
+    pub seq: u64,
+    #[serde(default)]
+    pub ip: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub ip6: Option<Ipv6Addr>,
+    pub port: u16, // QUIC, the same for both families
+    /// discv5 port, if the node speaks discovery over UDP too
+    #[serde(default)]
+    pub udp: Option<u16>,
+    /// hex compressed secp256k1 key the node id derives from
+    #[serde(default)]
+    pub pubkey: Option<String>,
+    /// "eth" entry: which fork of which chain the node follows
+    #[serde(default)]
+    pub fork_id: Option<ForkId>,
+    /// protocols the node serves, e.g. "mini-sync/0.1"
+    #[serde(default)]
+    pub caps: Vec<String>,
+    /// application topics the node takes part in, e.g. "shard/3"
+    #[serde(default)]
+    pub topics: Vec<String>,
+    /// the signed EIP-778 record this was read from; only these get relayed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed: Option<Vec<u8>>,
 }
 
 impl Enr {
-    pub fn new_local(port: u16) -> Self {
-        let mut bytes = [0u8; 32];
-        rand::thread_rng().fill_bytes(&mut bytes);
-
+    /// Our record under `key`, advertising the first IPv4 and first IPv6
+    /// address of `ips`.
+    pub fn new_local(key: &Keypair, ips: &[IpAddr], port: u16) -> Self {
         Self {
-            node_id: hex::encode(bytes),
-            ip: "127.0.0.1".to_string(),
+            node_id: hex::encode(node_id_from_pubkey(&key.verifying_key)),
+            seq: 1,
+            ip: ips.iter().find_map(|ip| match ip {
+                IpAddr::V4(v4) => Some(*v4),
+                IpAddr::V6(_) => None,
+            }),
+            ip6: ips.iter().find_map(|ip| match ip {
+                IpAddr::V6(v6) => Some(*v6),
+                IpAddr::V4(_) => None,
+            }),
+            port,
+            udp: None,
+            pubkey: Some(hex::encode(key.verifying_key.to_sec1_bytes())),
+            fork_id: None,
+            caps: vec![],
+            topics: vec![],
+            signed: None,
+        }
+    }
+
+    /// The node's key, if the record has one its node id matches.
+    pub fn public_key(&self) -> Option<VerifyingKey> {
+        let key = VerifyingKey::from_sec1_bytes(&hex::decode(self.pubkey.as_ref()?).ok()?).ok()?;
+        (hex::encode(node_id_from_pubkey(&key)) == self.node_id).then_some(key)
+    }
+
+    pub fn id_bytes(&self) -> Option<NodeId> {
+        hex::decode(&self.node_id).ok()?.try_into().ok()
+    }
+
+    pub fn serves(&self, cap: &str) -> bool {
+        self.caps.iter().any(|c| c == cap)
+    }
+
+    pub fn has_topic(&self, topic: &str) -> bool {
+        self.topics.iter().any(|t| t == topic)
+    }
+
+    /// Every advertised address, IPv4 first.
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        let v4 = self.ip.map(|ip| SocketAddr::from((ip, self.port)));
+        let v6 = self.ip6.map(|ip| SocketAddr::from((ip, self.port)));
+        v4.into_iter().chain(v6).collect()
+    }
+
+    /// The advertised address a socket serving `families` can dial.
+    pub fn dial_addr(&self, families: Families) -> Result<SocketAddr, EnrError> {
+        self.addrs()
+            .into_iter()
+            .find(|a| families.reaches(a))
+            .ok_or(EnrError::Unreachable)
+    }
+
+    /// Advertise `addr` for its family; returns whether anything changed.
+    pub fn set_addr(&mut self, addr: SocketAddr) -> bool {
+        let changed = match addr {
+            SocketAddr::V4(a) => self.ip.replace(*a.ip()) != Some(*a.ip()),
+            SocketAddr::V6(a) => self.ip6.replace(*a.ip()) != Some(*a.ip()),
+        } || self.port != addr.port();
+        if changed {
+            self.port = addr.port();
+            self.seq += 1;
+        }
+        changed
+    }
+
+    /// Take in a signed EIP-778 record, e.g. one from geth. A node without
+    /// a "quic" port is dialed on its "tcp" one.
+    pub fn from_record(record: &EnrRecord) -> Result<Self, EnrError> {
+        let key = record.public_key().ok_or(EnrError::Unreachable)?;
+        let port = record.port("quic").or_else(|| record.port("tcp")).ok_or(EnrError::Unreachable)?;
+        // "eth" is [[fork hash, fork next]]
+        let fork_id = record.get("eth").and_then(|eth| {
+            let id = eth.at(0).ok()?;
+            let hash: Vec<u8> = id.val_at(0).ok()?;
+            Some(ForkId {
+                hash: u32::from_be_bytes(hash.try_into().ok()?),
+                next: id.val_at(1).ok()?,
+            })
+        });
+        let strings = |key| record.get(key).and_then(|r| r.as_list().ok()).unwrap_or_default();
+        Ok(Self {
+            node_id: hex::encode(node_id_from_pubkey(&key)),
+            seq: record.seq,
+            ip: record.ip(),
+            ip6: record.ip6(),
             port,
+            udp: record.port("udp"),
+            pubkey: Some(hex::encode(key.to_sec1_bytes())),
+            fork_id,
+            caps: strings("caps"),
+            topics: strings("topics"),
+            signed: Some(record.encode()),
+        })
+    }
+
+    /// Our record in EIP-778 form, signed with the node key.
+    pub fn to_record(&self, key: &Keypair) -> EnrRecord {
+        let mut b = EnrBuilder::new().seq(self.seq).add_raw(b"quic", &rlp::encode(&self.port));
+        if let Some(ip) = self.ip {
+            b = b.add(b"ip", &ip.octets());
+        }
+        if let Some(ip6) = self.ip6 {
+            b = b.add(b"ip6", &ip6.octets());
+        }
+        if let Some(udp) = self.udp {
+            b = b.add_raw(b"udp", &rlp::encode(&udp));
+        }
+        if let Some(f) = self.fork_id {
+            let mut eth = RlpStream::new_list(1);
+            eth.begin_list(2).append(&f.hash.to_be_bytes().as_ref()).append(&f.next);
+            b = b.add_raw(b"eth", &eth.out());
         }
+        if !self.caps.is_empty() {
+            b = b.add_raw(b"caps", &rlp::encode_list::<String, String>(&self.caps));
+        }
+        if !self.topics.is_empty() {
+            b = b.add_raw(b"topics", &rlp::encode_list::<String, String>(&self.topics));
+        }
+        b.build(key)
+    }
+
+    /// Text form for command lines and config files: `enr-lite:` followed
+    /// by the unpadded base64url of the JSON record.
+    pub fn to_text(&self) -> String {
+        let json = serde_json::to_vec(self).expect("serialize enr");
+        format!("{TEXT_PREFIX}{}", URL_SAFE_NO_PAD.encode(json))
+    }
+
+    /// Whether `signed` is this very record, signed by the key its node id
+    /// comes from. Nothing else proves a record is the node's own.
+    pub fn authentic(&self) -> bool {
+        self.signed.as_deref().and_then(verified).as_ref() == Some(self)
+    }
+
+    /// Whether the record may take a table slot: it is authentic, and its
+    /// chain passes `compatible` or it names no chain (e.g. geth without
+    /// "eth"). Those are kept for discovery only.
+    pub fn admissible(&self, compatible: impl Fn(&ForkId) -> bool) -> bool {
+        self.authentic() && self.fork_id.as_ref().is_none_or(compatible)
+    }
+
+    /// A table entry we can look up nodes through but not sync with.
+    pub fn discovery_only(&self) -> bool {
+        self.fork_id.is_none()
+    }
+}
+
+// a signed record, as long as it verifies
+fn verified(raw: &[u8]) -> Option<Enr> {
+    Enr::from_record(&EnrRecord::decode(raw).ok()?).ok()
+}
+
+impl FromStr for Enr {
+    type Err = EnrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(body) = s.strip_prefix(TEXT_PREFIX) {
+            return Ok(serde_json::from_slice(&URL_SAFE_NO_PAD.decode(body)?)?);
+        }
+        let body = s.strip_prefix("enr:").ok_or(EnrError::MissingPrefix)?;
+        Self::from_record(&EnrRecord::decode(&URL_SAFE_NO_PAD.decode(body)?)?)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_keypair;
+
+    #[test]
+    fn text_form_round_trips() {
+        let mut enr = Enr::new_local(&generate_keypair().unwrap(), &["127.0.0.1".parse().unwrap()], 9001);
+        enr.fork_id = Some(ForkId { hash: 0xdeadbeef, next: 0 });
+
+        let text = enr.to_text();
+        assert!(text.starts_with(TEXT_PREFIX) && !text.contains('='));
+        assert_eq!(text.parse::<Enr>().unwrap(), enr);
+
+        assert!(matches!("9001".parse::<Enr>(), Err(EnrError::MissingPrefix)));
+        assert!(matches!("enr:!!".parse::<Enr>(), Err(EnrError::Base64(_))));
+        // JSON under the EIP-778 prefix is not a record
+        let json = text.replacen(TEXT_PREFIX, "enr:", 1);
+        assert!(matches!(json.parse::<Enr>(), Err(EnrError::Record(_))));
+    }
+
+    #[test]
+    fn takes_in_eip778_records() {
+        let key = generate_keypair().unwrap();
+        let mut eth = RlpStream::new_list(1);
+        eth.begin_list(2).append(&0xdeadbeefu32.to_be_bytes().as_ref()).append(&0u64);
+        // what a geth node puts in its record
+        let geth = EnrBuilder::new()
+            .seq(7)
+            .add_raw(b"eth", &eth.out())
+            .add(b"ip", &[10, 0, 0, 7])
+            .add_raw(b"tcp", &rlp::encode(&30303u16))
+            .add_raw(b"udp", &rlp::encode(&30301u16))
+            .build(&key);
+        let enr: Enr = geth.to_string().parse().unwrap();
+        assert_eq!(enr.node_id, hex::encode(node_id_from_pubkey(&key.verifying_key)));
+        assert_eq!((enr.seq, enr.udp), (7, Some(30301)));
+        assert_eq!(enr.dial_addr(Families::ANY).unwrap(), "10.0.0.7:30303".parse().unwrap());
+        assert_eq!(enr.fork_id, Some(ForkId { hash: 0xdeadbeef, next: 0 }));
+        assert_eq!(enr.public_key(), Some(key.verifying_key));
+
+        let mut ours = Enr::new_local(&key, &["127.0.0.1".parse().unwrap()], 9001);
+        ours.udp = Some(9011);
+        ours.fork_id = enr.fork_id;
+        ours.caps = vec!["mini-sync/0.1".into()];
+        let record = ours.to_record(&key);
+        assert_eq!(
+            record.to_string().parse::<Enr>().unwrap(),
+            Enr { signed: Some(record.encode()), ..ours }
+        );
+
+        // the EIP-778 example names no port we could dial
+        let example = "enr:-IS4QHCYrYZbAKWCBRlAy5zzaDZXJBGkcnh4MHcBFZntXNFrdvJjX04jRzjzCBOonrkTfj499SZuOh8R33Ls8RRcy5wBgmlkgnY0gmlwhH8AAAGJc2VjcDI1NmsxoQPKY0yuDUmstAHYpMa2_oxVtw0RW_QAdpzBQA8yWM0xOIN1ZHCCdl8";
+        assert!(matches!(example.parse::<Enr>(), Err(EnrError::Unreachable)));
+    }
+
+    #[test]
+    fn dials_the_family_the_socket_can_reach() {
+        let key = generate_keypair().unwrap();
+        let ips: Vec<IpAddr> = vec!["::1".parse().unwrap(), "10.0.0.7".parse().unwrap()];
+        let both = Enr::new_local(&key, &ips, 30303);
+        assert_eq!(both.ip, Some(Ipv4Addr::new(10, 0, 0, 7)));
+        assert_eq!(both.ip6, Some(Ipv6Addr::LOCALHOST));
+
+        let v4 = Families { v4: true, v6: false };
+        let v6 = Families { v4: false, v6: true };
+        assert_eq!(both.dial_addr(Families::ANY).unwrap(), "10.0.0.7:30303".parse().unwrap());
+        assert_eq!(both.dial_addr(v6).unwrap(), "[::1]:30303".parse().unwrap());
+
+        let only6 = Enr::new_local(&key, &ips[..1], 30303);
+        assert!(matches!(only6.dial_addr(v4), Err(EnrError::Unreachable)));
+
+        let mut moved = only6.clone();
+        assert!(!moved.set_addr("[::1]:30303".parse().unwrap()));
+        assert!(moved.set_addr("203.0.113.5:30303".parse().unwrap()));
+        assert_eq!((moved.seq, moved.dial_addr(v4).unwrap()), (2, "203.0.113.5:30303".parse().unwrap()));
+
+        // records from before ip6 existed still decode
+        let old = r#"{"node_id":"ab","ip":"127.0.0.1","port":9001}"#;
+        let old: Enr = serde_json::from_str(old).unwrap();
+        assert_eq!(old.dial_addr(v4).unwrap(), "127.0.0.1:9001".parse().unwrap());
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+// agreeing peers needed before we believe an address
+const MIN_VOTES: usize = 3;
+// a peer's observation only counts for this long
+const VOTE_TTL: Duration = Duration::from_secs(300);
+// how long a ping waits for the pong that may carry a vote
+const PING_TTL: Duration = Duration::from_secs(30);
+
+/// Tallies the addresses peers report seeing us at (the `observed` field of
+/// their Pongs) to learn our public address from behind a NAT.
+///
+/// Voters are the IPs our connections come from, not the ids they claim,
+/// and only a Pong answering a Ping we sent to that IP counts. Each IP has
+/// one vote per address family, the last one it cast. An address wins once
+/// it holds a strict majority of the live votes of its family and at least
+/// `MIN_VOTES` of them, so a single lying host can't move us.
+#[derive(Debug, Default)]
+pub struct AddrVotes {
+    votes: HashMap<(IpAddr, bool), (SocketAddr, Instant)>, // (voter, is_ipv4) -> vote
+    pings: HashMap<IpAddr, Instant>,                       // unanswered pings
+}
+
+impl AddrVotes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// We sent a Ping to `peer`; its Pong may vote.
+    pub fn pinged(&mut self, peer: IpAddr, now: Instant) {
+        self.pings.retain(|_, at| now.duration_since(*at) < PING_TTL);
+        self.pings.insert(peer, now);
+    }
+
+    /// Record that `voter` saw us at `observed`; returns the address its
+    /// family now agrees on, if any. Ignored unless it answers our ping.
+    pub fn vote(&mut self, voter: IpAddr, observed: SocketAddr, now: Instant) -> Option<SocketAddr> {
+        let asked = self.pings.remove(&voter)?;
+        if now.duration_since(asked) >= PING_TTL {
+            return None;
+        }
+        self.votes.retain(|_, (_, at)| now.duration_since(*at) < VOTE_TTL);
+        let family = observed.is_ipv4();
+        self.votes.insert((voter, family), (observed, now));
+
+        let mut tally: HashMap<SocketAddr, usize> = HashMap::new();
+        let mut total = 0;
+        for ((_, f), (addr, _)) in &self.votes {
+            if *f == family {
+                *tally.entry(*addr).or_default() += 1;
+                total += 1;
+            }
+        }
+        let (winner, count) = tally.into_iter().max_by_key(|(_, n)| *n)?;
+        (count >= MIN_VOTES && count * 2 > total).then_some(winner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    // a Pong from `voter` answering our Ping
+    fn answer(votes: &mut AddrVotes, voter: IpAddr, observed: SocketAddr, now: Instant) -> Option<SocketAddr> {
+        votes.pinged(voter, now);
+        votes.vote(voter, observed, now)
+    }
+
+    #[test]
+    fn majority_of_recent_voters_decides() {
+        let now = Instant::now();
+        let public: SocketAddr = "203.0.113.5:30303".parse().unwrap();
+        let other: SocketAddr = "198.51.100.9:40000".parse().unwrap();
+        let mut votes = AddrVotes::new();
+
+        assert_eq!(answer(&mut votes, ip(1), public, now), None);
+        // the same peer repeating itself is still one vote
+        assert_eq!(answer(&mut votes, ip(1), public, now), None);
+        assert_eq!(answer(&mut votes, ip(2), public, now), None);
+        assert_eq!(answer(&mut votes, ip(3), other, now), None);
+        assert_eq!(answer(&mut votes, ip(4), public, now), Some(public));
+
+        // IPv6 observations are tallied on their own
+        let v6: SocketAddr = "[2001:db8::1]:30303".parse().unwrap();
+        assert_eq!(answer(&mut votes, ip(5), v6, now), None);
+
+        // three against three is no majority; once the old votes expire it is
+        let later = now + VOTE_TTL / 2;
+        assert_eq!(answer(&mut votes, ip(6), other, later), Some(public));
+        assert_eq!(answer(&mut votes, ip(7), other, later), None);
+        assert_eq!(answer(&mut votes, ip(8), other, now + VOTE_TTL), Some(other));
+    }
+
+    #[test]
+    fn one_host_cannot_move_us() {
+        let now = Instant::now();
+        let public: SocketAddr = "203.0.113.5:30303".parse().unwrap();
+        let fake: SocketAddr = "198.51.100.9:40000".parse().unwrap();
+        let mut votes = AddrVotes::new();
+        for voter in 1..=3 {
+            answer(&mut votes, ip(voter), public, now);
+        }
+
+        // however many node ids it runs, one IP is one voter
+        for _ in 0..10 {
+            assert_eq!(answer(&mut votes, ip(9), fake, now), Some(public));
+        }
+        // and Pongs nobody asked for are not counted at all
+        for voter in 10..20 {
+            assert_eq!(votes.vote(ip(voter), fake, now), None);
+        }
+        assert_eq!(answer(&mut votes, ip(4), public, now), Some(public));
+    }
+}
@@ -1,14 +1,29 @@
 use crate::discovery::enr::Enr;
+use crate::discovery::table::PeerFilter;
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum DiscoveryMessage {
     Ping { from: Enr },
-    Pong { from: Enr },
+    Pong {
+        from: Enr,
+        // where the pinging peer's packets came from, as seen by the ponger
+        #[serde(default)]
+        observed: Option<SocketAddr>,
+    },
 
-    FindNodes { from: Enr },
+    FindNodes {
+        from: Enr,
+        // only records matching this; topic queries also return topic ads
+        #[serde(default)]
+        filter: PeerFilter,
+    },
     Nodes { from: Enr, peers: Vec<Enr> },
+
+    // "list me as a member of `topic`", renewed before the ad expires
+    RegisterTopic { from: Enr, topic: String },
 }
 
 impl DiscoveryMessage {
@@ -1,5 +1,11 @@
 pub mod enr;
+pub mod external;
 pub mod message;
+pub mod nodedb;
 pub mod table;
+pub mod topics;
+pub mod v5;
 pub mod service;
+pub mod client;
+pub mod dns;
   
\ No newline at end of file
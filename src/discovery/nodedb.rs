@@ -0,0 +1,227 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::discovery::enr::Enr;
+
+// failed dials in a row before a node is forgotten
+const MAX_FAILURES: u32 = 5;
+// records kept; past this the least proven ones make room
+const MAX_NODES: usize = 4096;
+
+#[derive(Debug, Error)]
+pub enum NodeDbError {
+    #[error("node database I/O: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("node database is corrupt: {0}")]
+    Corrupt(#[from] serde_json::Error),
+}
+
+/// What we remember about another node. Times are unix seconds, 0 for never.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeRecord {
+    pub enr: Enr,
+    // last time the node itself talked to us
+    pub last_seen: u64,
+    // last time it answered one of our pings
+    pub last_pong: u64,
+    // dials that failed since the last pong
+    pub failures: u32,
+}
+
+impl NodeRecord {
+    // only nodes that answered a ping are worth keeping across restarts
+    fn proven(&self) -> bool {
+        self.last_pong > 0
+    }
+
+    // seeds sort by this; the largest is the first to go
+    fn rank(&self) -> (u32, Reverse<u64>, Reverse<u64>) {
+        (self.failures, Reverse(self.last_pong), Reverse(self.last_seen))
+    }
+}
+
+/// Nodes we have heard of, at most `MAX_NODES` of them. The ones that
+/// answered a ping are kept in `<dir>/nodes.json` so a restarted node can
+/// reconnect without going back to its bootnodes.
+#[derive(Debug, Default)]
+pub struct NodeDb {
+    path: Option<PathBuf>,
+    nodes: HashMap<String, NodeRecord>, // node_id -> record
+    dirty: bool,
+}
+
+impl NodeDb {
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn open(dir: &Path) -> Result<Self, NodeDbError> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join("nodes.json");
+        let nodes = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            nodes,
+            dirty: false,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn get(&self, node_id: &str) -> Option<&NodeRecord> {
+        self.nodes.get(node_id)
+    }
+
+    /// Remember a record we were told about; a newer one replaces ours.
+    pub fn learn(&mut self, enr: Enr) {
+        self.update(enr, |_| {});
+    }
+
+    /// The node sent us something itself.
+    pub fn seen(&mut self, enr: Enr, now: u64) {
+        self.update(enr, |rec| rec.last_seen = now);
+    }
+
+    /// The node answered our ping, so it is alive and reachable.
+    pub fn pong(&mut self, enr: Enr, now: u64) {
+        self.update(enr, |rec| {
+            rec.last_seen = now;
+            rec.last_pong = now;
+            rec.failures = 0;
+        });
+    }
+
+    /// Count a failed dial; returns true once the node is given up on.
+    pub fn failed(&mut self, node_id: &str) -> bool {
+        let Some(rec) = self.nodes.get_mut(node_id) else { return false };
+        self.dirty |= rec.proven();
+        rec.failures += 1;
+        if rec.failures < MAX_FAILURES {
+            return false;
+        }
+        self.nodes.remove(node_id);
+        true
+    }
+
+    fn update(&mut self, enr: Enr, change: impl FnOnce(&mut NodeRecord)) {
+        let id = enr.node_id.clone();
+        let rec = self.nodes.entry(id.clone()).or_insert_with(|| NodeRecord {
+            enr: enr.clone(),
+            last_seen: 0,
+            last_pong: 0,
+            failures: 0,
+        });
+        if enr.seq > rec.enr.seq {
+            rec.enr = enr;
+        }
+        change(rec);
+        self.dirty |= rec.proven();
+
+        // never-contacted and failing nodes go first, maybe the new one
+        if self.nodes.len() > MAX_NODES
+            && let Some(worst) = self.nodes.values().max_by_key(|r| r.rank()).map(|r| r.enr.node_id.clone())
+            && let Some(gone) = self.nodes.remove(&worst)
+        {
+            self.dirty |= gone.proven();
+        }
+    }
+
+    /// Up to `limit` records to seed the routing table with: nodes that
+    /// answered lately first, never-seen ones last.
+    pub fn seeds(&self, limit: usize) -> Vec<Enr> {
+        let mut recs: Vec<&NodeRecord> = self.nodes.values().collect();
+        recs.sort_by_key(|r| r.rank());
+        recs.into_iter().take(limit).map(|r| r.enr.clone()).collect()
+    }
+
+    /// Write pending changes, replacing the file atomically.
+    pub fn flush(&mut self) -> Result<(), NodeDbError> {
+        let Some(path) = self.path.as_ref().filter(|_| self.dirty) else {
+            return Ok(());
+        };
+        let proven: HashMap<&String, &NodeRecord> = self.nodes.iter().filter(|(_, r)| r.proven()).collect();
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(&proven)?)?;
+        fs::rename(&tmp, path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_keypair;
+
+    fn enr(port: u16) -> Enr {
+        Enr::new_local(&generate_keypair().unwrap(), &["127.0.0.1".parse().unwrap()], port)
+    }
+
+    #[test]
+    fn seeds_live_nodes_first_and_persists() {
+        let dir = std::env::temp_dir().join(format!("ethnetlite-nodedb-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (a, b, c) = (enr(1), enr(2), enr(3));
+
+        let mut db = NodeDb::open(&dir).unwrap();
+        db.learn(c.clone());
+        db.seen(b.clone(), 100);
+        db.pong(a.clone(), 50);
+        assert_eq!(db.seeds(10), vec![a.clone(), b.clone(), c.clone()]);
+
+        // a failure pushes a node back; enough of them drop it
+        assert!(!db.failed(&a.node_id));
+        assert_eq!(db.seeds(2), vec![b.clone(), c.clone()]);
+        for _ in 1..MAX_FAILURES - 1 {
+            assert!(!db.failed(&a.node_id));
+        }
+        assert!(db.failed(&a.node_id));
+        assert!(db.get(&a.node_id).is_none());
+
+        let mut moved = b.clone();
+        moved.set_addr("203.0.113.5:2".parse().unwrap());
+        db.learn(moved.clone());
+        db.pong(b.clone(), 120);
+        db.flush().unwrap();
+
+        // c never answered a ping, so only b made it to disk
+        let db = NodeDb::open(&dir).unwrap();
+        assert_eq!(db.len(), 1);
+        assert_eq!(db.get(&b.node_id).unwrap().enr, moved);
+        assert_eq!(db.get(&b.node_id).unwrap().last_seen, 120);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn full_db_drops_unproven_nodes_first() {
+        let mut db = NodeDb::in_memory();
+        let alive = enr(1);
+        db.pong(alive.clone(), 10);
+        for port in 2..MAX_NODES as u16 + 10 {
+            db.learn(enr(port));
+        }
+        assert_eq!(db.len(), MAX_NODES);
+        assert!(db.get(&alive.node_id).is_some());
+
+        let fresh = enr(1);
+        db.seen(fresh.clone(), 20);
+        assert_eq!(db.len(), MAX_NODES);
+        assert!(db.get(&fresh.node_id).is_some());
+    }
+}
@@ -1,212 +1,1055 @@
+use crate::config::{Families, NodeConfig};
 use crate::discovery::enr::Enr;
+use crate::discovery::external::AddrVotes;
 use crate::discovery::message::DiscoveryMessage;
-use crate::discovery::table::PeerTable;
+use crate::discovery::dns::{SystemResolver, TreeClient, TreeUrl};
+use crate::discovery::nodedb::{NodeDb, NodeDbError};
+use crate::discovery::table::{PeerFilter, PeerTable};
+use crate::discovery::topics::{TopicAds, AD_LIFETIME};
+use crate::discovery::v5::message::Message;
+use crate::discovery::v5::transport::{nodes_response, Discv5, Handler};
+use crate::discovery::v5::{signed_record, Contact};
 
-use crate::protocol::envelope::Envelope;
-use crate::protocol::mini_sync::manager::ChainManager;
+use crate::protocol::envelope::{Envelope, MAX_FRAME_SIZE};
+use crate::protocol::gossip::message::{GossipMessage, MessageId};
+use crate::protocol::gossip::router::{GossipRouter, Outbox, Subscription, HEARTBEAT_INTERVAL};
+use crate::protocol::mini_sync::event::ChainEvent;
+use crate::protocol::mini_sync::manager::{ChainManager, SyncAction};
 use crate::protocol::mini_sync::message::{MiniSyncMessage, Status};
 use crate::protocol::mini_sync::producer::start_header_producer;
+use crate::protocol::tx::gossip::{TxAction, TxGossip};
+use crate::protocol::tx::message::TxMessage;
+use crate::protocol::tx::pool::{PoolConfig, PoolError, TxPool};
+use crate::protocol::tx::transaction::Transaction;
+use crate::protocol::mini_sync::store::{ChainStore, FileStore, MemoryStore, StoreError};
+use crate::protocol::mini_sync::sync::SyncState;
+use crate::protocol::mini_sync::validation::unix_now;
 
+use crate::crypto::{Keypair, NodeId};
+use crate::fork_id::IncompatiblePeer;
 use crate::session::handshake::{inbound_handshake, outbound_handshake};
+use crate::session::message::{ChainInfo, SessionMessage, Throttled};
+use crate::session::ratelimit::{Limit, RateLimiter};
+use crate::session::score::PeerScores;
+use crate::spec::ChainSpec;
+use crate::transport::quic::endpoint::{peer_addr, peer_host};
 
 use quinn::{Connection, Endpoint};
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use thiserror::Error;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, Semaphore};
 use tokio::time::{sleep, Duration};
 
-const DISC_PROTO: &str = "discv-lite/0.1";
+pub(crate) const DISC_PROTO: &str = "discv-lite/0.1";
 const SYNC_PROTO: &str = "mini-sync/0.1";
+const GOSSIP_PROTO: &str = "gossip/0.1";
+const TX_PROTO: &str = "tx-gossip/0.1";
+// session-level notices after the handshake, e.g. throttling
+const SESSION_PROTO: &str = "session/0.1";
+
+// remote address -> most recent live connection, for requests not sent as a reply
+type Connections = Arc<Mutex<HashMap<String, Connection>>>;
+
+const BUCKET_SIZE: usize = 16;
+// records from the node database put in the table on start
+const MAX_SEEDS: usize = 32;
+// how often the stalest entry of each bucket gets pinged
+const REVALIDATE_INTERVAL: Duration = Duration::from_secs(10);
+// a dead node must not stall a round until QUIC gives up on it
+const DIAL_TIMEOUT: Duration = Duration::from_secs(5);
+// connected peers we place topic ads with each round
+const MAX_REGISTRARS: usize = 8;
+// inbound streams being handled at once, over all connections
+const MAX_INBOUND_STREAMS: usize = 256;
+// and for any one connection, so a single peer cannot hold them all
+const MAX_STREAMS_PER_CONN: usize = 16;
+// how long a peer gets to deliver a frame once it opened the stream
+const FRAME_READ_TIMEOUT: Duration = Duration::from_secs(10);
+// per request over a rate limit
+const THROTTLE_PENALTY: i32 = -5;
+// how often discv5 asks a few table peers for the nodes they know
+const LOOKUP_INTERVAL: Duration = Duration::from_secs(30);
+const LOOKUP_PEERS: usize = 3;
+// the far buckets hold most of the id space
+const LOOKUP_DISTANCES: [u64; 3] = [256, 255, 254];
+
+// protocols that serve applications rather than the node itself
+#[derive(Clone)]
+struct AppProtocols {
+    gossip: Arc<Mutex<GossipRouter>>,
+    txs: Arc<Mutex<TxGossip>>,
+}
+
+#[derive(Debug, Error)]
+pub enum ServiceError {
+    #[error("failed to open chain: {0}")]
+    Chain(#[from] StoreError),
+
+    #[error(transparent)]
+    NodeDb(#[from] NodeDbError),
+}
+
+// what keeps peers from asking more of us than we want to serve
+#[derive(Clone)]
+struct Inbound {
+    scores: Arc<Mutex<PeerScores>>,
+    rate: Arc<Mutex<RateLimiter>>,
+    streams: Arc<Semaphore>,
+}
+
+// Requests that make us read the chain or the table get tight limits;
+// everything else shares a default roomy enough for gossip bursts.
+fn rate_limits() -> RateLimiter {
+    let serving = Limit { burst: 20, per_sec: 10 };
+    RateLimiter::new(Limit { burst: 500, per_sec: 250 })
+        .with_limit("RequestHeaders", serving)
+        .with_limit("RequestHashes", serving)
+        .with_limit("RequestBodies", serving)
+        .with_limit("GetPooledTransactions", serving)
+        .with_limit("FindNodes", Limit { burst: 10, per_sec: 2 })
+        .with_limit("RegisterTopic", Limit { burst: 10, per_sec: 1 })
+        .with_limit("Ping", Limit { burst: 5, per_sec: 1 })
+}
+
+// the routing table, the database that outlives it across restarts, and
+// the topic ads other nodes placed with us
+#[derive(Clone)]
+struct KnownNodes {
+    table: Arc<Mutex<PeerTable>>,
+    db: Arc<Mutex<NodeDb>>,
+    ads: Arc<Mutex<TopicAds>>,
+}
+
+// our own record; peers' address votes may move it unless the operator pinned it
+#[derive(Clone)]
+struct LocalNode {
+    enr: Arc<Mutex<Enr>>,
+    key: Keypair, // signs the record each time it changes
+    votes: Arc<Mutex<AddrVotes>>,
+    pinned: bool,
+}
+
+impl LocalNode {
+    fn enr(&self) -> Enr {
+        self.enr.lock().unwrap().clone()
+    }
+
+    // peers only take a record we signed, so every change is signed anew
+    fn sign(&self, enr: &mut Enr) {
+        enr.signed = signed_record(enr, &self.key);
+        if enr.signed.is_none() {
+            println!("[DISC] local record is too large to sign; peers will not take it");
+        }
+    }
+
+    /// We are about to ping `peer` over its connection.
+    fn pinged(&self, peer: SocketAddr) {
+        self.votes.lock().unwrap().pinged(peer.ip(), Instant::now());
+    }
+
+    /// Count `voter`'s report of where our packets come from.
+    fn observed(&self, voter: IpAddr, addr: SocketAddr) {
+        let agreed = self.votes.lock().unwrap().vote(voter, addr, Instant::now());
+        let Some(addr) = agreed.filter(|_| !self.pinned) else { return };
+        let mut enr = self.enr.lock().unwrap();
+        if enr.set_addr(addr) {
+            self.sign(&mut enr);
+            println!("[DISC] peers see us at {}, advertising it (seq {})", addr, enr.seq);
+        }
+    }
+}
 
 pub struct DiscoveryService {
     endpoint: Endpoint,
-    local_enr: Enr,
-    table: Arc<Mutex<PeerTable>>,
+    local: LocalNode,
+    nodes: KnownNodes,
     chain: Arc<Mutex<ChainManager>>,
+    scores: Arc<Mutex<PeerScores>>,
+    inbound: Inbound,
+    conns: Connections,
+    apps: AppProtocols,
     local_caps: Vec<String>,
+    spec: ChainSpec,
+    producer: Option<Keypair>,
+    families: Families,
+    v5: Option<Discv5>,
+}
+
+/// Application access to topic pubsub over the node's peer connections.
+#[derive(Clone)]
+pub struct GossipHandle {
+    router: Arc<Mutex<GossipRouter>>,
+    conns: Connections,
+}
+
+impl GossipHandle {
+    pub async fn subscribe(&self, topic: &str) -> Subscription {
+        let (sub, out) = self.router.lock().unwrap().subscribe(topic);
+        send_gossip(out, &self.conns).await;
+        sub
+    }
+
+    pub async fn unsubscribe(&self, topic: &str) {
+        let out = self.router.lock().unwrap().unsubscribe(topic);
+        send_gossip(out, &self.conns).await;
+    }
+
+    pub async fn publish(&self, topic: &str, data: Vec<u8>) -> MessageId {
+        let (id, out) = self.router.lock().unwrap().publish(topic, data, Instant::now());
+        send_gossip(out, &self.conns).await;
+        id
+    }
+}
+
+/// Local transaction submission, e.g. for load tests.
+#[derive(Clone)]
+pub struct TxHandle {
+    txs: Arc<Mutex<TxGossip>>,
+    conns: Connections,
+    scores: Arc<Mutex<PeerScores>>,
+}
+
+impl TxHandle {
+    /// Pool `tx` and gossip it to every connected peer; returns its hash.
+    pub async fn submit(&self, tx: Transaction) -> Result<String, PoolError> {
+        let hash = tx.hash.clone();
+        let peers: Vec<String> = self.conns.lock().unwrap().keys().cloned().collect();
+        let actions = self.txs.lock().unwrap().submit(tx, &peers)?;
+        perform_tx(actions, &self.conns, &self.scores).await;
+        Ok(hash)
+    }
+
+    pub fn pool_size(&self) -> usize {
+        self.txs.lock().unwrap().pool().len()
+    }
 }
 
 impl DiscoveryService {
-    pub fn new(endpoint: Endpoint, local_enr: Enr) -> Self {
-        let genesis = "0xgenesis".to_string();
-        Self {
+    pub fn new(
+        endpoint: Endpoint,
+        key: Keypair,
+        local_enr: Enr,
+        config: NodeConfig,
+    ) -> Result<Self, ServiceError> {
+        let store: Box<dyn ChainStore> = match &config.data_dir {
+            Some(dir) => Box::new(FileStore::open(dir)?),
+            None => Box::new(MemoryStore::new()),
+        };
+        let db = match &config.data_dir {
+            Some(dir) => NodeDb::open(dir)?,
+            None => NodeDb::in_memory(),
+        };
+        let chain = ChainManager::new(&config.chain, store)?;
+        println!(
+            "[CHAIN] {} network_id={} genesis={} fork_id={} fork_choice={}",
+            config.chain.name,
+            config.chain.network_id,
+            chain.genesis_hash(),
+            chain.fork_id(),
+            config.chain.fork_choice
+        );
+        let local_caps = vec![
+            DISC_PROTO.to_string(),
+            SYNC_PROTO.to_string(),
+            GOSSIP_PROTO.to_string(),
+            TX_PROTO.to_string(),
+        ];
+        let local_enr = Enr {
+            fork_id: Some(chain.fork_id()),
+            caps: local_caps.clone(),
+            topics: config.topics.clone(),
+            ..local_enr
+        };
+        let local = LocalNode {
+            enr: Arc::new(Mutex::new(local_enr)),
+            key,
+            votes: Arc::new(Mutex::new(AddrVotes::new())),
+            pinned: !config.advertise.is_empty(),
+        };
+        local.sign(&mut local.enr.lock().unwrap());
+        let apps = AppProtocols {
+            gossip: Arc::new(Mutex::new(GossipRouter::new(&local.enr().node_id))),
+            txs: Arc::new(Mutex::new(TxGossip::new(TxPool::new(PoolConfig::default())))),
+        };
+
+        let scores = Arc::new(Mutex::new(PeerScores::new()));
+        let inbound = Inbound {
+            scores: scores.clone(),
+            rate: Arc::new(Mutex::new(rate_limits())),
+            streams: Arc::new(Semaphore::new(MAX_INBOUND_STREAMS)),
+        };
+
+        Ok(Self {
             endpoint,
-            local_enr,
-            table: Arc::new(Mutex::new(PeerTable::new(32))),
-            chain: Arc::new(Mutex::new(ChainManager::new(genesis))),
-            local_caps: vec![DISC_PROTO.to_string(), SYNC_PROTO.to_string()],
+            local,
+            nodes: KnownNodes {
+                table: Arc::new(Mutex::new(PeerTable::new(BUCKET_SIZE))),
+                db: Arc::new(Mutex::new(db)),
+                ads: Arc::new(Mutex::new(TopicAds::new())),
+            },
+            chain: Arc::new(Mutex::new(chain)),
+            scores,
+            inbound,
+            conns: Arc::new(Mutex::new(HashMap::new())),
+            apps,
+            local_caps,
+            families: config.families(),
+            spec: config.chain,
+            producer: config.producer,
+            v5: None,
+        })
+    }
+
+    /// Also speak discv5 on `socket`, under the node key, and advertise
+    /// its port in our record.
+    pub fn with_discv5(mut self, socket: UdpSocket) -> Self {
+        let port = socket.local_addr().expect("bound socket").port();
+        {
+            let mut enr = self.local.enr.lock().unwrap();
+            enr.udp = Some(port);
+            self.local.sign(&mut enr);
+        }
+        let handler = V5Handler {
+            local: self.local.clone(),
+            nodes: self.nodes.clone(),
+            chain: self.chain.clone(),
+        };
+        self.v5 = Some(Discv5::start(socket, self.local.key.clone(), Arc::new(handler)));
+        println!("[DISC] discv5 on udp port {port}");
+        self
+    }
+
+    pub fn gossip(&self) -> GossipHandle {
+        GossipHandle {
+            router: self.apps.gossip.clone(),
+            conns: self.conns.clone(),
+        }
+    }
+
+    pub fn txs(&self) -> TxHandle {
+        TxHandle {
+            txs: self.apps.txs.clone(),
+            conns: self.conns.clone(),
+            scores: self.scores.clone(),
         }
     }
 
-    pub async fn run(self, bootstrap: Option<SocketAddr>) {
-        // enable header producer on leader
-        if self.local_enr.port == 9001 {
-            start_header_producer(self.chain.clone(), 2).await;
-            println!("[MINE] header producer enabled");
+    pub async fn run(self, bootnodes: Vec<SocketAddr>, trees: Vec<TreeUrl>) {
+        if let Some(keypair) = self.producer.clone() {
+            let (chain, txs) = (self.chain.clone(), self.apps.txs.clone());
+            start_header_producer(chain, self.spec.slot_secs, keypair, txs).await;
+            println!("[MINE] block producer enabled");
         }
 
+        let local_enr = self.local.enr();
+        let addrs: Vec<String> = local_enr.addrs().iter().map(|a| a.to_string()).collect();
         println!(
-            "[DISC] local ENR: node_id={} addr={}:{}",
-            self.local_enr.node_id, self.local_enr.ip, self.local_enr.port
+            "[DISC] local ENR: node_id={} addrs={}",
+            local_enr.node_id,
+            addrs.join(",")
         );
 
-        // ---------------- inbound accept loop ----------------
-        let ep = self.endpoint.clone();
-        let table = self.table.clone();
+        // ---------------- seed from the node database ----------------
+        let seeds = self.nodes.db.lock().unwrap().seeds(MAX_SEEDS);
+        let seeded = adopt(&self.nodes, &self.chain, &local_enr, seeds);
+        if seeded > 0 {
+            println!("[DISC] seeded {seeded} peers from the node database");
+        }
+
+        // ---------------- DNS trees ----------------
+        // resolved in the background; refresh rounds dial what they add
+        for url in trees {
+            let (nodes, chain, local) = (self.nodes.clone(), self.chain.clone(), local_enr.clone());
+            tokio::spawn(async move {
+                let lookup = url.clone();
+                let found = tokio::task::spawn_blocking(move || {
+                    TreeClient::new(SystemResolver::from_resolv_conf()?).resolve(&lookup)
+                })
+                .await
+                .expect("dns lookup task");
+                match found {
+                    Ok(enrs) => {
+                        let total = enrs.len();
+                        let added = adopt(&nodes, &chain, &local, enrs);
+                        println!("[DISC] {url}: {total} nodes, {added} added to the table");
+                    }
+                    Err(e) => println!("[DISC] cannot resolve {url}: {e}"),
+                }
+            });
+        }
+
+        // ---------------- sync progress log ----------------
+        let mut progress = self.chain.lock().unwrap().subscribe_sync();
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                let p = match progress.recv().await {
+                    Ok(p) => p,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                if last != Some(p.state) || p.state == SyncState::Downloading {
+                    let eta = p.eta_secs.map(|s| format!("{s}s")).unwrap_or_else(|| "?".into());
+                    println!(
+                        "[SYNC] {} #{} of #{} (from #{}, eta {})",
+                        p.state, p.current_block, p.highest_block, p.starting_block, eta
+                    );
+                }
+                last = Some(p.state);
+            }
+        });
+
+        // ---------------- header download driver ----------------
         let chain = self.chain.clone();
-        let local = self.local_enr.clone();
-        let caps = self.local_caps.clone();
+        let scores = self.scores.clone();
+        let conns = self.conns.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(Duration::from_secs(1)).await;
+                let actions = chain.lock().unwrap().download_tick();
+                perform(actions, &conns, &scores).await;
+            }
+        });
 
+        // ---------------- head announcements ----------------
+        let mut events = self.chain.lock().unwrap().subscribe();
+        let chain = self.chain.clone();
+        let txs = self.apps.txs.clone();
+        let scores = self.scores.clone();
+        let conns = self.conns.clone();
         tokio::spawn(async move {
             loop {
-                if let Some(connecting) = ep.accept().await {
-                    if let Ok(conn) = connecting.await {
-                        let table = table.clone();
-                        let chain = chain.clone();
-                        let local = local.clone();
-                        let caps = caps.clone();
-
-                        tokio::spawn(async move {
-                            if let Ok(sess) =
-                                inbound_handshake(&conn, &local.node_id, &caps).await
-                            {
-                                println!(
-                                    "[SESS] inbound {} agreed={:?}",
-                                    sess.remote_node_id, sess.agreed_caps
-                                );
-                                connection_loop(conn, local, table, chain).await;
-                            }
-                        });
+                // every kind of head change (and a lag) ends with announcing the current head
+                match events.recv().await {
+                    Ok(ChainEvent::Body { transactions, .. }) => {
+                        txs.lock().unwrap().pool_mut().remove_included(&transactions);
+                        continue;
                     }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                    _ => {}
                 }
+                let peers: Vec<String> = conns.lock().unwrap().keys().cloned().collect();
+                let actions = chain.lock().unwrap().announce_head(&peers);
+                perform(actions, &conns, &scores).await;
             }
         });
 
-        // ---------------- bootstrap ----------------
-        if let Some(addr) = bootstrap {
-            if let Ok(conn) = self.dial(addr).await {
-                if let Ok(sess) =
-                    outbound_handshake(&conn, &self.local_enr.node_id, &self.local_caps).await
+        // ---------------- gossip heartbeat ----------------
+        let gossip = self.apps.gossip.clone();
+        let conns = self.conns.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(HEARTBEAT_INTERVAL).await;
+                let out = gossip.lock().unwrap().heartbeat(Instant::now());
+                send_gossip(out, &conns).await;
+            }
+        });
+
+        // ---------------- inbound accept loop ----------------
+        let ep = self.endpoint.clone();
+        let nodes = self.nodes.clone();
+        let chain = self.chain.clone();
+        let inbound = self.inbound.clone();
+        let conns = self.conns.clone();
+        let apps = self.apps.clone();
+        let local = self.local.clone();
+        let caps = self.local_caps.clone();
+
+        tokio::spawn(async move {
+            loop {
+                if let Some(connecting) = ep.accept().await
+                    && let Ok(conn) = connecting.await
                 {
-                    println!("[SESS] outbound bootstrap {:?}", sess);
+                    if inbound.scores.lock().unwrap().is_banned(&peer_addr(conn.remote_address()).to_string()) {
+                        conn.close(0u32.into(), b"banned");
+                        continue;
+                    }
+                    let nodes = nodes.clone();
+                    let chain = chain.clone();
+                    let inbound = inbound.clone();
+                    let conns = conns.clone();
+                    let apps = apps.clone();
+                    let local = local.clone();
+                    let caps = caps.clone();
 
-                    let _ = send_enveloped(
-                        &conn,
-                        DISC_PROTO,
-                        &DiscoveryMessage::Ping {
-                            from: self.local_enr.clone(),
+                    tokio::spawn(async move {
+                        let local_chain = chain_info(&chain);
+                        let node_id = local.enr().node_id;
+                        if let Ok(sess) = inbound_handshake(
+                            &conn,
+                            &node_id,
+                            &caps,
+                            &local_chain,
+                            |remote| check_remote(&chain, remote),
+                        )
+                        .await
+                        {
+                            println!(
+                                "[SESS] inbound {} agreed={:?}",
+                                sess.remote_node_id, sess.agreed_caps
+                            );
+                            connection_loop(conn, local, nodes, chain, inbound, conns, apps).await;
                         }
-                        .to_bytes(),
-                    )
-                    .await;
-
-                    let st = self.local_status();
-                    let _ = send_enveloped(
-                        &conn,
-                        SYNC_PROTO,
-                        &MiniSyncMessage::Status(st).to_bytes(),
-                    )
-                    .await;
+                    });
                 }
             }
+        });
+
+        // ---------------- bootstrap ----------------
+        for addr in bootnodes {
+            self.bootstrap(addr).await;
         }
 
         // ---------------- refresh loop ----------------
+        let mut last_ads: Option<Instant> = None;
+        let mut last_check = Instant::now();
+        let mut last_lookup = Instant::now();
         loop {
             self.refresh_round().await;
+            if last_check.elapsed() >= REVALIDATE_INTERVAL {
+                self.revalidate_round().await;
+                last_check = Instant::now();
+            }
+            if last_lookup.elapsed() >= LOOKUP_INTERVAL {
+                self.lookup_round();
+                last_lookup = Instant::now();
+            }
+            // renew well before the ads expire
+            if last_ads.is_none_or(|at| at.elapsed() >= AD_LIFETIME / 3) {
+                self.topic_round().await;
+                last_ads = Some(Instant::now());
+            }
             sleep(Duration::from_secs(3)).await;
         }
     }
 
+    async fn bootstrap(&self, addr: SocketAddr) {
+        let Ok(conn) = self.dial(addr).await else { return };
+        let Ok(sess) = outbound_handshake(
+            &conn,
+            &self.local.enr().node_id,
+            &self.local_caps,
+            &chain_info(&self.chain),
+            |remote| check_remote(&self.chain, remote),
+        )
+        .await
+        else {
+            return;
+        };
+        println!("[SESS] outbound bootstrap {:?}", sess);
+        self.greet(conn).await;
+    }
+
+    /// Start talking to a peer we dialed. The Ping's Pong tells us where
+    /// the peer sees us, which feeds the external address vote.
+    async fn greet(&self, conn: Connection) {
+        self.local.pinged(peer_addr(conn.remote_address()));
+        let _ = send_enveloped(
+            &conn,
+            DISC_PROTO,
+            &DiscoveryMessage::Ping {
+                from: self.local.enr(),
+            }
+            .to_bytes(),
+        )
+        .await;
+
+        let st = self.local_status();
+        let _ = send_enveloped(
+            &conn,
+            SYNC_PROTO,
+            &MiniSyncMessage::Status(st).to_bytes(),
+        )
+        .await;
+        self.serve(conn);
+    }
+
     fn local_status(&self) -> Status {
         // ✅ ChainManager knows canonical head + genesis
         self.chain.lock().unwrap().status()
     }
 
-    async fn dial(&self, addr: SocketAddr) -> Result<Connection, quinn::ConnectionError> {
-        let conn = self.endpoint.connect(addr, "localhost").unwrap().await?;
+    async fn dial(&self, addr: SocketAddr) -> Result<Connection, String> {
+        // an address of a family our socket does not serve fails right here
+        let connecting = self.endpoint.connect(addr, "localhost").map_err(|e| e.to_string())?;
+        let conn = tokio::time::timeout(DIAL_TIMEOUT, connecting)
+            .await
+            .map_err(|_| "timed out".to_string())?
+            .map_err(|e| e.to_string())?;
         println!("[DISC] dialed {}", addr);
         Ok(conn)
     }
 
+    /// Keep handling whatever the peer sends back on a connection we dialed.
+    fn serve(&self, conn: Connection) {
+        tokio::spawn(connection_loop(
+            conn,
+            self.local.clone(),
+            self.nodes.clone(),
+            self.chain.clone(),
+            self.inbound.clone(),
+            self.conns.clone(),
+            self.apps.clone(),
+        ));
+    }
+
+    /// Connect to table peers we have no live connection with. Connected
+    /// peers hear about new heads through announcements, so they are left
+    /// alone.
     async fn refresh_round(&self) {
-        let peers = self.table.lock().unwrap().list();
-
-        for p in peers {
-            let addr: SocketAddr = format!("{}:{}", p.ip, p.port).parse().unwrap();
-            if let Ok(conn) = self.dial(addr).await {
-                if outbound_handshake(&conn, &self.local_enr.node_id, &self.local_caps)
-                    .await
-                    .is_ok()
-                {
-                    let st = self.local_status();
-                    let _ = send_enveloped(
-                        &conn,
-                        SYNC_PROTO,
-                        &MiniSyncMessage::Status(st).to_bytes(),
-                    )
-                    .await;
+        // a peer that can't sync with us is not worth a connection
+        let peers = self.nodes.table.lock().unwrap().query(&PeerFilter::serving(SYNC_PROTO));
+
+        for p in peers.into_iter().filter(|p| !p.discovery_only()) {
+            // e.g. an IPv6-only peer while we only speak IPv4
+            let Ok(addr) = p.dial_addr(self.families) else { continue };
+            if self.conns.lock().unwrap().contains_key(&addr.to_string()) {
+                continue;
+            }
+            self.connect(&p, addr).await;
+        }
+
+        if let Err(e) = self.nodes.db.lock().unwrap().flush() {
+            println!("[DISC] failed to save the node database: {e}");
+        }
+    }
+
+    /// Ping the least recently checked entry of each bucket, over discv5
+    /// or the live connection if there is one, and drop entries that
+    /// stopped answering.
+    async fn revalidate_round(&self) {
+        let round = self.nodes.table.lock().unwrap().revalidate(Instant::now());
+        for gone in round.dropped {
+            println!("[DISC] dropping {} from the table: no pong", gone.node_id);
+        }
+        for p in round.ping {
+            if let Some(v5) = &self.v5
+                && let Some(contact) = Contact::from_enr(&p, self.families)
+            {
+                tokio::spawn(v5_revalidate(v5.clone(), contact, p, self.local.clone(), self.nodes.clone()));
+                continue;
+            }
+            // nodes we only know from discovery don't speak our protocols
+            let addr = p.dial_addr(self.families).ok().filter(|_| !p.discovery_only());
+            let Some(addr) = addr else {
+                self.unreachable(&p);
+                continue;
+            };
+            let conn = self.conns.lock().unwrap().get(&addr.to_string()).cloned();
+            let Some(conn) = conn else {
+                // greeting a fresh connection pings it
+                self.connect(&p, addr).await;
+                continue;
+            };
+            self.local.pinged(peer_addr(conn.remote_address()));
+            let ping = DiscoveryMessage::Ping {
+                from: self.local.enr(),
+            }
+            .to_bytes();
+            // finishing the stream waits on the peer, which may be gone
+            tokio::spawn(async move {
+                let _ = send_enveloped(&conn, DISC_PROTO, &ping).await;
+            });
+        }
+    }
+
+    async fn connect(&self, p: &Enr, addr: SocketAddr) {
+        if let Ok(conn) = self.dial(addr).await
+            && outbound_handshake(
+                &conn,
+                &self.local.enr().node_id,
+                &self.local_caps,
+                &chain_info(&self.chain),
+                |remote| check_remote(&self.chain, remote),
+            )
+            .await
+            .is_ok()
+        {
+            self.greet(conn).await;
+        } else {
+            self.unreachable(p);
+        }
+    }
+
+    fn unreachable(&self, p: &Enr) {
+        self.nodes.db.lock().unwrap().failed(&p.node_id);
+        if let Some(gone) = self.nodes.table.lock().unwrap().on_unreachable(&p.node_id) {
+            println!("[DISC] dropping unreachable peer {}", gone.node_id);
+        }
+    }
+}
+
+// ---------------- discv5 ----------------
+
+impl DiscoveryService {
+    /// Ask a few table peers that speak discv5 for the nodes in their far
+    /// buckets; admissible ones join the table.
+    fn lookup_round(&self) {
+        let Some(v5) = self.v5.clone() else { return };
+        let contacts: Vec<Contact> = {
+            let table = self.nodes.table.lock().unwrap();
+            table
+                .list()
+                .iter()
+                .filter_map(|p| Contact::from_enr(p, self.families))
+                .take(LOOKUP_PEERS)
+                .collect()
+        };
+        let (nodes, chain, local) = (self.nodes.clone(), self.chain.clone(), self.local.clone());
+        tokio::spawn(async move {
+            for contact in contacts {
+                let found = v5.find_node(&contact, LOOKUP_DISTANCES.to_vec()).await;
+                let added = adopt(&nodes, &chain, &local.enr(), found);
+                if added > 0 {
+                    println!("[DISC] discv5 lookup via {}: {added} added to the table", contact.addr);
+                }
+            }
+        });
+    }
+}
+
+// A discv5 pong counts like one over QUIC; a higher record seq in it
+// makes us fetch the new record.
+async fn v5_revalidate(v5: Discv5, contact: Contact, held: Enr, local: LocalNode, nodes: KnownNodes) {
+    // no answer: the next round counts the ping as failed
+    let Some((seq, _)) = v5.ping(&contact, local.enr().seq).await else { return };
+    let mut enr = held;
+    // a newer record, or the signed form we need to pass it on
+    if (seq > enr.seq || enr.signed.is_none())
+        && let Some(fresh) = v5
+            .find_node(&contact, vec![0])
+            .await
+            .into_iter()
+            .find(|e| e.node_id == enr.node_id)
+    {
+        enr = fresh;
+    }
+    nodes.db.lock().unwrap().pong(enr.clone(), unix_now());
+    if nodes.table.lock().unwrap().on_pong(&enr, Instant::now()) {
+        println!("[DISC] {} updated its record (seq {})", enr.node_id, enr.seq);
+    }
+}
+
+// answers discv5 requests from what the table holds
+struct V5Handler {
+    local: LocalNode,
+    nodes: KnownNodes,
+    chain: Arc<Mutex<ChainManager>>,
+}
+
+impl Handler for V5Handler {
+    fn local(&self) -> Enr {
+        self.local.enr()
+    }
+
+    fn handle(&self, _src: &NodeId, from: SocketAddr, record: Option<Enr>, request: Message) -> Vec<Message> {
+        let local = self.local.enr();
+        if let Some(enr) = record {
+            adopt(&self.nodes, &self.chain, &local, vec![enr]);
+        }
+        match request {
+            Message::Ping { req_id, .. } => vec![Message::Pong {
+                req_id,
+                enr_seq: local.seq,
+                ip: from.ip(),
+                port: from.port(),
+            }],
+            Message::FindNode { req_id, distances } => {
+                let table = self.nodes.table.lock().unwrap();
+                let mut records = vec![];
+                for d in distances {
+                    match d {
+                        0 => records.extend(local.signed.clone()),
+                        // only records their owners signed travel on
+                        d => records.extend(table.at_distance(d as usize).iter().filter_map(|e| e.signed.clone())),
+                    }
                 }
+                // a bucket's worth, however many distances were asked for
+                records.truncate(BUCKET_SIZE);
+                nodes_response(&req_id, records)
             }
+            // no TALKREQ protocols yet; an empty answer says so
+            Message::TalkReq { req_id, .. } => vec![Message::TalkResp { req_id, response: vec![] }],
+            _ => vec![],
         }
     }
 }
 
+// ---------------- topics ----------------
+
+impl DiscoveryService {
+    /// Place ads for our topics with the peers we are connected to, and ask
+    /// them who else is in those topics; the answers land in the table.
+    async fn topic_round(&self) {
+        let local = self.local.enr();
+        if local.topics.is_empty() {
+            return;
+        }
+        let conns: Vec<Connection> = self
+            .conns
+            .lock()
+            .unwrap()
+            .values()
+            .take(MAX_REGISTRARS)
+            .cloned()
+            .collect();
+        for conn in conns {
+            for topic in &local.topics {
+                let register = DiscoveryMessage::RegisterTopic {
+                    from: local.clone(),
+                    topic: topic.clone(),
+                };
+                let ask = DiscoveryMessage::FindNodes {
+                    from: local.clone(),
+                    filter: PeerFilter::in_topic(topic),
+                };
+                let _ = send_enveloped(&conn, DISC_PROTO, &register.to_bytes()).await;
+                let _ = send_enveloped(&conn, DISC_PROTO, &ask.to_bytes()).await;
+            }
+        }
+    }
+}
+
+// Remember nodes found outside the wire protocol and give the admissible
+// ones a table slot; returns how many got one.
+fn adopt(nodes: &KnownNodes, chain: &Arc<Mutex<ChainManager>>, local: &Enr, enrs: Vec<Enr>) -> usize {
+    let mut db = nodes.db.lock().unwrap();
+    for e in enrs.iter().filter(|e| e.node_id != local.node_id) {
+        db.learn(e.clone());
+    }
+    let admitted: Vec<Enr> = {
+        let chain = chain.lock().unwrap();
+        enrs.into_iter()
+            .filter(|e| e.admissible(|f| chain.check_fork_id(f).is_ok()))
+            .collect()
+    };
+    nodes.table.lock().unwrap().insert_many(local, admitted).len()
+}
+
 // ---------------- per-connection demux ----------------
 
 async fn connection_loop(
     conn: Connection,
-    local: Enr,
-    table: Arc<Mutex<PeerTable>>,
+    local: LocalNode,
+    nodes: KnownNodes,
     chain: Arc<Mutex<ChainManager>>,
+    inbound: Inbound,
+    conns: Connections,
+    apps: AppProtocols,
 ) {
+    let scores = &inbound.scores;
+    let peer = peer_addr(conn.remote_address()).to_string();
+    conns.lock().unwrap().insert(peer.clone(), conn.clone());
+    let hello = apps.gossip.lock().unwrap().add_peer(&peer);
+    send_gossip(hello, &conns).await;
+    let pooled = apps.txs.lock().unwrap().on_connect(&peer);
+    perform_tx(pooled, &conns, scores).await;
+
+    let own_streams = Arc::new(Semaphore::new(MAX_STREAMS_PER_CONN));
     loop {
-        let Ok((_s, mut recv)) = conn.accept_bi().await else { return };
+        let Ok((_s, mut recv)) = conn.accept_bi().await else { break };
+        // past either cap, wait for a slot before taking more streams
+        let Ok(own_slot) = own_streams.clone().acquire_owned().await else { break };
+        let Ok(slot) = inbound.streams.clone().acquire_owned().await else { break };
 
-        let Ok(len) = recv.read_u32().await else { continue };
-        let mut buf = vec![0u8; len as usize];
-        if recv.read_exact(&mut buf).await.is_err() {
-            continue;
-        }
+        let conn = conn.clone();
+        let peer = peer.clone();
+        let local = local.clone();
+        let nodes = nodes.clone();
+        let chain = chain.clone();
+        let inbound = inbound.clone();
+        let conns = conns.clone();
+        let apps = apps.clone();
+        tokio::spawn(async move {
+            let _slots = (own_slot, slot);
+            let scores = &inbound.scores;
+            let Some(buf) = read_frame(&mut recv).await else { return };
+            let Some(env) = Envelope::from_bytes(&buf) else { return };
 
-        let Some(env) = Envelope::from_bytes(&buf) else { continue };
+            // nothing we would handle gets a rate-limit bucket either
+            let known = [DISC_PROTO, SYNC_PROTO, GOSSIP_PROTO, TX_PROTO, SESSION_PROTO];
+            if !known.contains(&env.proto.as_str()) {
+                return;
+            }
+            let Some(kind) = env.kind() else { return };
+            let allowed = inbound.rate.lock().unwrap().check(&peer, &kind, Instant::now());
+            if let Err(wait) = allowed {
+                println!("[SESS] throttling {kind} from {peer}");
+                apply_score(&peer, THROTTLE_PENALTY, &conns, scores);
+                let notice = SessionMessage::Throttled(Throttled {
+                    kind,
+                    retry_after_ms: wait.as_millis() as u64,
+                });
+                let _ = send_enveloped(&conn, SESSION_PROTO, &notice.to_bytes()).await;
+                return;
+            }
+
+            match env.proto.as_str() {
+                DISC_PROTO => handle_discovery_msg(&conn, &local, &nodes, &chain, &env.data).await,
+                SYNC_PROTO => handle_sync_msg(&conn, &chain, scores, &conns, &env.data).await,
+                GOSSIP_PROTO => handle_gossip_msg(&conn, &apps.gossip, &conns, &env.data).await,
+                TX_PROTO => handle_tx_msg(&conn, &apps.txs, scores, &conns, &env.data).await,
+                SESSION_PROTO => {
+                    if let Some(SessionMessage::Throttled(t)) = SessionMessage::from_bytes(&env.data) {
+                        println!("[SESS] {peer} throttled our {}, retry in {}ms", t.kind, t.retry_after_ms);
+                    }
+                }
+                _ => {}
+            }
+        });
+    }
 
-        match env.proto.as_str() {
-            DISC_PROTO => handle_discovery_msg(&conn, &local, &table, &env.data).await,
-            SYNC_PROTO => handle_sync_msg(&conn, &chain, &env.data).await,
-            _ => {}
+    let mut conns = conns.lock().unwrap();
+    if conns.get(&peer).is_some_and(|c| c.stable_id() == conn.stable_id()) {
+        conns.remove(&peer);
+        chain.lock().unwrap().on_disconnect(&peer);
+        apps.gossip.lock().unwrap().remove_peer(&peer);
+        apps.txs.lock().unwrap().forget_peer(&peer);
+        inbound.rate.lock().unwrap().forget(&peer);
+    }
+}
+
+// One length-prefixed frame; a length over the cap is refused before we
+// allocate for it, and a peer that trickles bytes is cut off.
+async fn read_frame(recv: &mut quinn::RecvStream) -> Option<Vec<u8>> {
+    let read = async {
+        let len = recv.read_u32().await.ok()? as usize;
+        if len > MAX_FRAME_SIZE {
+            return None;
         }
+        let mut buf = vec![0u8; len];
+        recv.read_exact(&mut buf).await.ok()?;
+        Some(buf)
+    };
+    tokio::time::timeout(FRAME_READ_TIMEOUT, read).await.ok()?
+}
+
+// ---------------- chain identity ----------------
+
+fn chain_info(chain: &Arc<Mutex<ChainManager>>) -> ChainInfo {
+    let mgr = chain.lock().unwrap();
+    ChainInfo {
+        network_id: mgr.network_id(),
+        genesis: mgr.genesis_hash(),
+        head_height: mgr.canonical_height(),
+        fork_id: mgr.fork_id(),
     }
 }
 
+fn check_remote(chain: &Arc<Mutex<ChainManager>>, remote: &ChainInfo) -> Result<(), IncompatiblePeer> {
+    chain
+        .lock()
+        .unwrap()
+        .check_peer(remote.network_id, &remote.genesis, &remote.fork_id)
+}
+
 // ---------------- discovery ----------------
 
 async fn handle_discovery_msg(
     conn: &Connection,
-    local: &Enr,
-    table: &Arc<Mutex<PeerTable>>,
+    node: &LocalNode,
+    nodes: &KnownNodes,
+    chain: &Arc<Mutex<ChainManager>>,
     payload: &[u8],
 ) {
     let Some(msg) = DiscoveryMessage::from_bytes(payload) else { return };
+    let local = &node.enr();
+    let table = &nodes.table;
+
+    // records their node did not sign, or without a compatible fork id,
+    // never take a table slot
+    let compatible = |enr: &Enr| {
+        enr.authentic()
+            && enr
+                .fork_id
+                .is_some_and(|f| chain.lock().unwrap().check_fork_id(&f).is_ok())
+    };
 
     match msg {
         DiscoveryMessage::Ping { from } => {
+            if !compatible(&from) {
+                println!("[DISC] ignoring ping from {} (unsigned, or fork id {:?})", from.node_id, from.fork_id);
+                return;
+            }
+            nodes.db.lock().unwrap().seen(from.clone(), unix_now());
             table.lock().unwrap().insert(local, from.clone());
             let _ = send_enveloped(
                 conn,
                 DISC_PROTO,
                 &DiscoveryMessage::Pong {
                     from: local.clone(),
+                    observed: Some(peer_addr(conn.remote_address())),
+                }
+                .to_bytes(),
+            )
+            .await;
+        }
+        DiscoveryMessage::FindNodes { filter, .. } => {
+            let mut peers = table.lock().unwrap().query(&filter);
+            if let Some(topic) = &filter.topic {
+                let ads = nodes.ads.lock().unwrap().nodes(topic, Instant::now());
+                for ad in ads.into_iter().filter(|e| filter.matches(e)) {
+                    if !peers.iter().any(|p| p.node_id == ad.node_id) {
+                        peers.push(ad);
+                    }
+                }
+            }
+            let _ = send_enveloped(
+                conn,
+                DISC_PROTO,
+                &DiscoveryMessage::Nodes {
+                    from: local.clone(),
+                    peers,
                 }
                 .to_bytes(),
             )
             .await;
         }
         DiscoveryMessage::Nodes { from, peers } => {
-            table.lock().unwrap().insert(local, from);
+            let peers: Vec<Enr> = peers
+                .into_iter()
+                .filter(|p| p.admissible(|f| chain.lock().unwrap().check_fork_id(f).is_ok()))
+                .collect();
+            {
+                let mut db = nodes.db.lock().unwrap();
+                for p in peers.iter().filter(|p| p.node_id != local.node_id) {
+                    db.learn(p.clone());
+                }
+                if compatible(&from) {
+                    db.seen(from.clone(), unix_now());
+                }
+            }
+            if compatible(&from) {
+                table.lock().unwrap().insert(local, from);
+            }
             table.lock().unwrap().insert_many(local, peers);
         }
-        _ => {}
+        DiscoveryMessage::Pong { from, observed } => {
+            if !compatible(&from) {
+                return;
+            }
+            if let Some(addr) = observed {
+                node.observed(peer_addr(conn.remote_address()).ip(), addr);
+            }
+            nodes.db.lock().unwrap().pong(from.clone(), unix_now());
+            let mut table = table.lock().unwrap();
+            if table.on_pong(&from, Instant::now()) {
+                println!("[DISC] {} updated its record (seq {})", from.node_id, from.seq);
+            } else {
+                table.insert(local, from);
+            }
+        }
+        DiscoveryMessage::RegisterTopic { from, topic } => {
+            // only members of the topic, on our chain, get an ad
+            if from.has_topic(&topic) && compatible(&from) {
+                nodes.ads.lock().unwrap().register(&topic, from, Instant::now());
+            }
+        }
     }
 }
 
@@ -215,40 +1058,144 @@ async fn handle_discovery_msg(
 async fn handle_sync_msg(
     conn: &Connection,
     chain: &Arc<Mutex<ChainManager>>,
+    scores: &Arc<Mutex<PeerScores>>,
+    conns: &Connections,
     payload: &[u8],
 ) {
     let Some(msg) = MiniSyncMessage::from_bytes(payload) else { return };
+    let peer = peer_addr(conn.remote_address()).to_string();
 
-    match msg {
-        MiniSyncMessage::Status(remote) => {
-            // ✅ decide under lock, do I/O after lock is dropped
-            let req_opt = {
-                let mgr = chain.lock().unwrap();
-                if mgr.should_request(&remote) {
-                    Some(mgr.build_request(&remote))
-                } else {
-                    None
-                }
-            };
+    // ✅ decide under lock, do I/O after lock is dropped
+    let actions = {
+        let mut mgr = chain.lock().unwrap();
+        match msg {
+            MiniSyncMessage::Status(remote) => reply_to(&peer, mgr.on_status(&peer, &remote)),
+
+            MiniSyncMessage::RequestHashes(req) => {
+                reply_to(&peer, Some(MiniSyncMessage::Hashes(mgr.hashes_for(&req))))
+            }
+
+            MiniSyncMessage::Hashes(hs) => reply_to(&peer, mgr.on_hashes(&peer, hs)),
+
+            MiniSyncMessage::RequestHeaders(req) => {
+                reply_to(&peer, Some(MiniSyncMessage::Headers(mgr.headers_for(&req))))
+            }
+
+            // may touch other peers: more work for them, or blame for a bad batch
+            MiniSyncMessage::Headers(hs) => mgr.on_headers(&peer, hs.headers),
+
+            MiniSyncMessage::NewHeader(nh) => mgr.on_new_header(&peer, nh.header),
+
+            MiniSyncMessage::NewHeadHashes(ann) => mgr.on_new_head_hashes(&peer, ann.hashes),
+
+            MiniSyncMessage::RequestBodies(req) => {
+                reply_to(&peer, Some(MiniSyncMessage::Bodies(mgr.bodies_for(&req))))
+            }
 
-            if let Some(req) = req_opt {
-                let _ = send_enveloped(conn, SYNC_PROTO, &req.to_bytes()).await;
+            MiniSyncMessage::Bodies(b) => mgr.on_bodies(&peer, b.bodies),
+        }
+    };
+
+    perform(actions, conns, scores).await;
+}
+
+fn reply_to(peer: &str, msg: Option<MiniSyncMessage>) -> Vec<SyncAction> {
+    msg.map(|msg| SyncAction::Send {
+        peer: peer.to_string(),
+        msg,
+    })
+    .into_iter()
+    .collect()
+}
+
+/// Carry out what the chain manager asked for.
+async fn perform(actions: Vec<SyncAction>, conns: &Connections, scores: &Arc<Mutex<PeerScores>>) {
+    for action in actions {
+        match action {
+            SyncAction::Send { peer, msg } => {
+                let conn = conns.lock().unwrap().get(&peer).cloned();
+                if let Some(conn) = conn {
+                    let _ = send_enveloped(&conn, SYNC_PROTO, &msg.to_bytes()).await;
+                }
             }
+            SyncAction::Score { peer, delta } => apply_score(&peer, delta, conns, scores),
         }
+    }
+}
 
-        MiniSyncMessage::Headers(hs) => {
-            // import_headers() is sync-only (no await), so locking is fine here
-            let mut mgr = chain.lock().unwrap();
-            mgr.import_headers(hs.headers);
+/// Adjust a peer's reputation and drop its connection once it is banned.
+fn apply_score(peer: &str, delta: i32, conns: &Connections, scores: &Arc<Mutex<PeerScores>>) {
+    if delta == 0 {
+        return;
+    }
+    let mut scores = scores.lock().unwrap();
+    let score = scores.adjust(peer, delta);
+    if scores.is_banned(peer) {
+        // every connection from the host goes, not just the one that misbehaved
+        let host = peer_host(peer);
+        println!("[SESS] banning {} (score {})", host, score);
+        for (_, conn) in conns.lock().unwrap().iter().filter(|(p, _)| peer_host(p) == host) {
+            conn.close(0u32.into(), b"misbehaving");
         }
+    }
+}
 
-        _ => {}
+// ---------------- gossip ----------------
+
+async fn handle_gossip_msg(
+    conn: &Connection,
+    gossip: &Arc<Mutex<GossipRouter>>,
+    conns: &Connections,
+    payload: &[u8],
+) {
+    let Some(msg) = GossipMessage::from_bytes(payload) else { return };
+    let peer = peer_addr(conn.remote_address()).to_string();
+    let out = gossip.lock().unwrap().handle(&peer, msg, Instant::now());
+    send_gossip(out, conns).await;
+}
+
+async fn send_gossip(out: Outbox, conns: &Connections) {
+    for (peer, msg) in out {
+        let conn = conns.lock().unwrap().get(&peer).cloned();
+        if let Some(conn) = conn {
+            let _ = send_enveloped(&conn, GOSSIP_PROTO, &msg.to_bytes()).await;
+        }
+    }
+}
+
+// ---------------- transactions ----------------
+
+async fn handle_tx_msg(
+    conn: &Connection,
+    txs: &Arc<Mutex<TxGossip>>,
+    scores: &Arc<Mutex<PeerScores>>,
+    conns: &Connections,
+    payload: &[u8],
+) {
+    let Some(msg) = TxMessage::from_bytes(payload) else { return };
+    let peer = peer_addr(conn.remote_address()).to_string();
+    let peers: Vec<String> = conns.lock().unwrap().keys().cloned().collect();
+    let actions = txs.lock().unwrap().handle(&peer, msg, &peers, Instant::now());
+    perform_tx(actions, conns, scores).await;
+}
+
+async fn perform_tx(actions: Vec<TxAction>, conns: &Connections, scores: &Arc<Mutex<PeerScores>>) {
+    for action in actions {
+        match action {
+            TxAction::Send { peer, msg } => {
+                let conn = conns.lock().unwrap().get(&peer).cloned();
+                if let Some(conn) = conn {
+                    let _ = send_enveloped(&conn, TX_PROTO, &msg.to_bytes()).await;
+                }
+            }
+            TxAction::Score { peer, delta } => apply_score(&peer, delta, conns, scores),
+        }
     }
 }
 
 // ---------------- framed sender ----------------
 
-async fn send_enveloped(conn: &Connection, proto: &str, payload: &[u8]) -> Result<(), ()> {
+pub(crate) async fn send_enveloped(conn: &Connection, proto: &str, payload: &[u8]) -> Result<(), ()> {
     let env = Envelope::new(proto.to_string(), payload.to_vec()).to_bytes();
     let (mut send, _) = conn.open_bi().await.map_err(|_| ())?;
     send.write_u32(env.len() as u32).await.map_err(|_| ())?;
@@ -1,35 +1,117 @@
 use crate::discovery::enr::Enr;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Which records a query wants; empty parts match anything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerFilter {
+    // every one of these must be served
+    #[serde(default)]
+    pub caps: Vec<String>,
+    #[serde(default)]
+    pub topic: Option<String>,
+}
+
+impl PeerFilter {
+    pub fn serving(cap: &str) -> Self {
+        Self {
+            caps: vec![cap.to_string()],
+            topic: None,
+        }
+    }
+
+    pub fn in_topic(topic: &str) -> Self {
+        Self {
+            caps: vec![],
+            topic: Some(topic.to_string()),
+        }
+    }
+
+    /// Records from before capabilities were advertised list none; they are
+    /// not ruled out on capabilities, only on topic.
+    pub fn matches(&self, enr: &Enr) -> bool {
+        let caps = enr.caps.is_empty() || self.caps.iter().all(|c| enr.serves(c));
+        let topic = self.topic.as_ref().is_none_or(|t| enr.has_topic(t));
+        caps && topic
+    }
+}
+
+// a ping unanswered for this long counts as a failure
+pub const PONG_TIMEOUT: Duration = Duration::from_secs(5);
+// failures in a row before an entry gives up its slot
+const MAX_FAILURES: u32 = 3;
+// per bucket: nodes waiting for a slot to free up, newest last
+const MAX_REPLACEMENTS: usize = 8;
 
+#[derive(Debug)]
+struct Entry {
+    enr: Enr,
+    last_checked: Option<Instant>,
+    ping_sent: Option<Instant>,
+    failures: u32,
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    entries: Vec<Entry>,
+    replacements: Vec<Enr>,
+}
+
+/// What a revalidation round asks of the service.
+#[derive(Debug, Default)]
+pub struct Revalidation {
+    // ping these; `on_pong` or `on_unreachable` reports back
+    pub ping: Vec<Enr>,
+    // entries that stopped answering and lost their slot
+    pub dropped: Vec<Enr>,
+}
+
+/// Known peers in Kademlia buckets by log distance from us. Entries that
+/// keep answering pings keep their slot; a full bucket queues newcomers as
+/// replacements for entries that stop answering.
 #[derive(Debug)]
 pub struct PeerTable {
-    max_size: usize,
-    peers: HashMap<String, Enr>, // node_id -> enr
+    bucket_size: usize,
+    buckets: BTreeMap<usize, Bucket>, // log distance -> bucket
 }
 
 impl PeerTable {
-    pub fn new(max_size: usize) -> Self {
+    pub fn new(bucket_size: usize) -> Self {
         Self {
-            max_size,
-            peers: HashMap::new(),
+            bucket_size,
+            buckets: BTreeMap::new(),
         }
     }
 
+    /// Add `enr` if its bucket has room; true if it got a slot.
     pub fn insert(&mut self, local: &Enr, enr: Enr) -> bool {
         if enr.node_id == local.node_id {
             return false;
         }
-        if self.peers.contains_key(&enr.node_id) {
+        let bucket = self.buckets.entry(log_distance(&local.node_id, &enr.node_id)).or_default();
+        if let Some(known) = bucket.entries.iter_mut().find(|e| e.enr.node_id == enr.node_id) {
+            // a newer copy of a record we hold replaces it in place
+            if supersedes(&enr, &known.enr) {
+                known.enr = enr;
+            }
             return false;
         }
-        if self.peers.len() >= self.max_size {
-            // simplest eviction: drop an arbitrary peer
-            if let Some(k) = self.peers.keys().next().cloned() {
-                self.peers.remove(&k);
-            }
+        if bucket.entries.len() < self.bucket_size {
+            bucket.entries.push(Entry {
+                enr,
+                last_checked: None,
+                ping_sent: None,
+                failures: 0,
+            });
+            return true;
         }
-        self.peers.insert(enr.node_id.clone(), enr);
-        true
+        bucket.replacements.retain(|r| r.node_id != enr.node_id);
+        if bucket.replacements.len() >= MAX_REPLACEMENTS {
+            bucket.replacements.remove(0);
+        }
+        bucket.replacements.push(enr);
+        false
     }
 
     pub fn insert_many(&mut self, local: &Enr, enrs: Vec<Enr>) -> Vec<Enr> {
@@ -42,7 +124,268 @@ impl PeerTable {
         added
     }
 
+    pub fn remove(&mut self, node_id: &str) -> Option<Enr> {
+        for bucket in self.buckets.values_mut() {
+            bucket.replacements.retain(|r| r.node_id != node_id);
+            if let Some(i) = bucket.entries.iter().position(|e| e.enr.node_id == node_id) {
+                return Some(bucket.evict(i));
+            }
+        }
+        None
+    }
+
     pub fn list(&self) -> Vec<Enr> {
-        self.peers.values().cloned().collect()
+        self.entries().map(|e| e.enr.clone()).collect()
+    }
+
+    pub fn query(&self, filter: &PeerFilter) -> Vec<Enr> {
+        self.entries().filter(|e| filter.matches(&e.enr)).map(|e| e.enr.clone()).collect()
+    }
+
+    /// Entries at `distance` from us, the bucket a discv5 FINDNODE asks for.
+    pub fn at_distance(&self, distance: usize) -> Vec<Enr> {
+        let Some(bucket) = self.buckets.get(&distance) else { return vec![] };
+        bucket.entries.iter().map(|e| e.enr.clone()).collect()
+    }
+
+    /// Count pings that went unanswered, then pick the least recently
+    /// checked entry of each bucket to ping next.
+    pub fn revalidate(&mut self, now: Instant) -> Revalidation {
+        let mut out = Revalidation::default();
+        for bucket in self.buckets.values_mut() {
+            let timed_out: Vec<String> = bucket
+                .entries
+                .iter()
+                .filter(|e| e.ping_sent.is_some_and(|at| now.duration_since(at) >= PONG_TIMEOUT))
+                .map(|e| e.enr.node_id.clone())
+                .collect();
+            for id in timed_out {
+                out.dropped.extend(bucket.fail(&id));
+            }
+
+            let next = bucket
+                .entries
+                .iter_mut()
+                .filter(|e| e.ping_sent.is_none())
+                .min_by_key(|e| e.last_checked);
+            if let Some(e) = next {
+                e.ping_sent = Some(now);
+                out.ping.push(e.enr.clone());
+            }
+        }
+        out
+    }
+
+    /// `enr` answered a ping. Returns true if it came with a newer record
+    /// than the one we held.
+    pub fn on_pong(&mut self, enr: &Enr, now: Instant) -> bool {
+        let Some(entry) = self.entry_mut(&enr.node_id) else { return false };
+        entry.last_checked = Some(now);
+        entry.ping_sent = None;
+        entry.failures = 0;
+        if !supersedes(enr, &entry.enr) {
+            return false;
+        }
+        entry.enr = enr.clone();
+        true
+    }
+
+    /// A dial to `node_id` failed; returns the entry if that cost it its slot.
+    pub fn on_unreachable(&mut self, node_id: &str) -> Option<Enr> {
+        self.buckets.values_mut().find_map(|b| b.fail(node_id))
+    }
+
+    fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.buckets.values().flat_map(|b| &b.entries)
+    }
+
+    fn entry_mut(&mut self, node_id: &str) -> Option<&mut Entry> {
+        self.buckets
+            .values_mut()
+            .flat_map(|b| &mut b.entries)
+            .find(|e| e.enr.node_id == node_id)
+    }
+}
+
+impl Bucket {
+    fn fail(&mut self, node_id: &str) -> Option<Enr> {
+        let i = self.entries.iter().position(|e| e.enr.node_id == node_id)?;
+        let e = &mut self.entries[i];
+        e.ping_sent = None;
+        e.failures += 1;
+        (e.failures >= MAX_FAILURES).then(|| self.evict(i))
+    }
+
+    // the newest replacement takes the freed slot
+    fn evict(&mut self, i: usize) -> Enr {
+        let gone = self.entries.remove(i).enr;
+        if let Some(enr) = self.replacements.pop() {
+            self.entries.push(Entry {
+                enr,
+                last_checked: None,
+                ping_sent: None,
+                failures: 0,
+            });
+        }
+        gone
+    }
+}
+
+// a newer record, or the one we hold now that its signed form turned up;
+// either way only one the node itself signed
+fn supersedes(enr: &Enr, held: &Enr) -> bool {
+    enr.authentic() && (enr.seq > held.seq || (enr.seq == held.seq && held.signed.is_none()))
+}
+
+// Kademlia log distance: one plus the index of the highest bit in which
+// the two ids differ; ids that aren't hex count as farthest
+fn log_distance(a: &str, b: &str) -> usize {
+    let (Ok(a), Ok(b)) = (hex::decode(a), hex::decode(b)) else { return 256 };
+    for (i, (x, y)) in a.iter().zip(&b).enumerate() {
+        let d = x ^ y;
+        if d != 0 {
+            return (a.len() - i) * 8 - d.leading_zeros() as usize;
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_keypair;
+
+    fn enr(caps: &[&str], topics: &[&str]) -> Enr {
+        let mut e = Enr::new_local(&generate_keypair().unwrap(), &["127.0.0.1".parse().unwrap()], 9000);
+        e.caps = caps.iter().map(|c| c.to_string()).collect();
+        e.topics = topics.iter().map(|t| t.to_string()).collect();
+        e
+    }
+
+    #[test]
+    fn queries_filter_on_capabilities_and_topic() {
+        let local = enr(&[], &[]);
+        let syncer = enr(&["discv-lite/0.1", "mini-sync/0.1"], &["shard/3"]);
+        let crawler = enr(&["discv-lite/0.1"], &[]);
+        let legacy = enr(&[], &[]);
+        let mut table = PeerTable::new(8);
+        table.insert_many(&local, vec![syncer.clone(), crawler.clone(), legacy.clone()]);
+
+        let mut sync = table.query(&PeerFilter::serving("mini-sync/0.1"));
+        sync.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        let mut want = vec![syncer.clone(), legacy];
+        want.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        assert_eq!(sync, want);
+
+        assert_eq!(table.query(&PeerFilter::in_topic("shard/3")), vec![syncer]);
+        assert!(table.query(&PeerFilter::in_topic("shard/4")).is_empty());
+        assert_eq!(table.query(&PeerFilter::default()).len(), 3);
+    }
+
+    #[test]
+    fn silent_entries_give_way_to_replacements() {
+        // ids that all land in the farthest bucket from an all-zero local id
+        let with_id = |n: u8| {
+            let mut e = enr(&[], &[]);
+            let mut id = [0u8; 32];
+            id[0] = 0x80 | n;
+            e.node_id = hex::encode(id);
+            e
+        };
+        let mut local = enr(&[], &[]);
+        local.node_id = hex::encode([0u8; 32]);
+        let (a, b, spare) = (with_id(1), with_id(2), with_id(3));
+        let mut table = PeerTable::new(2);
+        assert_eq!(table.insert_many(&local, vec![a.clone(), b.clone(), spare.clone()]).len(), 2);
+
+        // one ping per bucket, the never-checked entry first
+        let now = Instant::now();
+        assert_eq!(table.revalidate(now).ping, vec![a.clone()]);
+        // an unsigned claim of a newer record counts as a pong, nothing more
+        let mut moved = a.clone();
+        moved.set_addr("203.0.113.5:9000".parse().unwrap());
+        assert!(!table.on_pong(&moved, now));
+        assert_eq!(table.revalidate(now).ping, vec![b.clone()]);
+
+        // b never answers; its last miss hands the slot to the replacement
+        let mut at = now;
+        for _ in 1..MAX_FAILURES {
+            at += PONG_TIMEOUT;
+            let round = table.revalidate(at);
+            assert!(round.dropped.is_empty());
+            assert_eq!(round.ping, vec![b.clone()]);
+        }
+        let round = table.revalidate(at + PONG_TIMEOUT);
+        assert_eq!(round.dropped, vec![b]);
+        assert_eq!(round.ping, vec![spare.clone()]);
+
+        let mut left = table.list();
+        left.sort_by(|x, y| x.node_id.cmp(&y.node_id));
+        assert_eq!(left, vec![a, spare.clone()]);
+        for _ in 0..MAX_FAILURES - 1 {
+            assert!(table.on_unreachable(&spare.node_id).is_none());
+        }
+        assert_eq!(table.on_unreachable(&spare.node_id), Some(spare));
+    }
+
+    #[test]
+    fn only_records_their_node_signed_replace_held_ones() {
+        let key = generate_keypair().unwrap();
+        let local = enr(&[], &[]);
+        let held = Enr::new_local(&key, &["127.0.0.1".parse().unwrap()], 9000);
+        let mut table = PeerTable::new(8);
+        assert!(table.insert(&local, held.clone()));
+
+        let mut newer = held.clone();
+        newer.set_addr("203.0.113.5:9000".parse().unwrap());
+        assert!(!table.on_pong(&newer, Instant::now()));
+
+        // someone else's signature over the same fields proves nothing
+        let forger = generate_keypair().unwrap();
+        let forged = Enr {
+            signed: Some(newer.to_record(&forger).encode()),
+            ..newer.clone()
+        };
+        table.insert(&local, forged.clone());
+        assert!(!table.on_pong(&forged, Instant::now()));
+        assert_eq!(table.list(), vec![held]);
+
+        let signed = Enr {
+            signed: Some(newer.to_record(&key).encode()),
+            ..newer
+        };
+        assert!(signed.authentic());
+        assert!(table.on_pong(&signed, Instant::now()));
+        assert_eq!(table.list(), vec![signed]);
+    }
+
+    #[test]
+    fn takes_in_geth_records_for_discovery() {
+        use crate::enr::{EnrBuilder, EnrRecord};
+
+        // a geth node's record: no "eth", no capabilities, tcp for a port
+        let key = generate_keypair().unwrap();
+        let text = EnrBuilder::new()
+            .add(b"ip", &[10, 0, 0, 7])
+            .add_raw(b"tcp", &rlp::encode(&30303u16))
+            .add_raw(b"udp", &rlp::encode(&30301u16))
+            .build(&key)
+            .to_string();
+        let geth: Enr = text.parse().unwrap();
+        let local = enr(&[], &[]);
+        let our_chain_only = |_: &_| false;
+        assert!(geth.admissible(our_chain_only) && geth.discovery_only());
+
+        let mut table = PeerTable::new(8);
+        assert!(table.insert(&local, geth.clone()));
+        let distance = log_distance(&local.node_id, &geth.node_id);
+        let held = table.at_distance(distance);
+        assert_eq!(held, vec![geth.clone()]);
+        // and what we relay is what geth signed
+        assert_eq!(held[0].signed, Some(text.parse::<EnrRecord>().unwrap().encode()));
+
+        // the same fields without the signature prove nothing
+        let unsigned = Enr { signed: None, ..geth };
+        assert!(!unsigned.admissible(our_chain_only));
     }
 }
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::discovery::enr::Enr;
+
+/// How long an ad stays up unless it is renewed.
+pub const AD_LIFETIME: Duration = Duration::from_secs(120);
+// per topic and overall, so registrations can't take unbounded memory
+const MAX_ADS_PER_TOPIC: usize = 64;
+const MAX_ADS: usize = 1024;
+
+/// Ads other nodes placed with us: who to hand out when someone asks for
+/// members of a topic. Like discv5 topic ads, minus the tickets; when a
+/// topic is full the oldest ad makes room.
+#[derive(Debug, Default)]
+pub struct TopicAds {
+    ads: HashMap<String, Vec<(Enr, Instant)>>, // topic -> (advertiser, placed at)
+}
+
+impl TopicAds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Place or renew `enr`'s ad for `topic`; false if there is no room.
+    pub fn register(&mut self, topic: &str, enr: Enr, now: Instant) -> bool {
+        self.expire(now);
+        if let Some(ads) = self.ads.get_mut(topic)
+            && let Some(ad) = ads.iter_mut().find(|(e, _)| e.node_id == enr.node_id)
+        {
+            *ad = (enr, now);
+            return true;
+        }
+        if self.len() >= MAX_ADS {
+            return false;
+        }
+        let ads = self.ads.entry(topic.to_string()).or_default();
+        if ads.len() >= MAX_ADS_PER_TOPIC {
+            ads.remove(0);
+        }
+        ads.push((enr, now));
+        true
+    }
+
+    /// Live advertisers of `topic`, newest ad first.
+    pub fn nodes(&mut self, topic: &str, now: Instant) -> Vec<Enr> {
+        self.expire(now);
+        let Some(ads) = self.ads.get(topic) else { return vec![] };
+        ads.iter().rev().map(|(e, _)| e.clone()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ads.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ads.is_empty()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        for ads in self.ads.values_mut() {
+            ads.retain(|(_, at)| now.duration_since(*at) < AD_LIFETIME);
+        }
+        self.ads.retain(|_, ads| !ads.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_keypair;
+
+    fn enr(topic: &str) -> Enr {
+        let mut e = Enr::new_local(&generate_keypair().unwrap(), &["127.0.0.1".parse().unwrap()], 9000);
+        e.topics = vec![topic.into()];
+        e
+    }
+
+    #[test]
+    fn ads_are_renewed_capped_and_expire() {
+        let now = Instant::now();
+        let mut ads = TopicAds::new();
+        let (a, b) = (enr("shard/3"), enr("shard/3"));
+
+        assert!(ads.register("shard/3", a.clone(), now));
+        assert!(ads.register("shard/3", b.clone(), now + AD_LIFETIME / 2));
+        assert!(ads.register("shard/3", a.clone(), now + AD_LIFETIME / 2));
+        assert_eq!(ads.nodes("shard/3", now + AD_LIFETIME / 2), vec![b.clone(), a.clone()]);
+        assert!(ads.nodes("shard/4", now).is_empty());
+
+        // a full topic drops its oldest ad
+        for _ in 0..MAX_ADS_PER_TOPIC {
+            ads.register("shard/3", enr("shard/3"), now + AD_LIFETIME / 2);
+        }
+        let left = ads.nodes("shard/3", now + AD_LIFETIME / 2);
+        assert_eq!(left.len(), MAX_ADS_PER_TOPIC);
+        assert!(!left.contains(&b));
+
+        assert!(ads.nodes("shard/3", now + AD_LIFETIME * 2).is_empty());
+        assert!(ads.is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use aes::cipher::{BlockEncrypt, KeyInit, KeyIvInit, StreamCipher};
+use aes::Aes128;
+use hmac::{Hmac, Mac};
+use k256::ecdsa::signature::hazmat::{PrehashSigner, PrehashVerifier};
+use k256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::ProjectivePoint;
+use sha2::{Digest, Sha256};
+
+use crate::crypto::NodeId;
+
+type Aes128Ctr32 = ctr::Ctr32BE<Aes128>;
+
+const KEY_AGREEMENT_INFO: &[u8] = b"discovery v5 key agreement";
+const ID_PROOF_PREFIX: &[u8] = b"discovery v5 identity proof";
+pub const TAG_SIZE: usize = 16;
+
+/// Keys of one session; the handshake initiator writes with `initiator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionKeys {
+    pub initiator: [u8; 16],
+    pub recipient: [u8; 16],
+}
+
+/// secp256k1 ECDH as discv5 does it: the shared point, compressed.
+pub fn ecdh(public: &VerifyingKey, secret: &SigningKey) -> [u8; 33] {
+    let shared = ProjectivePoint::from(*public.as_affine()) * **secret.as_nonzero_scalar();
+    let point = shared.to_affine().to_encoded_point(true);
+    point.as_bytes().try_into().expect("compressed point")
+}
+
+/// HKDF-SHA256 over the shared secret, salted with the WHOAREYOU challenge.
+pub fn derive_keys(secret: &[u8], initiator: &NodeId, recipient: &NodeId, challenge: &[u8]) -> SessionKeys {
+    let prk = hmac(challenge, &[secret]);
+    // one expand block covers both keys
+    let okm = hmac(&prk, &[KEY_AGREEMENT_INFO, initiator, recipient, &[1]]);
+    SessionKeys {
+        initiator: okm[..16].try_into().unwrap(),
+        recipient: okm[16..].try_into().unwrap(),
+    }
+}
+
+fn id_proof_hash(challenge: &[u8], ephemeral: &[u8], recipient: &NodeId) -> [u8; 32] {
+    let mut h = Sha256::new();
+    h.update(ID_PROOF_PREFIX);
+    h.update(challenge);
+    h.update(ephemeral);
+    h.update(recipient);
+    h.finalize().into()
+}
+
+/// Proof that the handshake comes from the owner of `key`.
+pub fn id_sign(key: &SigningKey, challenge: &[u8], ephemeral: &[u8], recipient: &NodeId) -> [u8; 64] {
+    let sig: Signature = key
+        .sign_prehash(&id_proof_hash(challenge, ephemeral, recipient))
+        .expect("sign id proof");
+    sig.to_bytes().into()
+}
+
+pub fn id_verify(key: &VerifyingKey, challenge: &[u8], ephemeral: &[u8], recipient: &NodeId, sig: &[u8]) -> bool {
+    let Ok(sig) = Signature::from_slice(sig) else { return false };
+    key.verify_prehash(&id_proof_hash(challenge, ephemeral, recipient), &sig).is_ok()
+}
+
+/// AES-128-GCM with a 96-bit nonce; the tag is appended.
+pub fn encrypt(key: &[u8; 16], nonce: &[u8; 12], plaintext: &[u8], ad: &[u8]) -> Vec<u8> {
+    let mut out = plaintext.to_vec();
+    Aes128Ctr32::new(key.into(), &counter_block(nonce, 2).into()).apply_keystream(&mut out);
+    let tag = gcm_tag(key, nonce, ad, &out);
+    out.extend_from_slice(&tag);
+    out
+}
+
+pub fn decrypt(key: &[u8; 16], nonce: &[u8; 12], ciphertext: &[u8], ad: &[u8]) -> Option<Vec<u8>> {
+    let split = ciphertext.len().checked_sub(TAG_SIZE)?;
+    let (body, tag) = ciphertext.split_at(split);
+    // not constant time; a forged tag only ever costs the sender a WHOAREYOU
+    if gcm_tag(key, nonce, ad, body) != tag {
+        return None;
+    }
+    let mut out = body.to_vec();
+    Aes128Ctr32::new(key.into(), &counter_block(nonce, 2).into()).apply_keystream(&mut out);
+    Some(out)
+}
+
+fn counter_block(nonce: &[u8; 12], counter: u32) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[..12].copy_from_slice(nonce);
+    block[12..].copy_from_slice(&counter.to_be_bytes());
+    block
+}
+
+fn gcm_tag(key: &[u8; 16], nonce: &[u8; 12], ad: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+    let cipher = Aes128::new(key.into());
+    let mut h = [0u8; 16].into();
+    cipher.encrypt_block(&mut h);
+    let h = u128::from_be_bytes(h.into());
+
+    let mut x = 0u128;
+    for data in [ad, ciphertext] {
+        for chunk in data.chunks(16) {
+            let mut block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            x = gf_mul(x ^ u128::from_be_bytes(block), h);
+        }
+    }
+    let lengths = ((ad.len() as u128 * 8) << 64) | (ciphertext.len() as u128 * 8);
+    x = gf_mul(x ^ lengths, h);
+
+    let mut j0 = counter_block(nonce, 1).into();
+    cipher.encrypt_block(&mut j0);
+    (x ^ u128::from_be_bytes(j0.into())).to_be_bytes()
+}
+
+// multiplication in GCM's GF(2^128), bit 0 being the most significant
+fn gf_mul(x: u128, y: u128) -> u128 {
+    const R: u128 = 0xe1 << 120;
+    let (mut z, mut v) = (0u128, y);
+    for i in 0..128 {
+        if (x >> (127 - i)) & 1 == 1 {
+            z ^= v;
+        }
+        v = if v & 1 == 1 { (v >> 1) ^ R } else { v >> 1 };
+    }
+    z
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("hmac takes any key");
+    for p in parts {
+        mac.update(p);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex<const N: usize>(s: &str) -> [u8; N] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    // vectors from the discv5 wire specification
+    #[test]
+    fn matches_the_spec_vectors() {
+        let secret = SigningKey::from_slice(&hex::decode("fb757dc581730490a1d7a00deea65e9b1936924caaea8f44d476014856b68736").unwrap()).unwrap();
+        let public = VerifyingKey::from_sec1_bytes(&hex::decode("039961e4c2356d61bedb83052c115d311acb3a96f5777296dcf297351130266231").unwrap()).unwrap();
+        assert_eq!(
+            hex::encode(ecdh(&public, &secret)),
+            "033b11a2a1f214567e1537ce5e509ffd9b21373247f2a3ff6841f4976f53165e7e"
+        );
+
+        let dest = VerifyingKey::from_sec1_bytes(&hex::decode("0317931e6e0840220642f230037d285d122bc59063221ef3226b1f403ddc69ca91").unwrap()).unwrap();
+        let node_a: NodeId = unhex("aaaa8419e9f49d0083561b48287df592939a8d19947d8c0ef88f2a4856a69fbb");
+        let node_b: NodeId = unhex("bbbb9d047f0488c0b5a93c1c3f2d8bafc7c8ff337024a55434a0d0555de64db9");
+        let challenge = hex::decode("000000000000000000000000000000006469736376350001010102030405060708090a0b0c00180102030405060708090a0b0c0d0e0f100000000000000000").unwrap();
+        let keys = derive_keys(&ecdh(&dest, &secret), &node_a, &node_b, &challenge);
+        assert_eq!(hex::encode(keys.initiator), "dccc82d81bd610f4f76d3ebe97a40571");
+        assert_eq!(hex::encode(keys.recipient), "ac74bb8773749920b0d3a8881c173ec5");
+
+        let ephemeral = hex::decode("039961e4c2356d61bedb83052c115d311acb3a96f5777296dcf297351130266231").unwrap();
+        let sig = id_sign(&secret, &challenge, &ephemeral, &node_b);
+        assert_eq!(
+            hex::encode(sig),
+            "94852a1e2318c4e5e9d422c98eaf19d1d90d876b29cd06ca7cb7546d0fff7b484fe86c09a064fe72bdbef73ba8e9c34df0cd2b53e9d65528c2c7f336d5dfc6e6"
+        );
+        assert!(id_verify(secret.verifying_key(), &challenge, &ephemeral, &node_b, &sig));
+        assert!(!id_verify(secret.verifying_key(), &challenge, &ephemeral, &node_a, &sig));
+
+        let key = unhex("9f2d77db7004bf8a1a85107ac686990b");
+        let nonce = unhex("27b5af763c446acd2749fe8e");
+        let ad = hex::decode("93a7400fa0d6a694ebc24d5cf570f65d04215b6ac00757875e3f3a5f42107903").unwrap();
+        let sealed = encrypt(&key, &nonce, &[0x01, 0xc2, 0x01, 0x01], &ad);
+        assert_eq!(hex::encode(&sealed), "a5d12a2d94b8ccb3ba55558229867dc13bfa3648");
+        assert_eq!(decrypt(&key, &nonce, &sealed, &ad).unwrap(), [0x01, 0xc2, 0x01, 0x01]);
+        assert!(decrypt(&key, &nonce, &sealed, &ad[1..]).is_none());
+    }
+}
@@ -0,0 +1,156 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use rlp::{Rlp, RlpStream};
+
+/// discv5 messages: a type byte followed by an RLP list. Records travel as
+/// the raw RLP of each item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Ping {
+        req_id: Vec<u8>,
+        enr_seq: u64,
+    },
+    Pong {
+        req_id: Vec<u8>,
+        enr_seq: u64,
+        // where the ping came from, as seen by the ponger
+        ip: IpAddr,
+        port: u16,
+    },
+    FindNode {
+        req_id: Vec<u8>,
+        // log distances from the recipient; 0 asks for its own record
+        distances: Vec<u64>,
+    },
+    Nodes {
+        req_id: Vec<u8>,
+        // how many NODES messages answer the request
+        total: u64,
+        records: Vec<Vec<u8>>,
+    },
+    TalkReq {
+        req_id: Vec<u8>,
+        protocol: Vec<u8>,
+        request: Vec<u8>,
+    },
+    TalkResp {
+        req_id: Vec<u8>,
+        response: Vec<u8>,
+    },
+}
+
+impl Message {
+    pub fn req_id(&self) -> &[u8] {
+        match self {
+            Message::Ping { req_id, .. }
+            | Message::Pong { req_id, .. }
+            | Message::FindNode { req_id, .. }
+            | Message::Nodes { req_id, .. }
+            | Message::TalkReq { req_id, .. }
+            | Message::TalkResp { req_id, .. } => req_id,
+        }
+    }
+
+    pub fn is_request(&self) -> bool {
+        matches!(self, Message::Ping { .. } | Message::FindNode { .. } | Message::TalkReq { .. })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, body) = match self {
+            Message::Ping { req_id, enr_seq } => {
+                let mut s = RlpStream::new_list(2);
+                s.append(req_id).append(enr_seq);
+                (1, s)
+            }
+            Message::Pong {
+                req_id,
+                enr_seq,
+                ip,
+                port,
+            } => {
+                let ip = match ip {
+                    IpAddr::V4(v4) => v4.octets().to_vec(),
+                    IpAddr::V6(v6) => v6.octets().to_vec(),
+                };
+                let mut s = RlpStream::new_list(4);
+                s.append(req_id).append(enr_seq).append(&ip).append(port);
+                (2, s)
+            }
+            Message::FindNode { req_id, distances } => {
+                let mut s = RlpStream::new_list(2);
+                s.append(req_id).append_list(distances);
+                (3, s)
+            }
+            Message::Nodes { req_id, total, records } => {
+                let mut s = RlpStream::new_list(3);
+                s.append(req_id).append(total);
+                s.begin_list(records.len());
+                for r in records {
+                    s.append_raw(r, 1);
+                }
+                (4, s)
+            }
+            Message::TalkReq {
+                req_id,
+                protocol,
+                request,
+            } => {
+                let mut s = RlpStream::new_list(3);
+                s.append(req_id).append(protocol).append(request);
+                (5, s)
+            }
+            Message::TalkResp { req_id, response } => {
+                let mut s = RlpStream::new_list(2);
+                s.append(req_id).append(response);
+                (6, s)
+            }
+        };
+        [&[kind][..], &body.out()].concat()
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (&kind, body) = data.split_first()?;
+        let r = Rlp::new(body);
+        let bytes = |i: usize| r.val_at::<Vec<u8>>(i).ok();
+        let req_id = bytes(0).filter(|id| id.len() <= 8)?;
+        let msg = match kind {
+            1 => Message::Ping {
+                req_id,
+                enr_seq: r.val_at(1).ok()?,
+            },
+            2 => {
+                let ip = match bytes(2)?.len() {
+                    4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes(2)?).ok()?)),
+                    16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes(2)?).ok()?)),
+                    _ => return None,
+                };
+                Message::Pong {
+                    req_id,
+                    enr_seq: r.val_at(1).ok()?,
+                    ip,
+                    port: r.val_at(3).ok()?,
+                }
+            }
+            3 => Message::FindNode {
+                req_id,
+                distances: r.list_at(1).ok()?,
+            },
+            4 => Message::Nodes {
+                req_id,
+                total: r.val_at(1).ok()?,
+                records: r.at(2).ok()?.iter().map(|item| item.as_raw().to_vec()).collect(),
+            },
+            5 => Message::TalkReq {
+                req_id,
+                protocol: bytes(1)?,
+                request: bytes(2)?,
+            },
+            6 => Message::TalkResp {
+                req_id,
+                response: bytes(1)?,
+            },
+            _ => return None,
+        };
+        Some(msg)
+    }
+}
@@ -0,0 +1,47 @@
+pub mod crypto;
+pub mod message;
+pub mod packet;
+pub mod session;
+pub mod transport;
+
+use std::net::SocketAddr;
+
+use k256::ecdsa::VerifyingKey;
+
+use crate::config::Families;
+use crate::crypto::{Keypair, NodeId};
+use crate::discovery::enr::Enr;
+use crate::enr::record::{EnrRecord, MAX_RECORD_SIZE};
+
+/// Where and under which key a node takes discv5 packets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    pub id: NodeId,
+    pub addr: SocketAddr,
+    pub key: VerifyingKey,
+}
+
+impl Contact {
+    /// None unless the record names a UDP port and a key matching its id.
+    pub fn from_enr(enr: &Enr, families: Families) -> Option<Self> {
+        let mut addr = enr.dial_addr(families).ok()?;
+        addr.set_port(enr.udp?);
+        Some(Self {
+            id: enr.id_bytes()?,
+            addr,
+            key: enr.public_key()?,
+        })
+    }
+}
+
+/// Our own record, signed in EIP-778 form; None if it outgrows the size
+/// limit, in which case we send no record at all.
+pub fn signed_record(enr: &Enr, key: &Keypair) -> Option<Vec<u8>> {
+    let signed = enr.to_record(key).encode();
+    (signed.len() <= MAX_RECORD_SIZE).then_some(signed)
+}
+
+/// A record another node signed, checked against its key.
+pub fn decode_record(raw: &[u8]) -> Option<Enr> {
+    Enr::from_record(&EnrRecord::decode(raw).ok()?).ok()
+}
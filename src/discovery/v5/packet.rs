@@ -0,0 +1,237 @@
+use aes::cipher::{KeyIvInit, StreamCipher};
+use thiserror::Error;
+
+use crate::crypto::NodeId;
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+const PROTOCOL_ID: &[u8; 6] = b"discv5";
+const VERSION: [u8; 2] = [0, 1];
+const IV_SIZE: usize = 16;
+const STATIC_HEADER_SIZE: usize = 23;
+pub const MIN_PACKET_SIZE: usize = 63;
+pub const MAX_PACKET_SIZE: usize = 1280;
+
+const FLAG_MESSAGE: u8 = 0;
+const FLAG_WHOAREYOU: u8 = 1;
+const FLAG_HANDSHAKE: u8 = 2;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PacketError {
+    #[error("packet of {0} bytes is outside the allowed size")]
+    Size(usize),
+
+    #[error("not a discv5 packet for us")]
+    NotOurs,
+
+    #[error("malformed packet header")]
+    Header,
+}
+
+/// The flag-specific part of the header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthData {
+    Message {
+        src: NodeId,
+    },
+    WhoAreYou {
+        id_nonce: [u8; 16],
+        enr_seq: u64,
+    },
+    Handshake {
+        src: NodeId,
+        id_signature: Vec<u8>,
+        ephemeral_key: Vec<u8>,
+        // the sender's record, if the challenge showed ours was stale
+        record: Option<Vec<u8>>,
+    },
+}
+
+/// A packet before masking: header fields and the (encrypted) message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub iv: [u8; 16],
+    pub nonce: [u8; 12],
+    pub auth: AuthData,
+    pub message: Vec<u8>,
+}
+
+impl AuthData {
+    fn flag(&self) -> u8 {
+        match self {
+            AuthData::Message { .. } => FLAG_MESSAGE,
+            AuthData::WhoAreYou { .. } => FLAG_WHOAREYOU,
+            AuthData::Handshake { .. } => FLAG_HANDSHAKE,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            AuthData::Message { src } => src.to_vec(),
+            AuthData::WhoAreYou { id_nonce, enr_seq } => [&id_nonce[..], &enr_seq.to_be_bytes()].concat(),
+            AuthData::Handshake {
+                src,
+                id_signature,
+                ephemeral_key,
+                record,
+            } => {
+                let mut out = src.to_vec();
+                out.push(id_signature.len() as u8);
+                out.push(ephemeral_key.len() as u8);
+                out.extend_from_slice(id_signature);
+                out.extend_from_slice(ephemeral_key);
+                out.extend_from_slice(record.as_deref().unwrap_or_default());
+                out
+            }
+        }
+    }
+
+    fn decode(flag: u8, data: &[u8]) -> Option<Self> {
+        match flag {
+            FLAG_MESSAGE => Some(AuthData::Message {
+                src: data.try_into().ok()?,
+            }),
+            FLAG_WHOAREYOU if data.len() == 24 => Some(AuthData::WhoAreYou {
+                id_nonce: data[..16].try_into().ok()?,
+                enr_seq: u64::from_be_bytes(data[16..].try_into().ok()?),
+            }),
+            FLAG_HANDSHAKE => {
+                let src = data.get(..32)?.try_into().ok()?;
+                let (sig_size, key_size) = (*data.get(32)? as usize, *data.get(33)? as usize);
+                let id_signature = data.get(34..34 + sig_size)?.to_vec();
+                let rest = &data[34 + sig_size..];
+                let ephemeral_key = rest.get(..key_size)?.to_vec();
+                let record = &rest[key_size..];
+                Some(AuthData::Handshake {
+                    src,
+                    id_signature,
+                    ephemeral_key,
+                    record: (!record.is_empty()).then(|| record.to_vec()),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Packet {
+    /// The unmasked header: protocol id, version, flag, nonce, authdata.
+    pub fn header(&self) -> Vec<u8> {
+        let auth = self.auth.encode();
+        let mut out = Vec::with_capacity(STATIC_HEADER_SIZE + auth.len());
+        out.extend_from_slice(PROTOCOL_ID);
+        out.extend_from_slice(&VERSION);
+        out.push(self.auth.flag());
+        out.extend_from_slice(&self.nonce);
+        out.extend_from_slice(&(auth.len() as u16).to_be_bytes());
+        out.extend_from_slice(&auth);
+        out
+    }
+
+    /// masking-iv || header: what message encryption authenticates, and for
+    /// a WHOAREYOU the challenge data both session keys are salted with.
+    pub fn authenticated_data(&self) -> Vec<u8> {
+        [&self.iv[..], &self.header()].concat()
+    }
+
+    /// Wire bytes, with the header masked under the recipient's id.
+    pub fn encode(&self, dest: &NodeId) -> Vec<u8> {
+        let mut header = self.header();
+        mask(dest, &self.iv, &mut header);
+        [&self.iv[..], &header, &self.message].concat()
+    }
+
+    pub fn decode(local: &NodeId, data: &[u8]) -> Result<Self, PacketError> {
+        if !(MIN_PACKET_SIZE..=MAX_PACKET_SIZE).contains(&data.len()) {
+            return Err(PacketError::Size(data.len()));
+        }
+        let iv: [u8; 16] = data[..IV_SIZE].try_into().unwrap();
+        let mut header = data[IV_SIZE..IV_SIZE + STATIC_HEADER_SIZE].to_vec();
+        let mut cipher = Aes128Ctr::new(local[..16].into(), &iv.into());
+        cipher.apply_keystream(&mut header);
+        if &header[..6] != PROTOCOL_ID || header[6..8] != VERSION {
+            return Err(PacketError::NotOurs);
+        }
+
+        let flag = header[8];
+        let nonce = header[9..21].try_into().unwrap();
+        let auth_size = u16::from_be_bytes([header[21], header[22]]) as usize;
+        let auth_end = IV_SIZE + STATIC_HEADER_SIZE + auth_size;
+        let mut auth = data.get(IV_SIZE + STATIC_HEADER_SIZE..auth_end).ok_or(PacketError::Header)?.to_vec();
+        // the same keystream continues over the authdata
+        cipher.apply_keystream(&mut auth);
+
+        Ok(Self {
+            iv,
+            nonce,
+            auth: AuthData::decode(flag, &auth).ok_or(PacketError::Header)?,
+            message: data[auth_end..].to_vec(),
+        })
+    }
+}
+
+fn mask(dest: &NodeId, iv: &[u8; 16], header: &mut [u8]) {
+    Aes128Ctr::new(dest[..16].into(), iv.into()).apply_keystream(header);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::v5::crypto;
+
+    fn id(s: &str) -> NodeId {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    // vectors from the discv5 wire specification
+    #[test]
+    fn encodes_the_spec_packets() {
+        let a = id("aaaa8419e9f49d0083561b48287df592939a8d19947d8c0ef88f2a4856a69fbb");
+        let b = id("bbbb9d047f0488c0b5a93c1c3f2d8bafc7c8ff337024a55434a0d0555de64db9");
+
+        let mut ping = Packet {
+            iv: [0; 16],
+            nonce: [0xff; 12],
+            auth: AuthData::Message { src: a },
+            message: vec![],
+        };
+        // PING req-id 0x00000001, enr-seq 2, under an all-zero key
+        let pt = hex::decode("01c6840000000102").unwrap();
+        ping.message = crypto::encrypt(&[0; 16], &ping.nonce, &pt, &ping.authenticated_data());
+        let wire = ping.encode(&b);
+        // iv and masked header
+        assert_eq!(
+            hex::encode(&wire[..71]),
+            "00000000000000000000000000000000088b3d4342774649325f313964a39e55\
+             ea96c005ad52be8c7560413a7008f16c9e6d2f43bbea8814a546b7409ce783d3\
+             4c4f53245d08da"
+        );
+        let got = Packet::decode(&b, &wire).unwrap();
+        assert_eq!(got, ping);
+        let ad = got.authenticated_data();
+        assert_eq!(crypto::decrypt(&[0; 16], &got.nonce, &got.message, &ad).unwrap(), pt);
+        assert_eq!(Packet::decode(&a, &wire), Err(PacketError::NotOurs));
+
+        let whoareyou = Packet {
+            iv: [0; 16],
+            nonce: hex::decode("0102030405060708090a0b0c").unwrap().try_into().unwrap(),
+            auth: AuthData::WhoAreYou {
+                id_nonce: hex::decode("0102030405060708090a0b0c0d0e0f10").unwrap().try_into().unwrap(),
+                enr_seq: 0,
+            },
+            message: vec![],
+        };
+        assert_eq!(
+            hex::encode(whoareyou.authenticated_data()),
+            "000000000000000000000000000000006469736376350001010102030405060708090a0b0c\
+             00180102030405060708090a0b0c0d0e0f100000000000000000"
+        );
+        let wire = whoareyou.encode(&b);
+        assert_eq!(
+            hex::encode(&wire),
+            "00000000000000000000000000000000088b3d434277464933a1ccc59f5967ad\
+             1d6035f15e528627dde75cd68292f9e6c27d6b66c8100a873fcbaed4e16b8d"
+        );
+        assert_eq!(Packet::decode(&b, &wire).unwrap(), whoareyou);
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use k256::ecdsa::{SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use thiserror::Error;
+
+use crate::crypto::{node_id_from_pubkey, Keypair, NodeId};
+use crate::discovery::enr::Enr;
+use crate::discovery::v5::crypto::{self, SessionKeys};
+use crate::discovery::v5::message::Message;
+use crate::discovery::v5::packet::{AuthData, Packet, PacketError};
+use crate::discovery::v5::{decode_record, signed_record, Contact};
+
+// how long a sent challenge, or a message waiting for one, stays answerable
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// established sessions we keep before forgetting one to make room
+const MAX_SESSIONS: usize = 1024;
+// unanswered WHOAREYOUs; past this, unknown senders go unchallenged
+const MAX_CHALLENGES: usize = 1024;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SessionError {
+    #[error(transparent)]
+    Packet(#[from] PacketError),
+
+    #[error("WHOAREYOU for no packet we sent")]
+    UnexpectedChallenge,
+
+    #[error("handshake without a challenge from us")]
+    UnexpectedHandshake,
+
+    #[error("handshake does not prove the sender's identity")]
+    BadHandshake,
+
+    #[error("message does not decrypt or parse")]
+    BadMessage,
+
+    #[error("too many handshakes in progress")]
+    Busy,
+}
+
+/// What an incoming packet amounts to.
+#[derive(Debug)]
+pub enum Received {
+    /// A message from an authenticated node, with the record its handshake
+    /// carried, if any.
+    Message { src: NodeId, message: Message, record: Option<Box<Enr>> },
+    /// Send this back to the sender: a WHOAREYOU or our handshake.
+    Reply(Vec<u8>),
+}
+
+struct Session {
+    keys: SessionKeys,
+    initiator: bool,
+}
+
+impl Session {
+    fn write_key(&self) -> &[u8; 16] {
+        if self.initiator { &self.keys.initiator } else { &self.keys.recipient }
+    }
+
+    fn read_key(&self) -> &[u8; 16] {
+        if self.initiator { &self.keys.recipient } else { &self.keys.initiator }
+    }
+}
+
+// a message sent without a session, to go out again in the handshake
+struct Pending {
+    contact: Contact,
+    message: Vec<u8>,
+    at: Instant,
+}
+
+// the challenge-data of a WHOAREYOU we sent
+struct Challenge {
+    data: Vec<u8>,
+    at: Instant,
+}
+
+/// discv5 sessions: seals outgoing messages and opens incoming packets,
+/// running the WHOAREYOU handshake when either side lacks keys. Does no
+/// I/O; callers put the returned bytes on the wire.
+pub struct Sessions {
+    key: Keypair,
+    local_id: NodeId,
+    sessions: HashMap<(NodeId, SocketAddr), Session>,
+    pending: HashMap<[u8; 12], Pending>, // by nonce of the unreadable packet
+    challenges: HashMap<(NodeId, SocketAddr), Challenge>,
+    known: HashMap<NodeId, (VerifyingKey, u64)>, // key and record seq of nodes we talked to
+}
+
+impl Sessions {
+    pub fn new(key: Keypair) -> Self {
+        Self {
+            local_id: node_id_from_pubkey(&key.verifying_key),
+            key,
+            sessions: HashMap::new(),
+            pending: HashMap::new(),
+            challenges: HashMap::new(),
+            known: HashMap::new(),
+        }
+    }
+
+    /// Seal `message` for `to`. Without a session the packet is random
+    /// bytes that draw a WHOAREYOU; `receive` then answers with the
+    /// handshake carrying the message.
+    pub fn send(&mut self, to: &Contact, message: &Message, now: Instant) -> Vec<u8> {
+        self.expire(now);
+        self.known.entry(to.id).or_insert((to.key, 0));
+        let mut packet = self.packet(AuthData::Message { src: self.local_id });
+        match self.sessions.get(&(to.id, to.addr)) {
+            Some(session) => {
+                packet.message = crypto::encrypt(session.write_key(), &packet.nonce, &message.encode(), &packet.authenticated_data());
+            }
+            None => {
+                packet.message = random_bytes(20);
+                self.pending.insert(
+                    packet.nonce,
+                    Pending {
+                        contact: to.clone(),
+                        message: message.encode(),
+                        at: now,
+                    },
+                );
+            }
+        }
+        packet.encode(&to.id)
+    }
+
+    /// Seal a message for a node we hold a session with, e.g. a response.
+    pub fn reply(&mut self, to: &NodeId, addr: SocketAddr, message: &Message) -> Option<Vec<u8>> {
+        let session = self.sessions.get(&(*to, addr))?;
+        let mut packet = self.packet(AuthData::Message { src: self.local_id });
+        packet.message = crypto::encrypt(session.write_key(), &packet.nonce, &message.encode(), &packet.authenticated_data());
+        Some(packet.encode(to))
+    }
+
+    pub fn receive(&mut self, from: SocketAddr, data: &[u8], local: &Enr, now: Instant) -> Result<Received, SessionError> {
+        self.expire(now);
+        let packet = Packet::decode(&self.local_id, data)?;
+        match packet.auth.clone() {
+            AuthData::Message { src } => {
+                let opened = self.sessions.get(&(src, from)).and_then(|s| {
+                    crypto::decrypt(s.read_key(), &packet.nonce, &packet.message, &packet.authenticated_data())
+                });
+                match opened.and_then(|m| Message::decode(&m)) {
+                    Some(message) => Ok(Received::Message { src, message, record: None }),
+                    // no session, or a stale one: make the sender prove itself
+                    None => self
+                        .challenge(src, from, packet.nonce, now)
+                        .map(Received::Reply)
+                        .ok_or(SessionError::Busy),
+                }
+            }
+            AuthData::WhoAreYou { enr_seq, .. } => {
+                let pending = self.pending.remove(&packet.nonce).ok_or(SessionError::UnexpectedChallenge)?;
+                if pending.contact.addr != from {
+                    return Err(SessionError::UnexpectedChallenge);
+                }
+                Ok(Received::Reply(self.handshake(pending, &packet, enr_seq, local)))
+            }
+            AuthData::Handshake {
+                src,
+                id_signature,
+                ephemeral_key,
+                record,
+            } => {
+                let challenge = self
+                    .challenges
+                    .remove(&(src, from))
+                    .ok_or(SessionError::UnexpectedHandshake)?;
+                let record = match record {
+                    Some(raw) => match decode_record(&raw) {
+                        Some(enr) if enr.id_bytes() == Some(src) => Some(enr),
+                        _ => return Err(SessionError::BadHandshake),
+                    },
+                    None => None,
+                };
+                let key = match &record {
+                    Some(enr) => enr.public_key(),
+                    None => self.known.get(&src).map(|k| k.0),
+                }
+                .ok_or(SessionError::BadHandshake)?;
+                if !crypto::id_verify(&key, &challenge.data, &ephemeral_key, &self.local_id, &id_signature) {
+                    return Err(SessionError::BadHandshake);
+                }
+                let ephemeral = VerifyingKey::from_sec1_bytes(&ephemeral_key).map_err(|_| SessionError::BadHandshake)?;
+                let keys = crypto::derive_keys(&crypto::ecdh(&ephemeral, &self.key.signing_key), &src, &self.local_id, &challenge.data);
+                let message = crypto::decrypt(&keys.initiator, &packet.nonce, &packet.message, &packet.authenticated_data())
+                    .and_then(|m| Message::decode(&m))
+                    .ok_or(SessionError::BadMessage)?;
+
+                self.establish(src, from, Session { keys, initiator: false });
+                if let Some(enr) = &record {
+                    self.known.insert(src, (key, enr.seq));
+                }
+                Ok(Received::Message { src, message, record: record.map(Box::new) })
+            }
+        }
+    }
+
+    fn challenge(&mut self, src: NodeId, from: SocketAddr, nonce: [u8; 12], now: Instant) -> Option<Vec<u8>> {
+        if self.challenges.len() >= MAX_CHALLENGES && !self.challenges.contains_key(&(src, from)) {
+            return None;
+        }
+        let mut id_nonce = [0u8; 16];
+        OsRng.fill_bytes(&mut id_nonce);
+        let packet = Packet {
+            iv: random_iv(),
+            nonce,
+            auth: AuthData::WhoAreYou {
+                id_nonce,
+                // zero asks for the record: we don't know the node yet
+                enr_seq: self.known.get(&src).map_or(0, |k| k.1),
+            },
+            message: vec![],
+        };
+        self.challenges.insert(
+            (src, from),
+            Challenge {
+                data: packet.authenticated_data(),
+                at: now,
+            },
+        );
+        Some(packet.encode(&src))
+    }
+
+    fn handshake(&mut self, pending: Pending, whoareyou: &Packet, enr_seq: u64, local: &Enr) -> Vec<u8> {
+        let to = pending.contact;
+        let challenge = whoareyou.authenticated_data();
+        let ephemeral = SigningKey::random(&mut OsRng);
+        let ephemeral_key = ephemeral.verifying_key().to_sec1_bytes().to_vec();
+        let keys = crypto::derive_keys(&crypto::ecdh(&to.key, &ephemeral), &self.local_id, &to.id, &challenge);
+
+        let mut packet = self.packet(AuthData::Handshake {
+            src: self.local_id,
+            id_signature: crypto::id_sign(&self.key.signing_key, &challenge, &ephemeral_key, &to.id).to_vec(),
+            ephemeral_key,
+            record: (enr_seq < local.seq).then(|| signed_record(local, &self.key)).flatten(),
+        });
+        packet.message = crypto::encrypt(&keys.initiator, &packet.nonce, &pending.message, &packet.authenticated_data());
+        self.establish(to.id, to.addr, Session { keys, initiator: true });
+        packet.encode(&to.id)
+    }
+
+    fn establish(&mut self, id: NodeId, addr: SocketAddr, session: Session) {
+        if self.sessions.len() >= MAX_SESSIONS
+            && !self.sessions.contains_key(&(id, addr))
+            && let Some(k) = self.sessions.keys().next().copied()
+        {
+            self.sessions.remove(&k);
+        }
+        self.sessions.insert((id, addr), session);
+    }
+
+    fn packet(&self, auth: AuthData) -> Packet {
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        Packet {
+            iv: random_iv(),
+            nonce,
+            auth,
+            message: vec![],
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.pending.retain(|_, p| now.duration_since(p.at) < HANDSHAKE_TIMEOUT);
+        self.challenges.retain(|_, c| now.duration_since(c.at) < HANDSHAKE_TIMEOUT);
+    }
+}
+
+fn random_iv() -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    OsRng.fill_bytes(&mut iv);
+    iv
+}
+
+fn random_bytes(n: usize) -> Vec<u8> {
+    let mut out = vec![0u8; n];
+    OsRng.fill_bytes(&mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Families;
+    use crate::crypto::generate_keypair;
+
+    fn node(port: u16) -> (Sessions, Enr, Contact) {
+        let key = generate_keypair().unwrap();
+        let mut enr = Enr::new_local(&key, &["127.0.0.1".parse().unwrap()], port);
+        enr.udp = Some(port);
+        let contact = Contact::from_enr(&enr, Families::ANY).unwrap();
+        (Sessions::new(key), enr, contact)
+    }
+
+    fn expect_reply(r: Result<Received, SessionError>) -> Vec<u8> {
+        match r.unwrap() {
+            Received::Reply(bytes) => bytes,
+            other => panic!("expected a reply, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn handshake_establishes_a_session_both_ways() {
+        let now = Instant::now();
+        let (mut a, a_enr, a_contact) = node(9001);
+        let (mut b, b_enr, b_contact) = node(9002);
+        let ping = Message::Ping {
+            req_id: vec![1],
+            enr_seq: a_enr.seq,
+        };
+
+        // a has no session: b can't read the packet and challenges it
+        let first = a.send(&b_contact, &ping, now);
+        let whoareyou = expect_reply(b.receive(a_contact.addr, &first, &b_enr, now));
+        let handshake = expect_reply(a.receive(b_contact.addr, &whoareyou, &a_enr, now));
+        match b.receive(a_contact.addr, &handshake, &b_enr, now).unwrap() {
+            Received::Message { src, message, record } => {
+                assert_eq!(src, a_contact.id);
+                assert_eq!(message, ping);
+                // b asked with seq 0, so a's signed record came along
+                let record = *record.unwrap();
+                assert_eq!(Enr { signed: None, ..record.clone() }, a_enr);
+                assert_eq!(decode_record(record.signed.as_ref().unwrap()), Some(record));
+            }
+            other => panic!("expected the ping, got {other:?}"),
+        }
+
+        // from here on both sides seal with the session keys
+        let pong = Message::Pong {
+            req_id: vec![1],
+            enr_seq: b_enr.seq,
+            ip: a_contact.addr.ip(),
+            port: a_contact.addr.port(),
+        };
+        let sealed = b.reply(&a_contact.id, a_contact.addr, &pong).unwrap();
+        match a.receive(b_contact.addr, &sealed, &a_enr, now).unwrap() {
+            Received::Message { message, record: None, .. } => assert_eq!(message, pong),
+            other => panic!("expected the pong, got {other:?}"),
+        }
+        let again = a.send(&b_contact, &ping, now);
+        assert!(matches!(b.receive(a_contact.addr, &again, &b_enr, now), Ok(Received::Message { .. })));
+
+        // a handshake nobody asked for is refused
+        assert_eq!(
+            b.receive(a_contact.addr, &handshake, &b_enr, now).unwrap_err(),
+            SessionError::UnexpectedHandshake
+        );
+    }
+
+    #[test]
+    fn unanswered_challenges_are_capped() {
+        let now = Instant::now();
+        let (mut a, _, a_contact) = node(9001);
+        let (mut b, b_enr, b_contact) = node(9002);
+        let ping = Message::Ping { req_id: vec![1], enr_seq: 1 };
+
+        // the same node from ever new ports, never finishing a handshake
+        for port in 0..MAX_CHALLENGES as u16 {
+            let from = SocketAddr::new(a_contact.addr.ip(), 10000 + port);
+            expect_reply(b.receive(from, &a.send(&b_contact, &ping, now), &b_enr, now));
+        }
+        let from = SocketAddr::new(a_contact.addr.ip(), 20000);
+        let flood = a.send(&b_contact, &ping, now);
+        assert_eq!(b.receive(from, &flood, &b_enr, now).unwrap_err(), SessionError::Busy);
+
+        // they lapse, and new senders get challenged again
+        let later = now + HANDSHAKE_TIMEOUT;
+        expect_reply(b.receive(from, &a.send(&b_contact, &ping, later), &b_enr, later));
+    }
+}
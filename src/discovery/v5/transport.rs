@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+use crate::crypto::{Keypair, NodeId};
+use crate::discovery::enr::Enr;
+use crate::discovery::v5::message::Message;
+use crate::discovery::v5::packet::{PacketError, MAX_PACKET_SIZE};
+use crate::discovery::v5::session::{Received, SessionError, Sessions};
+use crate::discovery::v5::{decode_record, Contact};
+
+// how long a request waits for its responses, handshake included
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+// records per NODES message stay under this, leaving room for header and tag
+const MAX_RECORDS_SIZE: usize = 1000;
+
+/// Answers the requests other nodes send us.
+pub trait Handler: Send + Sync + 'static {
+    fn local(&self) -> Enr;
+
+    /// Responses to `request` from `src`; `record` is the sender's record
+    /// if its handshake carried one.
+    fn handle(&self, src: &NodeId, from: SocketAddr, record: Option<Enr>, request: Message) -> Vec<Message>;
+}
+
+// request id -> who must answer it, and where the answers go
+type Calls = HashMap<Vec<u8>, (NodeId, mpsc::UnboundedSender<Message>)>;
+
+/// discv5 over a UDP socket: requests to other nodes, with the handshake
+/// done on the way, and a task answering theirs through a `Handler`.
+#[derive(Clone)]
+pub struct Discv5 {
+    socket: Arc<UdpSocket>,
+    sessions: Arc<Mutex<Sessions>>,
+    calls: Arc<Mutex<Calls>>,
+}
+
+impl Discv5 {
+    pub fn start(socket: UdpSocket, key: Keypair, handler: Arc<dyn Handler>) -> Self {
+        let v5 = Self {
+            socket: Arc::new(socket),
+            sessions: Arc::new(Mutex::new(Sessions::new(key))),
+            calls: Arc::new(Mutex::new(HashMap::new())),
+        };
+        tokio::spawn(v5.clone().receive_loop(handler));
+        v5
+    }
+
+    /// The record seq `to` answers with, and where it sees us; None if it
+    /// does not answer.
+    pub async fn ping(&self, to: &Contact, enr_seq: u64) -> Option<(u64, SocketAddr)> {
+        match self.call(to, |req_id| Message::Ping { req_id, enr_seq }).await.pop()? {
+            Message::Pong { enr_seq, ip, port, .. } => Some((enr_seq, SocketAddr::new(ip, port))),
+            _ => None,
+        }
+    }
+
+    /// Records `to` holds at these log distances from itself; 0 asks for
+    /// its own.
+    pub async fn find_node(&self, to: &Contact, distances: Vec<u64>) -> Vec<Enr> {
+        self.call(to, |req_id| Message::FindNode { req_id, distances })
+            .await
+            .into_iter()
+            .flat_map(|m| match m {
+                Message::Nodes { records, .. } => records,
+                _ => vec![],
+            })
+            .filter_map(|r| decode_record(&r))
+            .collect()
+    }
+
+    // every response to the request, or what arrived before the timeout
+    async fn call(&self, to: &Contact, request: impl FnOnce(Vec<u8>) -> Message) -> Vec<Message> {
+        let req_id = rand::random::<[u8; 8]>().to_vec();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.calls.lock().unwrap().insert(req_id.clone(), (to.id, tx));
+        let packet = self.sessions.lock().unwrap().send(to, &request(req_id.clone()), Instant::now());
+
+        let mut responses = vec![];
+        if self.socket.send_to(&packet, to.addr).await.is_ok() {
+            let _ = tokio::time::timeout(REQUEST_TIMEOUT, async {
+                while let Some(m) = rx.recv().await {
+                    let total = match &m {
+                        Message::Nodes { total, .. } => *total as usize,
+                        _ => 1,
+                    };
+                    responses.push(m);
+                    if responses.len() >= total {
+                        break;
+                    }
+                }
+            })
+            .await;
+        }
+        self.calls.lock().unwrap().remove(&req_id);
+        responses
+    }
+
+    async fn receive_loop(self, handler: Arc<dyn Handler>) {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        loop {
+            let Ok((n, from)) = self.socket.recv_from(&mut buf).await else { continue };
+            let received = self
+                .sessions
+                .lock()
+                .unwrap()
+                .receive(from, &buf[..n], &handler.local(), Instant::now());
+            match received {
+                Ok(Received::Reply(packet)) => {
+                    let _ = self.socket.send_to(&packet, from).await;
+                }
+                Ok(Received::Message { src, message, record }) if message.is_request() => {
+                    for response in handler.handle(&src, from, record.map(|r| *r), message) {
+                        let packet = self.sessions.lock().unwrap().reply(&src, from, &response);
+                        if let Some(packet) = packet {
+                            let _ = self.socket.send_to(&packet, from).await;
+                        }
+                    }
+                }
+                Ok(Received::Message { src, message, .. }) => {
+                    let calls = self.calls.lock().unwrap();
+                    // a response only counts from the node we asked
+                    if let Some((id, tx)) = calls.get(message.req_id())
+                        && *id == src
+                    {
+                        let _ = tx.send(message);
+                    }
+                }
+                // stray traffic on the port, or more handshakes than we take
+                Err(SessionError::Packet(PacketError::NotOurs | PacketError::Size(_)) | SessionError::Busy) => {}
+                Err(e) => println!("[DISC] discv5 packet from {from}: {e}"),
+            }
+        }
+    }
+}
+
+/// NODES responses carrying `records`, split so each fits a packet.
+pub fn nodes_response(req_id: &[u8], records: Vec<Vec<u8>>) -> Vec<Message> {
+    let mut chunks: Vec<Vec<Vec<u8>>> = vec![vec![]];
+    let mut size = 0;
+    for record in records {
+        if chunks.last().is_some_and(|c| !c.is_empty()) && size + record.len() > MAX_RECORDS_SIZE {
+            chunks.push(vec![]);
+            size = 0;
+        }
+        size += record.len();
+        chunks.last_mut().expect("one chunk").push(record);
+    }
+    let total = chunks.len() as u64;
+    chunks
+        .into_iter()
+        .map(|records| Message::Nodes {
+            req_id: req_id.to_vec(),
+            total,
+            records,
+        })
+        .collect()
+}
@@ -1,11 +1,18 @@
-use super::record::EnrRecord;
+use super::record::{content_hash, EnrRecord};
 use crate::crypto::{sign_message, Keypair};
-use k256::ecdsa::Signature;
 
-pub struct EnrBuidler {
-    seq: u64;
-    pairs: Vec<(Vec<u8>, Vec<u8>)>,
+#[derive(Debug)]
+pub struct EnrBuilder {
+    seq: u64,
+    pairs: Vec<(Vec<u8>, Vec<u8>)>, // value is the RLP item
 }
+
+impl Default for EnrBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl EnrBuilder {
     pub fn new() -> Self {
         Self {
@@ -19,25 +26,32 @@ impl EnrBuilder {
         self
     }
 
-    pub fn add(mut self,key: &[u8], value: &[u8]) -? Self {
-        self.pairs.push((key.to_vec(), value.to_vec()));
+    /// Set `key` to the byte string `value`.
+    pub fn add(self, key: &[u8], value: &[u8]) -> Self {
+        self.add_raw(key, &rlp::encode(&value))
     }
 
+    /// Set `key` to an already encoded RLP item, e.g. a list or integer.
+    pub fn add_raw(mut self, key: &[u8], item: &[u8]) -> Self {
+        self.pairs.retain(|(k, _)| k != key);
+        self.pairs.push((key.to_vec(), item.to_vec()));
+        self
+    }
+
+    /// Sign the record with the "v4" scheme; the id and public key are
+    /// added here.
     pub fn build(self, keypair: &Keypair) -> EnrRecord {
-        let mut stream = rlp::RlpStream::new_list(1+self.pairs.len() * 2);
-        stream.append(&self.seq);
-        for (k,v) in &self.pairs {
-            stream.append(k);
-            stream.append(v);
-        }
+        let mut builder = self
+            .add(b"id", b"v4")
+            .add(b"secp256k1", &keypair.verifying_key.to_sec1_bytes());
+        builder.pairs.sort();
 
-        let hash = crate::crypto::keccak256(&stream.out());
-        let signature = sign_message(&keypair.signoing_key, &hash)
-        .expect("ENR signiong failed");
+        let hash = content_hash(builder.seq, &builder.pairs);
+        let signature = sign_message(&keypair.signing_key, &hash).expect("ENR signing failed");
         EnrRecord {
             signature,
-            seq: self.seq,
-            pairs: self.pairs,
+            seq: builder.seq,
+            pairs: builder.pairs,
         }
     }
-}
\ No newline at end of file
+}
@@ -10,4 +10,25 @@ pub enum EnrError {
 
     #[error("missing required field: {0}")]
     MissingField(&'static str),
-}
\ No newline at end of file
+
+    #[error("record of {0} bytes is over the 300 byte limit")]
+    TooLarge(usize),
+
+    #[error("record keys are not sorted and unique")]
+    UnsortedKeys,
+
+    #[error("unknown identity scheme")]
+    UnknownScheme,
+
+    #[error("node record must start with \"enr:\"")]
+    MissingPrefix,
+
+    #[error("node record is not base64url: {0}")]
+    Base64(#[from] base64::DecodeError),
+}
+
+impl From<rlp::DecoderError> for EnrError {
+    fn from(_: rlp::DecoderError) -> Self {
+        EnrError::RlpError
+    }
+}
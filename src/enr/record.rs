@@ -1,36 +1,147 @@
-use crate::crypto::{keccak256, sign_message, verify_signature};
-use crate::crypto::{Keypair};
-use k256::ecdsa::Signature;
-use rlp::{Encodable, RlpStream};
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use k256::ecdsa::{Signature, VerifyingKey};
+use rlp::{Rlp, RlpStream};
+
+use super::error::EnrError;
+use crate::crypto::{keccak256, node_id_from_pubkey, verify_signature, NodeId};
+
+/// EIP-778 caps the encoded record, signature included.
+pub const MAX_RECORD_SIZE: usize = 300;
+
+/// An EIP-778 node record as geth and discv5 exchange it: a signature over
+/// the sequence number and the key/value pairs, keys sorted. Values are
+/// kept as their RLP items, since some (like "eth") are lists.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EnrRecord {
     pub signature: Signature,
     pub seq: u64,
     pub pairs: Vec<(Vec<u8>, Vec<u8>)>,
 }
 
+// keccak256 of rlp([seq, k, v, ...]), what the "v4" scheme signs
+pub(crate) fn content_hash(seq: u64, pairs: &[(Vec<u8>, Vec<u8>)]) -> [u8; 32] {
+    let mut stream = RlpStream::new_list(1 + pairs.len() * 2);
+    stream.append(&seq);
+    for (k, v) in pairs {
+        stream.append(k);
+        stream.append_raw(v, 1);
+    }
+    keccak256(&stream.out())
+}
+
 impl EnrRecord {
     pub fn content_hash(&self) -> [u8; 32] {
-        let mut stream = RlpStream::new_list(1 + self.pairs.len() * 2);
-        stream.append(*self.seq);
-        for (k,v) in &self.pairs {
-            stream.append(k);
-            stream.append(v);
-        }
-        keccak256(&stream.out())
+        content_hash(self.seq, &self.pairs)
     }
 
-    pub fn verify(&self, pubkey: &k256::ecdsa::VerifyingKey) -> bool {
+    pub fn verify(&self, pubkey: &VerifyingKey) -> bool {
         let content_hash = self.content_hash();
         verify_signature(pubkey, &content_hash, &self.signature)
     }
+
+    /// The RLP item stored under `key`.
+    pub fn get(&self, key: &str) -> Option<Rlp<'_>> {
+        let (_, v) = self.pairs.iter().find(|(k, _)| k == key.as_bytes())?;
+        Some(Rlp::new(v))
+    }
+
+    pub fn public_key(&self) -> Option<VerifyingKey> {
+        let bytes: Vec<u8> = self.get("secp256k1")?.as_val().ok()?;
+        VerifyingKey::from_sec1_bytes(&bytes).ok()
+    }
+
+    pub fn node_id(&self) -> Option<NodeId> {
+        self.public_key().map(|k| node_id_from_pubkey(&k))
+    }
+
+    pub fn ip(&self) -> Option<Ipv4Addr> {
+        let bytes: Vec<u8> = self.get("ip")?.as_val().ok()?;
+        Some(<[u8; 4]>::try_from(bytes).ok()?.into())
+    }
+
+    pub fn ip6(&self) -> Option<Ipv6Addr> {
+        let bytes: Vec<u8> = self.get("ip6")?.as_val().ok()?;
+        Some(<[u8; 16]>::try_from(bytes).ok()?.into())
+    }
+
+    /// A port entry such as "tcp", "udp" or "quic".
+    pub fn port(&self, key: &str) -> Option<u16> {
+        self.get(key)?.as_val().ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(2 + self.pairs.len() * 2);
+        stream.append(&self.signature.to_vec()).append(&self.seq);
+        for (k, v) in &self.pairs {
+            stream.append(k);
+            stream.append_raw(v, 1);
+        }
+        stream.out().to_vec()
+    }
+
+    /// Parse a record and check it is signed by the key it names.
+    pub fn decode(data: &[u8]) -> Result<Self, EnrError> {
+        if data.len() > MAX_RECORD_SIZE {
+            return Err(EnrError::TooLarge(data.len()));
+        }
+        let rlp = Rlp::new(data);
+        let items = rlp.item_count()?;
+        if items < 2 || items % 2 != 0 {
+            return Err(EnrError::RlpError);
+        }
+        let signature: Vec<u8> = rlp.val_at(0)?;
+        let signature = Signature::from_slice(&signature).map_err(|_| EnrError::InvalidSignature)?;
+        let mut pairs: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(items / 2 - 1);
+        for i in (2..items).step_by(2) {
+            let key: Vec<u8> = rlp.val_at(i)?;
+            if pairs.last().is_some_and(|(last, _)| *last >= key) {
+                return Err(EnrError::UnsortedKeys);
+            }
+            pairs.push((key, rlp.at(i + 1)?.as_raw().to_vec()));
+        }
+        let record = Self {
+            signature,
+            seq: rlp.val_at(1)?,
+            pairs,
+        };
+
+        let scheme: Vec<u8> = record.get("id").ok_or(EnrError::MissingField("id"))?.as_val()?;
+        if scheme != b"v4" {
+            return Err(EnrError::UnknownScheme);
+        }
+        let key = record.public_key().ok_or(EnrError::MissingField("secp256k1"))?;
+        if !record.verify(&key) {
+            return Err(EnrError::InvalidSignature);
+        }
+        Ok(record)
+    }
+}
+
+/// `enr:` and the unpadded base64url of the RLP, as geth prints records.
+impl fmt::Display for EnrRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "enr:{}", URL_SAFE_NO_PAD.encode(self.encode()))
+    }
+}
+
+impl FromStr for EnrRecord {
+    type Err = EnrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let body = s.trim().strip_prefix("enr:").ok_or(EnrError::MissingPrefix)?;
+        Self::decode(&URL_SAFE_NO_PAD.decode(body)?)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::crypto::generate_keypair;
+    use crate::crypto::{generate_keypair, keypair_from_secret};
 
     #[test]
     fn test_enr_sign_and_verify() {
@@ -44,4 +155,32 @@ mod tests {
 
         assert!(enr.verify(&kp.verifying_key));
     }
+
+    // the example record of EIP-778
+    #[test]
+    fn reads_and_writes_the_eip778_example() {
+        let text = "enr:-IS4QHCYrYZbAKWCBRlAy5zzaDZXJBGkcnh4MHcBFZntXNFrdvJjX04jRzjzCBOonrkTfj499SZuOh8R33Ls8RRcy5wBgmlkgnY0gmlwhH8AAAGJc2VjcDI1NmsxoQPKY0yuDUmstAHYpMa2_oxVtw0RW_QAdpzBQA8yWM0xOIN1ZHCCdl8";
+        let record: EnrRecord = text.parse().unwrap();
+        assert_eq!(record.seq, 1);
+        assert_eq!(record.ip(), Some(Ipv4Addr::LOCALHOST));
+        assert_eq!(record.port("udp"), Some(30303));
+        assert_eq!(
+            hex::encode(record.node_id().unwrap()),
+            "a448f24c6d18e575453db13171562b71999873db5b286df957af199ec94617f7"
+        );
+        assert_eq!(record.to_string(), text);
+
+        // signing the same content with the example key gives the same bytes
+        let key = keypair_from_secret(&hex::decode("b71c71a67e1177ad4e901695e1b4b9ee17ae16c6668d313eac2f96dbcda3f291").unwrap()).unwrap();
+        let built = super::super::builder::EnrBuilder::new()
+            .add(b"ip", &[127, 0, 0, 1])
+            .add_raw(b"udp", &rlp::encode(&30303u16))
+            .build(&key);
+        assert_eq!(built, record);
+
+        let mut forged = record.clone();
+        forged.seq = 2;
+        let forged = forged.to_string();
+        assert!(matches!(forged.parse::<EnrRecord>(), Err(EnrError::InvalidSignature)));
+    }
 }
@@ -0,0 +1,175 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::spec::ChainSpec;
+
+/// EIP-2124 fork identifier: CRC32 over genesis hash and every fork block
+/// passed so far, plus the next scheduled fork (0 = none known).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ForkId {
+    pub hash: u32,
+    pub next: u64,
+}
+
+impl fmt::Display for ForkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:08x}/{}", self.hash, self.next)
+    }
+}
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum ForkIdError {
+    #[error("remote fork id {0} is not on our chain")]
+    Incompatible(ForkId),
+
+    #[error("remote {0} is missing a fork we already passed")]
+    RemoteStale(ForkId),
+
+    #[error("remote {0} announces a fork we passed without applying it")]
+    LocalIncompatibleOrStale(ForkId),
+}
+
+/// Why a peer's advertised chain is not one we can talk to.
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum IncompatiblePeer {
+    #[error("network id {remote} differs from ours ({local})")]
+    NetworkId { local: u64, remote: u64 },
+
+    #[error("genesis {remote} differs from ours ({local})")]
+    Genesis { local: String, remote: String },
+
+    #[error(transparent)]
+    Fork(#[from] ForkIdError),
+}
+
+/// Everything needed to compute our own fork id and to judge a peer's.
+#[derive(Debug, Clone)]
+pub struct ForkFilter {
+    forks: Vec<u64>,
+    // sums[i] = checksum after applying forks[..i]
+    sums: Vec<u32>,
+}
+
+impl ForkFilter {
+    pub fn new(genesis_hash: &str, forks: &[u64]) -> Self {
+        let mut forks: Vec<u64> = forks.iter().copied().filter(|&f| f > 0).collect();
+        forks.sort_unstable();
+        forks.dedup();
+
+        let genesis = hex::decode(genesis_hash.trim_start_matches("0x"))
+            .unwrap_or_else(|_| genesis_hash.as_bytes().to_vec());
+        let mut sums = vec![crc32(0, &genesis)];
+        for f in &forks {
+            let last = *sums.last().unwrap();
+            sums.push(crc32(last, &f.to_be_bytes()));
+        }
+
+        Self { forks, sums }
+    }
+
+    pub fn from_spec(spec: &ChainSpec) -> Self {
+        Self::new(&spec.genesis_hash(), &spec.forks)
+    }
+
+    pub fn fork_id(&self, head: u64) -> ForkId {
+        let passed = self.forks.iter().take_while(|&&f| f <= head).count();
+        ForkId {
+            hash: self.sums[passed],
+            next: self.forks.get(passed).copied().unwrap_or(0),
+        }
+    }
+
+    /// The EIP-2124 validation rules, run against our current head.
+    pub fn check(&self, head: u64, remote: &ForkId) -> Result<(), ForkIdError> {
+        let passed = self.forks.iter().take_while(|&&f| f <= head).count();
+
+        // 1) same fork state: fine unless they expect a fork we already went past
+        if self.sums[passed] == remote.hash {
+            if remote.next > 0 && head >= remote.next {
+                return Err(ForkIdError::LocalIncompatibleOrStale(*remote));
+            }
+            return Ok(());
+        }
+
+        // 2) remote is behind us: they must at least know about the next fork
+        if let Some(j) = self.sums[..passed].iter().position(|&s| s == remote.hash) {
+            if self.forks[j] != remote.next {
+                return Err(ForkIdError::RemoteStale(*remote));
+            }
+            return Ok(());
+        }
+
+        // 3) remote is ahead of us (we are still syncing)
+        if self.sums[passed + 1..].contains(&remote.hash) {
+            return Ok(());
+        }
+
+        Err(ForkIdError::Incompatible(*remote))
+    }
+}
+
+/// Bitwise IEEE CRC32, continuing from a previous checksum `seed`.
+fn crc32(seed: u32, data: &[u8]) -> u32 {
+    let mut crc = !seed;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // vectors from EIP-2124 (Ethereum mainnet up to Petersburg)
+    const MAINNET_GENESIS: &str =
+        "0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3";
+    const MAINNET_FORKS: [u64; 7] = [1150000, 1920000, 2463000, 2675000, 2675000, 4370000, 7280000];
+
+    fn id(hash: u32, next: u64) -> ForkId {
+        ForkId { hash, next }
+    }
+
+    #[test]
+    fn computes_mainnet_fork_ids() {
+        let f = ForkFilter::new(MAINNET_GENESIS, &MAINNET_FORKS);
+        assert_eq!(f.fork_id(0), id(0xfc64ec04, 1150000));
+        assert_eq!(f.fork_id(1150000), id(0x97c2c34c, 1920000));
+        assert_eq!(f.fork_id(4370000), id(0xa00bc324, 7280000));
+        assert_eq!(f.fork_id(7987396), id(0x668db0af, 0));
+    }
+
+    #[test]
+    fn applies_eip_2124_rules() {
+        let f = ForkFilter::new(MAINNET_GENESIS, &MAINNET_FORKS);
+
+        assert_eq!(f.check(7987396, &id(0x668db0af, 0)), Ok(()));
+        // remote behind but aware of our fork
+        assert_eq!(f.check(7987396, &id(0xa00bc324, 7280000)), Ok(()));
+        // remote behind and unaware: needs an upgrade
+        assert_eq!(
+            f.check(7987396, &id(0xa00bc324, 0)),
+            Err(ForkIdError::RemoteStale(id(0xa00bc324, 0)))
+        );
+        // we are still syncing, remote already on Petersburg
+        assert_eq!(f.check(7279999, &id(0x668db0af, 0)), Ok(()));
+        // remote expects a fork at a block we've already passed
+        assert_eq!(
+            f.check(7987396, &id(0x668db0af, 7987396)),
+            Err(ForkIdError::LocalIncompatibleOrStale(id(0x668db0af, 7987396)))
+        );
+        assert!(matches!(
+            f.check(7987396, &id(0xdeadbeef, 0)),
+            Err(ForkIdError::Incompatible(_))
+        ));
+    }
+}
@@ -0,0 +1,10 @@
+pub mod cli;
+pub mod config;
+pub mod crypto;
+pub mod discovery;
+pub mod enr;
+pub mod fork_id;
+pub mod protocol;
+pub mod session;
+pub mod spec;
+pub mod transport;
@@ -1,28 +1,138 @@
-mod transport;
-mod discovery;
-mod session;
-mod protocol;
-use discovery::{enr::Enr, service::DiscoveryService};
-use transport::quic::endpoint::start_endpoint;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process::ExitCode;
+
+use ethnetlite::cli::{self, CliError, Command, RunArgs, USAGE};
+use ethnetlite::crypto::keystore::{load_or_create_node_key, write_secret, Keystore};
+use ethnetlite::crypto::{generate_keypair, node_id_from_pubkey};
+use ethnetlite::discovery::client::find_nodes;
+use ethnetlite::discovery::table::PeerFilter;
+use ethnetlite::discovery::{enr::Enr, service::DiscoveryService};
+use ethnetlite::transport::quic::endpoint::start_endpoint;
+use tokio::net::UdpSocket;
 
 #[tokio::main]
-async fn main() {
-    let args: Vec<String> = std::env::args().collect();
+async fn main() -> ExitCode {
+    let cmd = match cli::parse(std::env::args().skip(1)) {
+        Ok(cmd) => cmd,
+        Err(e) => {
+            eprintln!("error: {e}");
+            if e.is_usage() {
+                eprintln!("\n{USAGE}");
+            }
+            return ExitCode::from(2);
+        }
+    };
+    match execute(cmd).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    // usage:
-    // cargo run -- <port> [bootstrap_port]
-    let port: u16 = args[1].parse().unwrap();
+async fn execute(cmd: Command) -> Result<(), CliError> {
+    match cmd {
+        Command::Run(args) => run(*args).await,
+        Command::Keygen { out, password } => keygen(out.as_deref(), password.as_deref()),
+        Command::EnrShow { node, chain } => {
+            let (enr, _) = find_nodes(node, &chain, PeerFilter::default()).await?;
+            print_enr(&enr);
+            Ok(())
+        }
+        Command::EnrDecode { enr } => {
+            print_enr(&enr);
+            Ok(())
+        }
+        Command::Peers { node, chain, filter } => {
+            let (enr, peers) = find_nodes(node, &chain, filter).await?;
+            println!("{} peers known to {} ({})", peers.len(), node, enr.node_id);
+            for p in peers {
+                println!("{}  {}  {}", p.node_id, join(&p.addrs()), list(&p.topics));
+            }
+            Ok(())
+        }
+        Command::Help => {
+            println!("{USAGE}");
+            Ok(())
+        }
+    }
+}
 
-    let endpoint = start_endpoint(port);
-    let local_enr = Enr::new_local(port);
+async fn run(args: RunArgs) -> Result<(), CliError> {
+    tracing_subscriber::fmt().with_max_level(args.log_level).init();
 
-    let bootstrap = if args.len() > 2 {
-        let bp: u16 = args[2].parse().unwrap();
-        Some(format!("127.0.0.1:{bp}").parse().unwrap())
-    } else {
-        None
+    let config = args.config;
+    // without a data dir the node is a new one on every start
+    let node_key = match &config.data_dir {
+        Some(dir) => load_or_create_node_key(dir, args.password.as_deref())?,
+        None => generate_keypair().expect("node keygen"),
     };
+    let endpoint = start_endpoint(config.listen, config.ipv6_only)
+        .map_err(|e| CliError::Listen(config.listen, e))?;
+    let local_enr = Enr::new_local(&node_key, &config.advertised(), config.listen.port());
+    let discv5 = config.discv5.map(|port| SocketAddr::new(config.listen.ip(), port));
+
+    let mut svc = DiscoveryService::new(endpoint, node_key.clone(), local_enr, config)?;
+    if let Some(addr) = discv5 {
+        let socket = UdpSocket::bind(addr).await.map_err(|e| CliError::Listen(addr, e))?;
+        svc = svc.with_discv5(socket);
+    }
+    svc.run(args.bootnodes, args.trees).await;
+    Ok(())
+}
+
+fn keygen(out: Option<&Path>, password: Option<&str>) -> Result<(), CliError> {
+    let keypair = generate_keypair().expect("keygen");
+    let secret = hex::encode(keypair.signing_key.to_bytes());
+
+    println!("node id:    {}", hex::encode(node_id_from_pubkey(&keypair.verifying_key)));
+    println!("public key: {}", hex::encode(keypair.verifying_key.to_sec1_bytes()));
+    let Some(path) = out else {
+        println!("secret key: {secret}");
+        return Ok(());
+    };
+    let contents = match password {
+        Some(password) => {
+            let ks = Keystore::encrypt(&keypair, password);
+            serde_json::to_vec_pretty(&ks).expect("serialize keystore")
+        }
+        None => secret.into_bytes(),
+    };
+    // never clobbers an existing key
+    write_secret(path, &contents).map_err(|e| CliError::Write(path.into(), e))?;
+    println!("secret key written to {}", path.display());
+    Ok(())
+}
+
+fn print_enr(enr: &Enr) {
+    println!("{}", enr.to_text());
+    println!("  node id  {}", enr.node_id);
+    println!("  address  {}", join(&enr.addrs()));
+    match enr.udp {
+        Some(port) => println!("  discv5   udp {port}"),
+        None => println!("  discv5   -"),
+    }
+    match &enr.fork_id {
+        Some(f) => println!("  fork id  {f}"),
+        None => println!("  fork id  -"),
+    }
+    println!("  caps     {}", list(&enr.caps));
+    println!("  topics   {}", list(&enr.topics));
+}
+
+fn list(items: &[String]) -> String {
+    if items.is_empty() {
+        return "-".into();
+    }
+    items.join(",")
+}
 
-    let svc = DiscoveryService::new(endpoint, local_enr);
-    svc.run(bootstrap).await;
+fn join(addrs: &[SocketAddr]) -> String {
+    let addrs: Vec<String> = addrs.iter().map(|a| a.to_string()).collect();
+    if addrs.is_empty() {
+        return "-".into();
+    }
+    addrs.join(" ")
 }
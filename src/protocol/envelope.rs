@@ -1,5 +1,8 @@
 use serde::{Serialize, Deserialize};
 
+/// Largest length prefix a reader accepts; a full sync response fits well below it.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Envelope {
     pub proto: String,     // "discv-lite/0.1" | "mini-sync/0.1"
@@ -18,4 +21,14 @@ impl Envelope {
     pub fn from_bytes(b: &[u8]) -> Option<Self> {
         serde_json::from_slice(b).ok()
     }
+
+    /// The `type` tag every protocol message carries, e.g. "FindNodes".
+    pub fn kind(&self) -> Option<String> {
+        #[derive(Deserialize)]
+        struct Tagged {
+            #[serde(rename = "type")]
+            kind: String,
+        }
+        serde_json::from_slice::<Tagged>(&self.data).ok().map(|t| t.kind)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::protocol::gossip::message::{Message, MessageId};
+
+/// Recently seen messages, kept for a few heartbeats so they can be
+/// advertised with IHAVE and served on IWANT.
+///
+/// Each heartbeat opens a new window; only the newest `gossip` windows are
+/// advertised, and messages older than `history` windows are dropped.
+#[derive(Debug)]
+pub struct MessageCache {
+    msgs: HashMap<MessageId, Message>,
+    windows: VecDeque<Vec<(MessageId, String)>>,
+    history: usize,
+    gossip: usize,
+}
+
+impl MessageCache {
+    pub fn new(history: usize, gossip: usize) -> Self {
+        Self {
+            msgs: HashMap::new(),
+            windows: VecDeque::from([vec![]]),
+            history,
+            gossip: gossip.min(history),
+        }
+    }
+
+    pub fn put(&mut self, msg: Message) {
+        if self.msgs.contains_key(&msg.id) {
+            return;
+        }
+        self.windows[0].push((msg.id.clone(), msg.topic.clone()));
+        self.msgs.insert(msg.id.clone(), msg);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Message> {
+        self.msgs.get(id)
+    }
+
+    /// Ids from the newest windows published on `topic`.
+    pub fn gossip_ids(&self, topic: &str) -> Vec<MessageId> {
+        self.windows
+            .iter()
+            .take(self.gossip)
+            .flatten()
+            .filter(|(_, t)| t == topic)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Start a new window, forgetting the oldest one past `history`.
+    pub fn shift(&mut self) {
+        self.windows.push_front(vec![]);
+        while self.windows.len() > self.history {
+            for (id, _) in self.windows.pop_back().unwrap_or_default() {
+                self.msgs.remove(&id);
+            }
+        }
+    }
+}
+
+/// Ids of every message handled lately, so copies arriving over other
+/// paths are dropped. Entries expire after `ttl`.
+#[derive(Debug)]
+pub struct SeenCache {
+    expiry: HashMap<MessageId, Instant>,
+    ttl: Duration,
+}
+
+impl SeenCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            expiry: HashMap::new(),
+            ttl,
+        }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.expiry.contains_key(id)
+    }
+
+    /// Returns false if `id` was already seen.
+    pub fn insert(&mut self, id: &str, now: Instant) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        self.expiry.insert(id.to_string(), now + self.ttl);
+        true
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        self.expiry.retain(|_, until| *until > now);
+    }
+}
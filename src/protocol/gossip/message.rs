@@ -0,0 +1,72 @@
+use rlp::RlpStream;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::keccak256;
+
+/// keccak256 over (source, seqno, topic, data), hex encoded.
+pub type MessageId = String;
+
+/// One published payload as it travels through the mesh.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    pub id: MessageId,
+    /// node id of the original publisher
+    pub source: String,
+    pub seqno: u64,
+    pub topic: String,
+    pub data: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(source: &str, seqno: u64, topic: &str, data: Vec<u8>) -> Self {
+        Self {
+            id: message_id(source, seqno, topic, &data),
+            source: source.to_string(),
+            seqno,
+            topic: topic.to_string(),
+            data,
+        }
+    }
+
+    /// The id really covers the content, so nobody can shadow a message by
+    /// reusing its id.
+    pub fn has_valid_id(&self) -> bool {
+        self.id == message_id(&self.source, self.seqno, &self.topic, &self.data)
+    }
+}
+
+fn message_id(source: &str, seqno: u64, topic: &str, data: &[u8]) -> MessageId {
+    let mut s = RlpStream::new_list(4);
+    s.append(&source);
+    s.append(&seqno);
+    s.append(&topic);
+    s.append(&data);
+    format!("0x{}", hex::encode(keccak256(&s.out())))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum GossipMessage {
+    /// topics the sender is interested in (sent on connect and on change)
+    Subscribe { topics: Vec<String> },
+    Unsubscribe { topics: Vec<String> },
+    Publish(Message),
+
+    /// recent message ids the sender holds for `topic`
+    IHave { topic: String, ids: Vec<MessageId> },
+    IWant { ids: Vec<MessageId> },
+
+    /// add the sender to / drop it from the receiver's mesh for `topic`
+    Graft { topic: String },
+    Prune { topic: String },
+}
+
+impl GossipMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("serialize gossip msg")
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Option<Self> {
+        serde_json::from_slice(b).ok()
+    }
+}
@@ -0,0 +1,3 @@
+pub mod message;
+pub mod cache;
+pub mod router;
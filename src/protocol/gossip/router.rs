@@ -0,0 +1,478 @@
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::Stream;
+use rand::seq::{IteratorRandom, SliceRandom};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::protocol::gossip::cache::{MessageCache, SeenCache};
+use crate::protocol::gossip::message::{GossipMessage, Message, MessageId};
+
+// mesh degree per topic, and the band it may drift in before a heartbeat fixes it
+const D: usize = 6;
+const D_LOW: usize = 4;
+const D_HIGH: usize = 12;
+// peers outside the mesh that get IHAVE per topic and heartbeat
+const D_LAZY: usize = 6;
+// heartbeats a message stays in the cache, and how many of those it is advertised in
+const HISTORY_LENGTH: usize = 5;
+const HISTORY_GOSSIP: usize = 3;
+const SEEN_TTL: Duration = Duration::from_secs(120);
+// a topic we only publish to keeps its fanout peers this long after the last publish
+const FANOUT_TTL: Duration = Duration::from_secs(60);
+const MAX_IWANT_REPLY: usize = 256;
+// messages a subscriber may fall behind by before new ones are dropped for it
+const SUBSCRIPTION_BUFFER: usize = 256;
+// topics we track for one peer; subscriptions past it are ignored
+const MAX_TOPICS_PER_PEER: usize = 64;
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Messages the network layer should send: (peer, message).
+pub type Outbox = Vec<(String, GossipMessage)>;
+
+/// Messages delivered on one topic, in arrival order.
+#[derive(Debug)]
+pub struct Subscription {
+    topic: String,
+    rx: mpsc::Receiver<Message>,
+}
+
+impl Subscription {
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Wait for the next message; `None` once the router has dropped us.
+    pub async fn next(&mut self) -> Option<Message> {
+        self.rx.recv().await
+    }
+
+    pub fn try_next(&mut self) -> Option<Message> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// GossipSub-style topic routing, without any I/O.
+///
+/// Full messages flow along a per-topic mesh of about `D` peers; everyone
+/// else subscribed to the topic hears about recent message ids through
+/// IHAVE and can pull what it missed with IWANT. The heartbeat keeps mesh
+/// sizes within bounds and ages the caches.
+#[derive(Debug)]
+pub struct GossipRouter {
+    local_id: String,
+    seqno: u64,
+    peers: HashMap<String, HashSet<String>>, // peer -> topics it subscribed to
+    mesh: HashMap<String, HashSet<String>>,  // topic -> peers we forward to
+    fanout: HashMap<String, (HashSet<String>, Instant)>, // topic -> peers, last publish
+    subscribers: HashMap<String, Vec<mpsc::Sender<Message>>>,
+    mcache: MessageCache,
+    seen: SeenCache,
+}
+
+impl GossipRouter {
+    pub fn new(local_id: &str) -> Self {
+        Self {
+            local_id: local_id.to_string(),
+            seqno: 0,
+            peers: HashMap::new(),
+            mesh: HashMap::new(),
+            fanout: HashMap::new(),
+            subscribers: HashMap::new(),
+            mcache: MessageCache::new(HISTORY_LENGTH, HISTORY_GOSSIP),
+            seen: SeenCache::new(SEEN_TTL),
+        }
+    }
+
+    pub fn topics(&self) -> Vec<String> {
+        self.subscribers.keys().cloned().collect()
+    }
+
+    pub fn mesh_peers(&self, topic: &str) -> Vec<String> {
+        self.mesh.get(topic).map(|m| m.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    /// A new connection: tell it what we are subscribed to.
+    pub fn add_peer(&mut self, peer: &str) -> Outbox {
+        self.peers.entry(peer.to_string()).or_default();
+        let topics = self.topics();
+        if topics.is_empty() {
+            return vec![];
+        }
+        vec![(peer.to_string(), GossipMessage::Subscribe { topics })]
+    }
+
+    pub fn remove_peer(&mut self, peer: &str) {
+        self.peers.remove(peer);
+        for mesh in self.mesh.values_mut() {
+            mesh.remove(peer);
+        }
+        for (peers, _) in self.fanout.values_mut() {
+            peers.remove(peer);
+        }
+    }
+
+    pub fn subscribe(&mut self, topic: &str) -> (Subscription, Outbox) {
+        let (tx, rx) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        let first = !self.subscribers.contains_key(topic);
+        self.subscribers.entry(topic.to_string()).or_default().push(tx);
+
+        let mut out = vec![];
+        if first {
+            for peer in self.peers.keys() {
+                let topics = vec![topic.to_string()];
+                out.push((peer.clone(), GossipMessage::Subscribe { topics }));
+            }
+            out.extend(self.join(topic));
+        }
+        let sub = Subscription {
+            topic: topic.to_string(),
+            rx,
+        };
+        (sub, out)
+    }
+
+    /// Leave `topic` entirely, closing every subscription to it.
+    pub fn unsubscribe(&mut self, topic: &str) -> Outbox {
+        if self.subscribers.remove(topic).is_none() {
+            return vec![];
+        }
+        let prune = GossipMessage::Prune {
+            topic: topic.to_string(),
+        };
+        let mut out: Outbox = self
+            .mesh
+            .remove(topic)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|p| (p, prune.clone()))
+            .collect();
+        for peer in self.peers.keys() {
+            let topics = vec![topic.to_string()];
+            out.push((peer.clone(), GossipMessage::Unsubscribe { topics }));
+        }
+        out
+    }
+
+    pub fn publish(&mut self, topic: &str, data: Vec<u8>, now: Instant) -> (MessageId, Outbox) {
+        self.seqno += 1;
+        let msg = Message::new(&self.local_id, self.seqno, topic, data);
+        self.seen.insert(&msg.id, now);
+        self.mcache.put(msg.clone());
+
+        let targets = match self.mesh.get(topic) {
+            Some(mesh) => mesh.clone(),
+            None => {
+                // not subscribed ourselves: keep publishing through the same few peers
+                let mut peers = self.fanout.remove(topic).map(|(p, _)| p).unwrap_or_default();
+                if peers.len() < D {
+                    let extra = self.candidates(topic, &peers, D - peers.len());
+                    peers.extend(extra);
+                }
+                self.fanout.insert(topic.to_string(), (peers.clone(), now));
+                peers
+            }
+        };
+        let out = targets
+            .into_iter()
+            .map(|p| (p, GossipMessage::Publish(msg.clone())))
+            .collect();
+        (msg.id, out)
+    }
+
+    pub fn handle(&mut self, peer: &str, msg: GossipMessage, now: Instant) -> Outbox {
+        match msg {
+            GossipMessage::Subscribe { topics } => {
+                for topic in topics {
+                    self.note_topic(peer, topic);
+                }
+                vec![]
+            }
+            GossipMessage::Unsubscribe { topics } => {
+                for topic in &topics {
+                    if let Some(t) = self.peers.get_mut(peer) {
+                        t.remove(topic);
+                    }
+                    if let Some(mesh) = self.mesh.get_mut(topic) {
+                        mesh.remove(peer);
+                    }
+                }
+                vec![]
+            }
+            GossipMessage::Publish(m) => self.on_publish(peer, m, now),
+            GossipMessage::IHave { topic, ids } => {
+                if !self.subscribers.contains_key(&topic) {
+                    return vec![];
+                }
+                let ids: Vec<MessageId> = ids.into_iter().filter(|id| !self.seen.contains(id)).collect();
+                if ids.is_empty() {
+                    return vec![];
+                }
+                vec![(peer.to_string(), GossipMessage::IWant { ids })]
+            }
+            GossipMessage::IWant { ids } => ids
+                .iter()
+                .take(MAX_IWANT_REPLY)
+                .filter_map(|id| self.mcache.get(id))
+                .map(|m| (peer.to_string(), GossipMessage::Publish(m.clone())))
+                .collect(),
+            GossipMessage::Graft { topic } => {
+                let full = self.mesh.get(&topic).is_some_and(|m| m.len() >= D_HIGH && !m.contains(peer));
+                if !self.subscribers.contains_key(&topic) || full || !self.note_topic(peer, topic.clone()) {
+                    return vec![(peer.to_string(), GossipMessage::Prune { topic })];
+                }
+                self.mesh.entry(topic).or_default().insert(peer.to_string());
+                vec![]
+            }
+            GossipMessage::Prune { topic } => {
+                if let Some(mesh) = self.mesh.get_mut(&topic) {
+                    mesh.remove(peer);
+                }
+                vec![]
+            }
+        }
+    }
+
+    /// Periodic upkeep: repair mesh sizes, expire fanout, advertise recent
+    /// ids to peers outside the mesh and age the caches.
+    pub fn heartbeat(&mut self, now: Instant) -> Outbox {
+        let mut out = vec![];
+
+        let topics: Vec<String> = self.mesh.keys().cloned().collect();
+        for topic in &topics {
+            let mut mesh = self.mesh.remove(topic).unwrap_or_default();
+            mesh.retain(|p| self.subscribed(p, topic));
+            if mesh.len() < D_LOW {
+                for p in self.candidates(topic, &mesh, D - mesh.len()) {
+                    out.push((p.clone(), GossipMessage::Graft { topic: topic.clone() }));
+                    mesh.insert(p);
+                }
+            } else if mesh.len() > D_HIGH {
+                let mut keep: Vec<String> = mesh.iter().cloned().collect();
+                keep.shuffle(&mut rand::thread_rng());
+                for p in keep.split_off(D) {
+                    mesh.remove(&p);
+                    out.push((p, GossipMessage::Prune { topic: topic.clone() }));
+                }
+            }
+            self.mesh.insert(topic.clone(), mesh);
+        }
+
+        self.fanout.retain(|_, (_, last)| now.duration_since(*last) < FANOUT_TTL);
+        for (topic, (peers, _)) in self.fanout.iter_mut() {
+            peers.retain(|p| self.peers.get(p).is_some_and(|t| t.contains(topic)));
+        }
+
+        let gossip_topics = self.mesh.iter().chain(self.fanout.iter().map(|(t, (p, _))| (t, p)));
+        let mut gossip = vec![];
+        for (topic, direct) in gossip_topics {
+            let ids = self.mcache.gossip_ids(topic);
+            if ids.is_empty() {
+                continue;
+            }
+            for p in self.candidates(topic, direct, D_LAZY) {
+                let ids = ids.clone();
+                gossip.push((p, GossipMessage::IHave { topic: topic.clone(), ids }));
+            }
+        }
+        out.extend(gossip);
+
+        self.mcache.shift();
+        self.seen.expire(now);
+        out
+    }
+
+    fn on_publish(&mut self, peer: &str, m: Message, now: Instant) -> Outbox {
+        if !m.has_valid_id() {
+            println!("[GOSSIP] dropping message with a forged id from {}", peer);
+            return vec![];
+        }
+        if !self.seen.insert(&m.id, now) {
+            return vec![];
+        }
+        self.mcache.put(m.clone());
+        self.deliver(&m);
+
+        let Some(mesh) = self.mesh.get(&m.topic) else { return vec![] };
+        mesh.iter()
+            .filter(|p| *p != peer)
+            .map(|p| (p.clone(), GossipMessage::Publish(m.clone())))
+            .collect()
+    }
+
+    fn deliver(&mut self, m: &Message) {
+        let Some(subs) = self.subscribers.get_mut(&m.topic) else { return };
+        // a full buffer only costs that subscriber this message; closed ones go
+        subs.retain(|tx| !matches!(tx.try_send(m.clone()), Err(TrySendError::Closed(_))));
+    }
+
+    fn subscribed(&self, peer: &str, topic: &str) -> bool {
+        self.peers.get(peer).is_some_and(|t| t.contains(topic))
+    }
+
+    /// Up to `n` random peers subscribed to `topic` and not in `exclude`.
+    fn candidates(&self, topic: &str, exclude: &HashSet<String>, n: usize) -> Vec<String> {
+        self.peers
+            .iter()
+            .filter(|(p, t)| t.contains(topic) && !exclude.contains(*p))
+            .map(|(p, _)| p.clone())
+            .choose_multiple(&mut rand::thread_rng(), n)
+    }
+
+    // remember that `peer` is in `topic`, unless that would take it past
+    // the cap; true if it is (now) known to be
+    fn note_topic(&mut self, peer: &str, topic: String) -> bool {
+        let topics = self.peers.entry(peer.to_string()).or_default();
+        if topics.len() >= MAX_TOPICS_PER_PEER && !topics.contains(&topic) {
+            return false;
+        }
+        topics.insert(topic);
+        true
+    }
+
+    /// Graft a fresh mesh for a topic we just subscribed to, starting from
+    /// the fanout peers we were already publishing through.
+    fn join(&mut self, topic: &str) -> Outbox {
+        let mut mesh = self.fanout.remove(topic).map(|(p, _)| p).unwrap_or_default();
+        mesh.retain(|p| self.subscribed(p, topic));
+        let extra = self.candidates(topic, &mesh, D.saturating_sub(mesh.len()));
+        mesh.extend(extra);
+
+        let out = mesh
+            .iter()
+            .map(|p| (p.clone(), GossipMessage::Graft { topic: topic.to_string() }))
+            .collect();
+        self.mesh.insert(topic.to_string(), mesh);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deliver `out` (sent by `from`) and everything it triggers.
+    fn route(nodes: &mut HashMap<String, GossipRouter>, from: &str, out: Outbox, now: Instant) {
+        let mut queue: Vec<(String, String, GossipMessage)> =
+            out.into_iter().map(|(to, m)| (from.to_string(), to, m)).collect();
+        while let Some((from, to, msg)) = queue.pop() {
+            let Some(node) = nodes.get_mut(&to) else { continue };
+            for (next, m) in node.handle(&from, msg, now) {
+                queue.push((to.clone(), next, m));
+            }
+        }
+    }
+
+    /// a - b - c in a line, plus d which only publishes
+    fn line() -> HashMap<String, GossipRouter> {
+        let mut nodes: HashMap<String, GossipRouter> =
+            ["a", "b", "c", "d"].iter().map(|n| (n.to_string(), GossipRouter::new(n))).collect();
+        for (x, y) in [("a", "b"), ("b", "c"), ("d", "a")] {
+            nodes.get_mut(x).unwrap().add_peer(y);
+            nodes.get_mut(y).unwrap().add_peer(x);
+        }
+        nodes
+    }
+
+    #[test]
+    fn messages_flow_along_the_mesh_once() {
+        let now = Instant::now();
+        let mut nodes = line();
+        let mut subs = HashMap::new();
+        for n in ["a", "b", "c"] {
+            let (sub, out) = nodes.get_mut(n).unwrap().subscribe("blocks");
+            route(&mut nodes, n, out, now);
+            subs.insert(n, sub);
+        }
+        assert_eq!(nodes["b"].mesh_peers("blocks").len(), 2);
+
+        let (id, out) = nodes.get_mut("a").unwrap().publish("blocks", b"hi".to_vec(), now);
+        route(&mut nodes, "a", out.clone(), now);
+        let got = subs.get_mut("c").unwrap().try_next().unwrap();
+        assert_eq!((got.id.as_str(), got.data.as_slice()), (id.as_str(), &b"hi"[..]));
+        assert!(subs.get_mut("a").unwrap().try_next().is_none());
+
+        // the same message again is dropped everywhere
+        route(&mut nodes, "a", out, now);
+        assert!(subs.get_mut("b").unwrap().try_next().is_some());
+        assert!(subs.get_mut("b").unwrap().try_next().is_none());
+
+        // d is not subscribed but can publish through its fanout
+        let (_, out) = nodes.get_mut("d").unwrap().publish("blocks", b"from d".to_vec(), now);
+        assert_eq!(out.len(), 1);
+        route(&mut nodes, "d", out, now);
+        assert_eq!(subs.get_mut("c").unwrap().try_next().unwrap().data, b"from d");
+    }
+
+    #[test]
+    fn peers_cannot_grow_our_state_without_bound() {
+        let now = Instant::now();
+        let mut r = GossipRouter::new("r");
+        let (_sub, _) = r.subscribe("t");
+
+        let topics = (0..MAX_TOPICS_PER_PEER + 10).map(|n| format!("junk/{n}")).collect();
+        r.handle("p", GossipMessage::Subscribe { topics }, now);
+        assert_eq!(r.peers["p"].len(), MAX_TOPICS_PER_PEER);
+        // at its cap, even a topic we do serve is refused
+        let out = r.handle("p", GossipMessage::Graft { topic: "t".into() }, now);
+        assert!(matches!(&out[..], [(_, GossipMessage::Prune { .. })]));
+
+        for n in 0..D_HIGH {
+            assert!(r.handle(&format!("m{n}"), GossipMessage::Graft { topic: "t".into() }, now).is_empty());
+        }
+        let out = r.handle("late", GossipMessage::Graft { topic: "t".into() }, now);
+        assert!(matches!(&out[..], [(_, GossipMessage::Prune { .. })]));
+        assert_eq!(r.mesh_peers("t").len(), D_HIGH);
+        // a member grafting again is not turned away
+        assert!(r.handle("m0", GossipMessage::Graft { topic: "t".into() }, now).is_empty());
+    }
+
+    #[test]
+    fn missed_messages_are_pulled_with_ihave_and_iwant() {
+        let now = Instant::now();
+        let mut b = GossipRouter::new("b");
+        let mut c = GossipRouter::new("c");
+        let (_sub_b, _) = b.subscribe("t");
+        let (mut sub_c, _) = c.subscribe("t");
+
+        let (id, _) = b.publish("t", b"late".to_vec(), now);
+        // c connects afterwards and is only told about it
+        b.add_peer("c");
+        b.handle("c", GossipMessage::Subscribe { topics: vec!["t".into()] }, now);
+        b.handle("c", GossipMessage::Prune { topic: "t".into() }, now);
+        // with too small a mesh, the heartbeat grafts c straight back
+        let beat = b.heartbeat(now);
+        assert!(beat.iter().any(|(_, m)| matches!(m, GossipMessage::Graft { .. })));
+
+        let want = c.handle("b", GossipMessage::IHave { topic: "t".into(), ids: vec![id.clone()] }, now);
+        let [(_, GossipMessage::IWant { ids })] = &want[..] else { panic!("expected IWANT") };
+        for (_, m) in b.handle("c", GossipMessage::IWant { ids: ids.clone() }, now) {
+            c.handle("b", m, now);
+        }
+        assert_eq!(sub_c.try_next().unwrap().id, id);
+        let (_, out) = b.publish("t", b"streamed".to_vec(), now);
+        for (_, m) in out {
+            c.handle("b", m, now);
+        }
+        let streamed = futures::executor::block_on(futures::StreamExt::next(&mut sub_c));
+        assert_eq!(streamed.unwrap().data, b"streamed");
+
+        // a forged id never gets delivered
+        let mut forged = Message::new("b", 99, "t", b"x".to_vec());
+        forged.data = b"y".to_vec();
+        c.handle("b", GossipMessage::Publish(forged), now);
+        assert!(sub_c.try_next().is_none());
+    }
+}
@@ -0,0 +1,94 @@
+/// Binary search for the highest block we share with a peer.
+///
+/// We only ever ask the peer for the hash at one height at a time. Genesis is
+/// known to match (the Status check guarantees it), so the answer always lies
+/// in `lo..=hi` and each reply halves that range.
+#[derive(Debug, Clone)]
+pub struct AncestorSearch {
+    lo: u64, // highest height known to match
+    hi: u64, // highest height that might still match
+    probe: u64,
+    pub remote_head: u64,
+}
+
+impl AncestorSearch {
+    pub fn new(local_height: u64, remote_head: u64) -> Self {
+        Self::above(0, local_height, remote_head)
+    }
+
+    /// Search that takes `floor` as matching without asking, e.g. the
+    /// checkpoint a node was started from.
+    pub fn above(floor: u64, local_height: u64, remote_head: u64) -> Self {
+        let hi = local_height.min(remote_head).max(floor);
+        // probe the top first: a peer that simply extends our chain is
+        // resolved in a single round trip
+        Self {
+            lo: floor,
+            hi,
+            probe: hi,
+            remote_head,
+        }
+    }
+
+    /// Height to ask the peer about next, or None once the ancestor is known.
+    pub fn next_probe(&self) -> Option<u64> {
+        (self.lo < self.hi).then_some(self.probe)
+    }
+
+    /// Feed back whether the peer's hash at `number` is one we also have.
+    /// Replies for anything but the outstanding probe are ignored.
+    pub fn on_reply(&mut self, number: u64, known: bool) {
+        if number != self.probe || self.lo >= self.hi {
+            return;
+        }
+        if known {
+            self.lo = number;
+        } else {
+            self.hi = number - 1;
+        }
+        self.probe = self.lo + (self.hi - self.lo).div_ceil(2);
+    }
+
+    pub fn ancestor(&self) -> Option<u64> {
+        (self.lo >= self.hi).then_some(self.lo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(local: u64, remote: u64, fork_at: u64) -> (u64, usize) {
+        let mut s = AncestorSearch::new(local, remote);
+        let mut rounds = 0;
+        while let Some(n) = s.next_probe() {
+            s.on_reply(n, n <= fork_at);
+            rounds += 1;
+        }
+        (s.ancestor().unwrap(), rounds)
+    }
+
+    #[test]
+    fn straight_extension_resolves_in_one_probe() {
+        assert_eq!(run(100, 150, 100), (100, 1));
+    }
+
+    #[test]
+    fn finds_deep_fork_point_in_log_rounds() {
+        let (ancestor, rounds) = run(1000, 1200, 37);
+        assert_eq!(ancestor, 37);
+        assert!(rounds <= 12, "took {rounds} rounds");
+    }
+
+    #[test]
+    fn diverged_right_after_genesis() {
+        assert_eq!(run(10, 10, 0).0, 0);
+    }
+
+    #[test]
+    fn stale_replies_are_ignored() {
+        let mut s = AncestorSearch::new(8, 8);
+        s.on_reply(3, true);
+        assert_eq!(s.next_probe(), Some(8));
+    }
+}
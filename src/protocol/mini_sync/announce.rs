@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use rand::seq::SliceRandom;
+
+// how many hashes to remember, globally and per peer
+const MAX_SEEN: usize = 1024;
+const MAX_KNOWN_PER_PEER: usize = 256;
+
+/// Insertion-ordered set that forgets its oldest entries past `cap`.
+#[derive(Debug)]
+struct RecentSet {
+    order: VecDeque<String>,
+    items: HashSet<String>,
+    cap: usize,
+}
+
+impl RecentSet {
+    fn new(cap: usize) -> Self {
+        Self {
+            order: VecDeque::new(),
+            items: HashSet::new(),
+            cap,
+        }
+    }
+
+    fn contains(&self, item: &str) -> bool {
+        self.items.contains(item)
+    }
+
+    /// Returns false if `item` was already present.
+    fn insert(&mut self, item: &str) -> bool {
+        if !self.items.insert(item.to_string()) {
+            return false;
+        }
+        self.order.push_back(item.to_string());
+        if self.order.len() > self.cap
+            && let Some(old) = self.order.pop_front()
+        {
+            self.items.remove(&old);
+        }
+        true
+    }
+}
+
+/// Decides who hears about a new head and how, and drops repeats.
+///
+/// Like eth/6x block propagation: the full header is pushed to about
+/// sqrt(n) peers and the rest only get its hash, which they can fetch if
+/// they want it. Nobody is told about a hash they are known to have.
+#[derive(Debug)]
+pub struct Announcer {
+    seen: RecentSet,
+    known: HashMap<String, RecentSet>,
+}
+
+impl Default for Announcer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Announcer {
+    pub fn new() -> Self {
+        Self {
+            seen: RecentSet::new(MAX_SEEN),
+            known: HashMap::new(),
+        }
+    }
+
+    /// Note that `peer` has `hash`. Returns true the first time anyone
+    /// announces it to us.
+    pub fn mark(&mut self, peer: &str, hash: &str) -> bool {
+        self.known
+            .entry(peer.to_string())
+            .or_insert_with(|| RecentSet::new(MAX_KNOWN_PER_PEER))
+            .insert(hash);
+        self.seen.insert(hash)
+    }
+
+    pub fn peer_knows(&self, peer: &str, hash: &str) -> bool {
+        self.known.get(peer).is_some_and(|k| k.contains(hash))
+    }
+
+    pub fn forget_peer(&mut self, peer: &str) {
+        self.known.remove(peer);
+    }
+
+    /// Split the `peers` that do not have `hash` yet into those that get the
+    /// full header and those that only get the announcement. Both groups are
+    /// then considered to know it.
+    pub fn plan(&mut self, hash: &str, peers: &[String]) -> (Vec<String>, Vec<String>) {
+        self.seen.insert(hash);
+        let mut targets: Vec<String> = peers
+            .iter()
+            .filter(|p| !self.peer_knows(p, hash))
+            .cloned()
+            .collect();
+        targets.shuffle(&mut rand::thread_rng());
+
+        let push = (targets.len() as f64).sqrt().ceil() as usize;
+        let announce = targets.split_off(push);
+        for p in targets.iter().chain(&announce) {
+            self.mark(p, hash);
+        }
+        (targets, announce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pushes_to_sqrt_n_and_never_repeats() {
+        let mut a = Announcer::new();
+        let peers: Vec<String> = (0..10).map(|i| format!("p{i}")).collect();
+        a.mark("p0", "h1");
+
+        let (push, announce) = a.plan("h1", &peers);
+        assert_eq!((push.len(), announce.len()), (3, 6));
+        assert!(!push.contains(&"p0".to_string()) && !announce.contains(&"p0".to_string()));
+
+        // everyone has it now
+        assert_eq!(a.plan("h1", &peers), (vec![], vec![]));
+        assert!(!a.mark("p3", "h1"));
+        assert!(a.mark("p3", "h2"));
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::Instant;
+
+use thiserror::Error;
+
+use crate::protocol::mini_sync::download::{DownloadError, BATCH_SIZE, REQUEST_TIMEOUT};
+use crate::protocol::mini_sync::header::Header;
+use crate::protocol::mini_sync::message::RequestHeaders;
+use crate::protocol::mini_sync::validation::{HeaderError, HeaderValidator};
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum BackfillError {
+    #[error(transparent)]
+    Download(#[from] DownloadError),
+
+    #[error("header {0}: {1}")]
+    Invalid(String, HeaderError),
+
+    #[error("history ends in genesis {got}, expected {expected}")]
+    WrongGenesis { expected: String, got: String },
+}
+
+/// Walks history below a checkpoint back to genesis, one batch at a time.
+///
+/// Every batch must end in the parent of the lowest header we already hold,
+/// so a single trusted hash at the top is enough to check all of it.
+#[derive(Debug)]
+pub struct Backfill {
+    lowest: Header,
+    genesis_hash: String,
+    in_flight: Option<(String, Instant)>,
+    // last peer that timed out or lied; asked again only if nobody else can
+    last_failed: Option<String>,
+}
+
+impl Backfill {
+    pub fn new(lowest: Header, genesis_hash: String) -> Self {
+        Self {
+            lowest,
+            genesis_hash,
+            in_flight: None,
+            last_failed: None,
+        }
+    }
+
+    pub fn lowest(&self) -> u64 {
+        self.lowest.number
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.lowest.number == 0
+    }
+
+    pub fn is_waiting_on(&self, peer: &str) -> bool {
+        self.in_flight.as_ref().is_some_and(|(p, _)| p == peer)
+    }
+
+    /// Ask the first suitable peer among `peers` for the next batch down.
+    pub fn next_request<'a>(
+        &mut self,
+        mut peers: impl Iterator<Item = &'a String> + Clone,
+        now: Instant,
+    ) -> Option<(String, RequestHeaders)> {
+        if self.is_done() || self.in_flight.is_some() {
+            return None;
+        }
+        let fresh = peers.clone().find(|p| Some(*p) != self.last_failed.as_ref());
+        let peer = fresh.or_else(|| peers.next())?.clone();
+
+        let count = BATCH_SIZE.min(self.lowest.number);
+        let req = RequestHeaders {
+            start: self.lowest.number - count,
+            count,
+            skip: 0,
+        };
+        self.in_flight = Some((peer.clone(), now));
+        Some((peer, req))
+    }
+
+    /// Give up on a request that took too long; returns the slow peer.
+    pub fn expire(&mut self, now: Instant) -> Option<String> {
+        let (_, sent) = self.in_flight.as_ref()?;
+        if now.duration_since(*sent) <= REQUEST_TIMEOUT {
+            return None;
+        }
+        let (peer, _) = self.in_flight.take()?;
+        self.last_failed = Some(peer.clone());
+        Some(peer)
+    }
+
+    /// Check a reply and return its headers (oldest first) for storage.
+    pub fn on_headers(
+        &mut self,
+        peer: &str,
+        headers: Vec<Header>,
+        validator: &dyn HeaderValidator,
+    ) -> Result<Vec<Header>, BackfillError> {
+        if !self.is_waiting_on(peer) {
+            return Err(DownloadError::Unsolicited.into());
+        }
+        self.in_flight = None;
+        let res = self.check(&headers, validator);
+        match res {
+            Ok(()) => {
+                self.lowest = headers[0].clone();
+                Ok(headers)
+            }
+            Err(e) => {
+                self.last_failed = Some(peer.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    fn check(&self, headers: &[Header], validator: &dyn HeaderValidator) -> Result<(), BackfillError> {
+        let count = BATCH_SIZE.min(self.lowest.number);
+        let start = self.lowest.number - count;
+        for i in 0..count {
+            let got = headers.get(i as usize).map(|h| h.number);
+            if got != Some(start + i) {
+                let expected = start + i;
+                return Err(DownloadError::WrongNumber { expected, got }.into());
+            }
+        }
+        if headers.len() as u64 != count {
+            let got = headers.last().map(|h| h.number);
+            return Err(DownloadError::WrongNumber { expected: self.lowest.number, got }.into());
+        }
+
+        for h in headers {
+            if h.compute_hash() != h.hash {
+                return Err(DownloadError::BadHash(h.number).into());
+            }
+        }
+        // walk down from what we already trust
+        let mut child = &self.lowest;
+        for parent in headers.iter().rev() {
+            if child.parent_hash != parent.hash {
+                return Err(DownloadError::BrokenLink(child.number).into());
+            }
+            validator
+                .validate(child, parent)
+                .map_err(|e| BackfillError::Invalid(child.hash.clone(), e))?;
+            child = parent;
+        }
+
+        let first = &headers[0];
+        if first.number == 0 && first.hash != self.genesis_hash {
+            return Err(BackfillError::WrongGenesis {
+                expected: self.genesis_hash.clone(),
+                got: first.hash.clone(),
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::Keypair;
+use crate::protocol::mini_sync::header::{tx_root, Header};
+use crate::protocol::tx::transaction::Transaction;
+
+/// A header together with the transactions its `tx_root` commits to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub header: Header,
+    pub transactions: Vec<Transaction>,
+}
+
+/// The transactions of the block with header `hash`, as sent over the wire.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Body {
+    pub hash: String,
+    pub transactions: Vec<Transaction>,
+}
+
+impl Block {
+    /// Commit `header` to `transactions` and seal it.
+    pub fn seal(mut header: Header, transactions: Vec<Transaction>, keypair: &Keypair) -> Self {
+        header.tx_root = tx_root(&transactions);
+        Self {
+            header: header.seal(keypair),
+            transactions,
+        }
+    }
+
+    pub fn body(&self) -> Body {
+        Body {
+            hash: self.header.hash.clone(),
+            transactions: self.transactions.clone(),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use thiserror::Error;
+
+use crate::protocol::mini_sync::block::Body;
+use crate::protocol::mini_sync::download::REQUEST_TIMEOUT;
+use crate::protocol::mini_sync::header::tx_root;
+use crate::protocol::mini_sync::message::RequestBodies;
+use crate::protocol::tx::transaction::Transaction;
+
+// bodies asked from one peer at a time
+const BODY_BATCH: usize = 32;
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum BodyError {
+    #[error("bodies nobody asked this peer for")]
+    Unsolicited,
+
+    #[error("body of {0} does not match its header's tx root")]
+    RootMismatch(String),
+
+    #[error("body of {0} holds a transaction with a bad hash or signature")]
+    BadTransaction(String),
+}
+
+#[derive(Debug, Clone)]
+struct Wanted {
+    number: u64,
+    hash: String,
+    tx_root: String,
+}
+
+/// Fetches the transactions of canonical blocks whose headers arrived
+/// without them, lowest first, spread over the peers that have them.
+#[derive(Debug, Default)]
+pub struct BodySync {
+    queue: BTreeMap<u64, Wanted>,
+    in_flight: HashMap<String, (Vec<Wanted>, Instant)>,
+}
+
+impl BodySync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enqueue(&mut self, number: u64, hash: &str, tx_root: &str) {
+        let wanted = Wanted {
+            number,
+            hash: hash.to_string(),
+            tx_root: tx_root.to_string(),
+        };
+        // a reorg may replace what we wanted at this height
+        self.queue.insert(number, wanted);
+    }
+
+    /// Bodies still missing, queued or in flight.
+    pub fn remaining(&self) -> usize {
+        self.queue.len() + self.in_flight.values().map(|(w, _)| w.len()).sum::<usize>()
+    }
+
+    pub fn is_busy(&self, peer: &str) -> bool {
+        self.in_flight.contains_key(peer)
+    }
+
+    /// Hand `peer` the lowest queued bodies at or below its `head`.
+    pub fn assign(&mut self, peer: &str, head: u64, now: Instant) -> Option<RequestBodies> {
+        if self.is_busy(peer) {
+            return None;
+        }
+        let numbers: Vec<u64> = self.queue.range(..=head).take(BODY_BATCH).map(|(n, _)| *n).collect();
+        if numbers.is_empty() {
+            return None;
+        }
+        let batch: Vec<Wanted> = numbers.iter().filter_map(|n| self.queue.remove(n)).collect();
+        let hashes = batch.iter().map(|w| w.hash.clone()).collect();
+        self.in_flight.insert(peer.to_string(), (batch, now));
+        Some(RequestBodies { hashes })
+    }
+
+    /// Put requests that took too long back in the queue; returns the slow peers.
+    pub fn expire(&mut self, now: Instant) -> Vec<String> {
+        let slow: Vec<String> = self
+            .in_flight
+            .iter()
+            .filter(|(_, (_, sent))| now.duration_since(*sent) > REQUEST_TIMEOUT)
+            .map(|(p, _)| p.clone())
+            .collect();
+        for peer in &slow {
+            self.requeue(peer);
+        }
+        slow
+    }
+
+    /// Check a reply against the roots we expect. Bodies the peer left out
+    /// go back in the queue; one bad body rejects the whole reply.
+    pub fn on_bodies(
+        &mut self,
+        peer: &str,
+        bodies: Vec<Body>,
+    ) -> Result<Vec<(u64, String, Vec<Transaction>)>, BodyError> {
+        let Some((batch, _)) = self.in_flight.remove(peer) else {
+            return Err(BodyError::Unsolicited);
+        };
+        let mut by_hash: HashMap<String, Vec<Transaction>> =
+            bodies.into_iter().map(|b| (b.hash, b.transactions)).collect();
+
+        let mut done = vec![];
+        let mut missing = vec![];
+        let mut bad = None;
+        for w in batch {
+            let Some(txs) = by_hash.remove(&w.hash) else {
+                missing.push(w);
+                continue;
+            };
+            if bad.is_none() {
+                bad = check(&w, &txs).err();
+            }
+            done.push((w, txs));
+        }
+
+        if let Some(err) = bad {
+            missing.extend(done.into_iter().map(|(w, _)| w));
+            self.put_back(missing);
+            return Err(err);
+        }
+        self.put_back(missing);
+        Ok(done.into_iter().map(|(w, txs)| (w.number, w.hash, txs)).collect())
+    }
+
+    fn requeue(&mut self, peer: &str) {
+        let batch = self.in_flight.remove(peer).map(|(b, _)| b).unwrap_or_default();
+        self.put_back(batch);
+    }
+
+    // never clobbers what a reorg queued at the same height meanwhile
+    fn put_back(&mut self, batch: Vec<Wanted>) {
+        for w in batch {
+            self.queue.entry(w.number).or_insert(w);
+        }
+    }
+}
+
+fn check(w: &Wanted, txs: &[Transaction]) -> Result<(), BodyError> {
+    if tx_root(txs) != w.tx_root {
+        return Err(BodyError::RootMismatch(w.hash.clone()));
+    }
+    if txs.iter().any(|tx| tx.compute_hash() != tx.hash || !tx.verify_signature()) {
+        return Err(BodyError::BadTransaction(w.hash.clone()));
+    }
+    Ok(())
+}
@@ -0,0 +1,467 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::protocol::mini_sync::header::Header;
+use crate::protocol::mini_sync::message::RequestHeaders;
+
+// headers per fill request; also the distance between skeleton headers
+pub const BATCH_SIZE: u64 = 64;
+// skeleton headers per round, so one round covers at most 64 * 64 headers
+const MAX_SKELETON: u64 = 64;
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// a batch that fails this often aborts the round
+const MAX_ATTEMPTS: u32 = 4;
+// a peer that times out or misbehaves this often gets no more work
+const MAX_STRIKES: u32 = 3;
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum DownloadError {
+    #[error("no request outstanding to this peer")]
+    Unsolicited,
+
+    #[error("expected header #{expected}, got {got:?}")]
+    WrongNumber { expected: u64, got: Option<u64> },
+
+    #[error("header #{0} does not link to its predecessor")]
+    BrokenLink(u64),
+
+    #[error("header #{0} hash does not match its contents")]
+    BadHash(u64),
+
+    #[error("batch ending at #{0} does not reach the skeleton header")]
+    AnchorMismatch(u64),
+}
+
+#[derive(Debug)]
+enum Slot {
+    Pending,
+    InFlight { peer: String, sent: Instant },
+    Done { peer: String, headers: Vec<Header> },
+}
+
+/// A contiguous run of headers. `anchor` is the skeleton hash the last one
+/// must have; the tail batch past the last skeleton point has none.
+#[derive(Debug)]
+struct Batch {
+    start: u64,
+    count: u64,
+    anchor: Option<String>,
+    slot: Slot,
+    attempts: u32,
+    // peers that already failed this batch; others are asked first
+    avoid: HashSet<String>,
+}
+
+impl Batch {
+    fn end(&self) -> u64 {
+        self.start + self.count.saturating_sub(1)
+    }
+
+    fn request(&self) -> RequestHeaders {
+        RequestHeaders {
+            start: self.start,
+            count: self.count,
+            skip: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Skeleton {
+    InFlight { sent: Instant, first: u64, count: u64 },
+    Done,
+}
+
+#[derive(Debug)]
+struct PeerLoad {
+    head: u64,
+    strikes: u32,
+}
+
+/// One round of skeleton sync from `start` up to a peer's advertised head.
+///
+/// The skeleton peer first sends every `BATCH_SIZE`-th header; the gaps
+/// between them are then filled in parallel by every peer that has the range.
+/// Each filled batch must end on its skeleton hash, so peers on another
+/// branch cannot slip headers in. Completed batches are handed out strictly
+/// in order for import.
+#[derive(Debug)]
+pub struct SkeletonSync {
+    target: u64,
+    skeleton_peer: String,
+    skeleton: Skeleton,
+    batches: VecDeque<Batch>,
+    peers: HashMap<String, PeerLoad>,
+    failed: bool,
+}
+
+impl SkeletonSync {
+    /// Plan a round against `peer`, whose head is `target`, and return the
+    /// first request to send it.
+    pub fn new(peer: &str, start: u64, target: u64, now: Instant) -> (Self, RequestHeaders) {
+        let mut sync = Self {
+            target,
+            skeleton_peer: peer.to_string(),
+            skeleton: Skeleton::Done,
+            batches: VecDeque::new(),
+            peers: HashMap::new(),
+            failed: false,
+        };
+        sync.add_peer(peer, target);
+
+        let span = target.checked_sub(start).map_or(0, |d| d.saturating_add(1));
+        let points = (span / BATCH_SIZE).min(MAX_SKELETON);
+        if points == 0 {
+            // short range: one plain batch, no skeleton needed
+            sync.push_batch(start, span, None);
+            let req = sync.assign(peer, now).expect("fresh batch is assignable");
+            return (sync, req);
+        }
+
+        let first = start.saturating_add(BATCH_SIZE - 1);
+        sync.skeleton = Skeleton::InFlight {
+            sent: now,
+            first,
+            count: points,
+        };
+        let req = RequestHeaders {
+            start: first,
+            count: points,
+            skip: BATCH_SIZE - 1,
+        };
+        (sync, req)
+    }
+
+    pub fn target(&self) -> u64 {
+        self.target
+    }
+
+    /// First header number not yet handed out for import.
+    pub fn next_to_import(&self) -> u64 {
+        self.batches.front().map(|b| b.start).unwrap_or(self.target.saturating_add(1))
+    }
+
+    pub fn add_peer(&mut self, peer: &str, head: u64) {
+        self.peers
+            .entry(peer.to_string())
+            .and_modify(|p| p.head = p.head.max(head))
+            .or_insert(PeerLoad { head, strikes: 0 });
+    }
+
+    pub fn is_waiting_on(&self, peer: &str) -> bool {
+        let skeleton = matches!(self.skeleton, Skeleton::InFlight { .. }) && self.skeleton_peer == peer;
+        skeleton || self.in_flight(peer).is_some()
+    }
+
+    /// A batch or the skeleton ran out of retries; the round should be dropped.
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.skeleton, Skeleton::Done) && self.batches.is_empty()
+    }
+
+    fn in_flight(&self, peer: &str) -> Option<usize> {
+        self.batches
+            .iter()
+            .position(|b| matches!(&b.slot, Slot::InFlight { peer: p, .. } if p == peer))
+    }
+
+    fn push_batch(&mut self, start: u64, count: u64, anchor: Option<String>) {
+        self.batches.push_back(Batch {
+            start,
+            count,
+            anchor,
+            slot: Slot::Pending,
+            attempts: 0,
+            avoid: HashSet::new(),
+        });
+    }
+
+    /// Next batch for `peer`, if it is idle, in good standing and has the range.
+    pub fn assign(&mut self, peer: &str, now: Instant) -> Option<RequestHeaders> {
+        if self.failed || !matches!(self.skeleton, Skeleton::Done) || self.is_waiting_on(peer) {
+            return None;
+        }
+        let load = self.peers.get(peer)?;
+        if load.strikes >= MAX_STRIKES {
+            return None;
+        }
+        let head = load.head;
+
+        let eligible = |b: &Batch| matches!(b.slot, Slot::Pending) && b.end() <= head;
+        let idx = self
+            .batches
+            .iter()
+            .position(|b| eligible(b) && !b.avoid.contains(peer))
+            .or_else(|| self.batches.iter().position(eligible))?;
+
+        let batch = &mut self.batches[idx];
+        batch.slot = Slot::InFlight {
+            peer: peer.to_string(),
+            sent: now,
+        };
+        Some(batch.request())
+    }
+
+    /// Hand work to every idle peer.
+    pub fn assign_idle(&mut self, now: Instant) -> Vec<(String, RequestHeaders)> {
+        let peers: Vec<String> = self.peers.keys().cloned().collect();
+        peers
+            .into_iter()
+            .filter_map(|p| self.assign(&p, now).map(|req| (p, req)))
+            .collect()
+    }
+
+    /// Deliver a `Headers` reply. On error the work goes back in the queue
+    /// and the peer takes a strike.
+    pub fn on_headers(&mut self, peer: &str, headers: Vec<Header>) -> Result<(), DownloadError> {
+        if let Skeleton::InFlight { first, count, .. } = self.skeleton
+            && self.skeleton_peer == peer
+        {
+            let res = self.on_skeleton(first, count, headers);
+            if res.is_err() {
+                self.failed = true;
+            }
+            return res;
+        }
+
+        let idx = self.in_flight(peer).ok_or(DownloadError::Unsolicited)?;
+        let batch = &mut self.batches[idx];
+        match check_batch(batch, &headers) {
+            Ok(()) => {
+                batch.slot = Slot::Done {
+                    peer: peer.to_string(),
+                    headers,
+                };
+                Ok(())
+            }
+            Err(e) => {
+                self.requeue(idx, peer);
+                Err(e)
+            }
+        }
+    }
+
+    fn on_skeleton(&mut self, first: u64, count: u64, headers: Vec<Header>) -> Result<(), DownloadError> {
+        if headers.is_empty() || headers.len() as u64 > count {
+            return Err(DownloadError::WrongNumber {
+                expected: first,
+                got: headers.first().map(|h| h.number),
+            });
+        }
+        for (i, h) in headers.iter().enumerate() {
+            let expected = (i as u64).saturating_mul(BATCH_SIZE).saturating_add(first);
+            if h.number != expected {
+                return Err(DownloadError::WrongNumber {
+                    expected,
+                    got: Some(h.number),
+                });
+            }
+            if h.compute_hash() != h.hash {
+                return Err(DownloadError::BadHash(h.number));
+            }
+        }
+
+        for h in &headers {
+            self.push_batch(h.number + 1 - BATCH_SIZE, BATCH_SIZE, Some(h.hash.clone()));
+        }
+        // the remainder past the last skeleton point, if the skeleton got that far
+        let covered = headers.last().unwrap().number;
+        let reached_end = headers.len() as u64 == count && self.target - covered < BATCH_SIZE;
+        if reached_end && covered < self.target {
+            self.push_batch(covered + 1, self.target - covered, None);
+        }
+        self.target = self.batches.back().map(Batch::end).unwrap_or(covered);
+        self.skeleton = Skeleton::Done;
+        Ok(())
+    }
+
+    fn requeue(&mut self, idx: usize, peer: &str) {
+        let batch = &mut self.batches[idx];
+        batch.slot = Slot::Pending;
+        batch.attempts += 1;
+        batch.avoid.insert(peer.to_string());
+        if batch.attempts >= MAX_ATTEMPTS {
+            self.failed = true;
+        }
+        if let Some(load) = self.peers.get_mut(peer) {
+            load.strikes += 1;
+        }
+    }
+
+    /// Requeue everything that has been outstanding longer than
+    /// `REQUEST_TIMEOUT` and return the peers that were too slow.
+    pub fn expire(&mut self, now: Instant) -> Vec<String> {
+        let mut slow = vec![];
+        if let Skeleton::InFlight { sent, .. } = self.skeleton
+            && now.duration_since(sent) > REQUEST_TIMEOUT
+        {
+            self.failed = true;
+            slow.push(self.skeleton_peer.clone());
+        }
+
+        for idx in 0..self.batches.len() {
+            if let Slot::InFlight { peer, sent } = &self.batches[idx].slot
+                && now.duration_since(*sent) > REQUEST_TIMEOUT
+            {
+                let peer = peer.clone();
+                self.requeue(idx, &peer);
+                slow.push(peer);
+            }
+        }
+        slow
+    }
+
+    /// Pop the completed batches at the front, in order, with whoever served them.
+    pub fn take_ready(&mut self) -> Vec<(String, Vec<Header>)> {
+        let mut out = vec![];
+        while matches!(self.batches.front(), Some(Batch { slot: Slot::Done { .. }, .. })) {
+            if let Some(Batch {
+                slot: Slot::Done { peer, headers },
+                ..
+            }) = self.batches.pop_front()
+            {
+                out.push((peer, headers));
+            }
+        }
+        out
+    }
+}
+
+fn check_batch(batch: &Batch, headers: &[Header]) -> Result<(), DownloadError> {
+    for i in 0..batch.count {
+        let expected = batch.start + i;
+        let h = headers.get(i as usize);
+        if h.map(|h| h.number) != Some(expected) {
+            return Err(DownloadError::WrongNumber {
+                expected,
+                got: h.map(|h| h.number),
+            });
+        }
+    }
+    if headers.len() as u64 != batch.count {
+        return Err(DownloadError::WrongNumber {
+            expected: batch.end() + 1,
+            got: headers.last().map(|h| h.number),
+        });
+    }
+
+    for (i, h) in headers.iter().enumerate() {
+        if h.compute_hash() != h.hash {
+            return Err(DownloadError::BadHash(h.number));
+        }
+        if i > 0 && h.parent_hash != headers[i - 1].hash {
+            return Err(DownloadError::BrokenLink(h.number));
+        }
+    }
+
+    if let Some(anchor) = &batch.anchor
+        && headers.last().map(|h| &h.hash) != Some(anchor)
+    {
+        return Err(DownloadError::AnchorMismatch(batch.end()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(len: u64) -> Vec<Header> {
+        let mut out: Vec<Header> = vec![];
+        for n in 0..=len {
+            let mut h = Header {
+                parent_hash: out.last().map(|p| p.hash.clone()).unwrap_or_default(),
+                number: n,
+                timestamp: n,
+                ..Default::default()
+            };
+            h.hash = h.compute_hash();
+            out.push(h);
+        }
+        out
+    }
+
+    fn serve(chain: &[Header], req: &RequestHeaders) -> Vec<Header> {
+        (0..req.count)
+            .map(|i| chain[(req.start + i * (req.skip + 1)) as usize].clone())
+            .collect()
+    }
+
+    #[test]
+    fn skeleton_then_parallel_fill_imports_in_order() {
+        let c = chain(200);
+        let t0 = Instant::now();
+        let (mut dl, skel) = SkeletonSync::new("a", 1, 200, t0);
+        assert_eq!((skel.start, skel.count, skel.skip), (64, 3, 63));
+        dl.on_headers("a", serve(&c, &skel)).unwrap();
+        dl.add_peer("b", 200);
+        dl.add_peer("c", 100); // only has the first batch's range
+
+        let cw = dl.assign("c", t0).unwrap();
+        assert_eq!(cw.start, 1);
+        let mut work: HashMap<String, RequestHeaders> = dl.assign_idle(t0).into_iter().collect();
+        assert_eq!(work.len(), 2);
+        assert!(dl.assign("c", t0).is_none());
+
+        // later batches land first but nothing is released until #1.. is done
+        let b = work.remove("b").unwrap();
+        dl.on_headers("b", serve(&c, &b)).unwrap();
+        assert!(dl.take_ready().is_empty());
+
+        let a = work.remove("a").unwrap();
+        dl.on_headers("a", serve(&c, &a)).unwrap();
+        dl.on_headers("c", serve(&c, &cw)).unwrap();
+
+        let ready: Vec<u64> = dl.take_ready().iter().flat_map(|(_, hs)| hs.iter().map(|h| h.number)).collect();
+        assert_eq!(ready, (1..=192).collect::<Vec<_>>());
+
+        // tail 193..=200 has no skeleton anchor
+        let tail = dl.assign("b", t0).unwrap();
+        assert_eq!((tail.start, tail.count), (193, 8));
+        dl.on_headers("b", serve(&c, &tail)).unwrap();
+        assert_eq!(dl.take_ready().len(), 1);
+        assert!(dl.is_finished());
+    }
+
+    #[test]
+    fn unbounded_target_is_planned_without_overflow() {
+        let (dl, skel) = SkeletonSync::new("a", 1, u64::MAX, Instant::now());
+        assert_eq!((skel.start, skel.count, skel.skip), (64, MAX_SKELETON, 63));
+        assert_eq!(dl.next_to_import(), u64::MAX);
+
+        let (_, near_top) = SkeletonSync::new("a", u64::MAX - 3, u64::MAX, Instant::now());
+        assert_eq!((near_top.start, near_top.count), (u64::MAX - 3, 4));
+    }
+
+    #[test]
+    fn slow_or_lying_peers_lose_their_batches() {
+        let c = chain(130);
+        let t0 = Instant::now();
+        let (mut dl, skel) = SkeletonSync::new("a", 1, 130, t0);
+        dl.on_headers("a", serve(&c, &skel)).unwrap();
+        dl.add_peer("b", 130);
+
+        let first = dl.assign("b", t0).unwrap();
+        assert_eq!(first.start, 1);
+        // b never answers; its batch goes to a, and b is sent elsewhere
+        let later = t0 + REQUEST_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(dl.expire(later), vec!["b".to_string()]);
+        assert_eq!(dl.assign("a", later).unwrap().start, 1);
+        let other = dl.assign("b", later).unwrap();
+        assert_eq!(other.start, 65);
+
+        // b then answers with headers from a different branch
+        let mut forged = serve(&c, &other);
+        forged.last_mut().unwrap().timestamp += 1;
+        let last = forged.last_mut().unwrap();
+        last.hash = last.compute_hash();
+        assert_eq!(dl.on_headers("b", forged), Err(DownloadError::AnchorMismatch(128)));
+        assert!(!dl.is_waiting_on("b"));
+        assert_eq!(dl.on_headers("b", vec![]), Err(DownloadError::Unsolicited));
+    }
+}
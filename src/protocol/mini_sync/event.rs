@@ -0,0 +1,27 @@
+use crate::protocol::tx::transaction::Transaction;
+
+/// Things that happen to the canonical chain, fanned out to subscribers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEvent {
+    /// The head moved forward without dropping any canonical header.
+    NewHead { hash: String, number: u64 },
+    Reorg(Reorg),
+    /// The transactions of a block came in, produced here or fetched.
+    Body {
+        hash: String,
+        number: u64,
+        transactions: Vec<Transaction>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reorg {
+    pub old_head: String,
+    pub new_head: String,
+    /// number of canonical headers that were rolled back
+    pub depth: u64,
+    /// hashes that left the canonical chain, lowest number first
+    pub dropped: Vec<String>,
+    /// hashes that joined the canonical chain, lowest number first
+    pub added: Vec<String>,
+}
@@ -1,18 +1,186 @@
-use crate::protocol::mini_sync::chain::Chain;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::mini_sync::tree::{HeaderTree, TreeNode};
+
+/// voter -> hash of the latest head it told us about (LMD = latest message driven)
+pub type Votes = HashMap<String, String>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ForkChoiceRule {
+    #[default]
+    #[serde(rename = "longest")]
     LongestChain,
-    
+    #[serde(rename = "heaviest")]
+    HeaviestChain,
+    #[serde(rename = "ghost")]
+    Ghost,
+}
+
+impl FromStr for ForkChoiceRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "longest" => Ok(Self::LongestChain),
+            "heaviest" => Ok(Self::HeaviestChain),
+            "ghost" => Ok(Self::Ghost),
+            other => Err(format!(
+                "unknown fork-choice rule '{other}' (expected longest|heaviest|ghost)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ForkChoiceRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::LongestChain => "longest",
+            Self::HeaviestChain => "heaviest",
+            Self::Ghost => "ghost",
+        };
+        f.write_str(s)
+    }
 }
 
-pub fn choose<'a>(
-    rule: &ForkChoiceRule,
-    candidates: &'a [Chain],
-) -> Option<&'a Chain> {
+/// Pick the canonical head among the fork tips in `tree`.
+///
+/// Every rule breaks ties on the lowest head hash so that all nodes
+/// holding the same set of forks agree on the same head.
+pub fn choose(rule: &ForkChoiceRule, tree: &HeaderTree, votes: &Votes) -> Option<String> {
     match rule {
-        ForkChoiceRule::LongestChain => {
-            candidates.iter().max_by_key(|c| c.height())
+        ForkChoiceRule::LongestChain => best_leaf_by(tree, |n| n.header.number),
+        ForkChoiceRule::HeaviestChain => best_leaf_by(tree, |n| n.total_difficulty),
+        ForkChoiceRule::Ghost => Some(ghost_head(tree, votes)),
+    }
+}
+
+fn best_leaf_by<F>(tree: &HeaderTree, key: F) -> Option<String>
+where
+    F: Fn(&TreeNode) -> u64,
+{
+    tree.leaves()
+        .max_by(|a, b| {
+            key(a)
+                .cmp(&key(b))
+                // lower hash wins, so it must compare as "greater"
+                .then_with(|| b.header.hash.cmp(&a.header.hash))
+        })
+        .map(|n| n.header.hash.clone())
+}
+
+/// LMD-GHOST over the header tree.
+///
+/// A block weighs its own difficulty plus one per latest vote pointing at it.
+/// Starting at the finalized header we repeatedly step into the child whose
+/// subtree is heaviest, until we hit a leaf. The tree keeps each subtree's
+/// difficulty; votes are added up along the path from each voted head.
+fn ghost_head(tree: &HeaderTree, votes: &Votes) -> String {
+    let root = tree.finalized();
+
+    // votes cast for a header or anything built on it
+    let mut backing: HashMap<&str, u64> = HashMap::new();
+    for head in votes.values() {
+        let mut cur = tree.get(head);
+        while let Some(node) = cur.filter(|n| n.header.number > root.number) {
+            *backing.entry(node.header.hash.as_str()).or_default() += 1;
+            cur = tree.get(&node.header.parent_hash);
         }
     }
+    let weight = |hash: &str| {
+        let node = tree.get(hash).expect("children are always in the tree");
+        node.subtree_difficulty
+            .saturating_add(backing.get(hash).copied().unwrap_or(0))
+    };
+
+    let mut head = root.hash.as_str();
+    loop {
+        let kids = &tree.get(head).expect("walk stays inside the tree").children;
+        let Some(best) = kids
+            .iter()
+            .map(|k| (k.as_str(), weight(k)))
+            .max_by(|(ha, wa), (hb, wb)| wa.cmp(wb).then_with(|| hb.cmp(ha)))
+        else {
+            break;
+        };
+        head = best.0;
+    }
+
+    head.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::mini_sync::header::Header;
+
+    fn tree(branches: &[&[(&str, u64)]]) -> HeaderTree {
+        let mut t = HeaderTree::new(Header {
+            parent_hash: "0x00".into(),
+            hash: "0xgenesis".into(),
+            number: 0,
+            difficulty: 1,
+            ..Default::default()
+        });
+        for blocks in branches {
+            let mut parent = t.root().clone();
+            for (hash, difficulty) in *blocks {
+                let h = Header {
+                    parent_hash: parent.hash.clone(),
+                    hash: hash.to_string(),
+                    number: parent.number + 1,
+                    difficulty: *difficulty,
+                    ..Default::default()
+                };
+                t.insert(h.clone());
+                parent = h;
+            }
+        }
+        t
+    }
+
+    #[test]
+    fn longest_chain_breaks_ties_on_lowest_hash() {
+        let t = tree(&[&[("0xbb", 1)], &[("0xaa", 1)]]);
+        let best = choose(&ForkChoiceRule::LongestChain, &t, &Votes::new()).unwrap();
+        assert_eq!(best, "0xaa");
+    }
+
+    #[test]
+    fn heaviest_chain_prefers_work_over_length() {
+        let t = tree(&[&[("0x01", 1), ("0x02", 1), ("0x03", 1)], &[("0x11", 5)]]);
+        let longest = choose(&ForkChoiceRule::LongestChain, &t, &Votes::new()).unwrap();
+        let heaviest = choose(&ForkChoiceRule::HeaviestChain, &t, &Votes::new()).unwrap();
+        assert_eq!(longest, "0x03");
+        assert_eq!(heaviest, "0x11");
+    }
+
+    #[test]
+    fn ghost_follows_heaviest_subtree_not_longest_branch() {
+        // genesis -> a -> {a1, a2} (bushy) vs genesis -> b -> b1 -> b2 (long)
+        let t = tree(&[
+            &[("0xa", 1), ("0xa1", 1)],
+            &[("0xa", 1), ("0xa2", 1)],
+            &[("0xb", 1), ("0xb1", 1), ("0xb2", 1)],
+        ]);
+        let mut votes = Votes::new();
+        votes.insert("p1".into(), "0xa1".into());
+        votes.insert("p2".into(), "0xa2".into());
+
+        let best = choose(&ForkChoiceRule::Ghost, &t, &votes).unwrap();
+        // subtree(a) = 1 + (1+1) + (1+1) = 5, subtree(b) = 3; a1/a2 tie -> lowest hash
+        assert_eq!(best, "0xa1");
+
+        let longest = choose(&ForkChoiceRule::LongestChain, &t, &votes).unwrap();
+        assert_eq!(longest, "0xb2");
+    }
+
+    #[test]
+    fn parses_rule_names() {
+        assert_eq!("ghost".parse::<ForkChoiceRule>(), Ok(ForkChoiceRule::Ghost));
+        assert!("bogus".parse::<ForkChoiceRule>().is_err());
+    }
 }
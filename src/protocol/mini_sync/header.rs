@@ -1,8 +1,93 @@
+use k256::ecdsa::{Signature, VerifyingKey};
+use rlp::RlpStream;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::crypto::{keccak256, sign_message, verify_signature, Keypair};
+use crate::protocol::tx::transaction::Transaction;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Header {
     pub parent_hash: String,
     pub hash: String,
     pub number: u64,
+    // work carried by this header; feeds the heaviest-chain and GHOST rules
+    #[serde(default = "default_difficulty")]
+    pub difficulty: u64,
+    #[serde(default)]
+    pub timestamp: u64, // unix seconds
+    // commits to the block's transactions, see `tx_root()`
+    #[serde(default = "empty_tx_root")]
+    pub tx_root: String,
+    #[serde(default)]
+    pub extra_data: Vec<u8>,
+    #[serde(default)]
+    pub producer: String, // hex SEC1-compressed secp256k1 pubkey
+    #[serde(default)]
+    pub signature: String, // hex r||s over `hash`
+}
+
+fn default_difficulty() -> u64 {
+    1
+}
+
+/// keccak256 over the RLP list of transaction hashes, in block order.
+pub fn tx_root(txs: &[Transaction]) -> String {
+    let mut s = RlpStream::new_list(txs.len());
+    for tx in txs {
+        s.append(&tx.hash);
+    }
+    format!("0x{}", hex::encode(keccak256(&s.out())))
+}
+
+/// Root of a block without transactions; such blocks have nothing to fetch.
+pub fn empty_tx_root() -> String {
+    tx_root(&[])
+}
+
+impl Header {
+    /// keccak256 over the RLP of every field except `hash` and `signature`.
+    pub fn compute_hash(&self) -> String {
+        format!("0x{}", hex::encode(self.seal_digest()))
+    }
+
+    fn seal_digest(&self) -> [u8; 32] {
+        let mut s = RlpStream::new_list(7);
+        s.append(&self.parent_hash);
+        s.append(&self.number);
+        s.append(&self.difficulty);
+        s.append(&self.timestamp);
+        s.append(&self.tx_root);
+        s.append(&self.extra_data);
+        s.append(&self.producer);
+        keccak256(&s.out())
+    }
+
+    /// Fill in `producer`, `hash` and `signature` using the producer's key.
+    pub fn seal(mut self, keypair: &Keypair) -> Self {
+        self.producer = hex::encode(keypair.verifying_key.to_sec1_bytes());
+        let digest = self.seal_digest();
+        let sig = sign_message(&keypair.signing_key, &digest).expect("header signing failed");
+        self.hash = format!("0x{}", hex::encode(digest));
+        self.signature = hex::encode(sig.to_bytes());
+        self
+    }
+
+    /// Whether the block has transactions that have to be fetched separately.
+    pub fn has_body(&self) -> bool {
+        self.tx_root != empty_tx_root()
+    }
+
+    /// Check `signature` over `hash` against `producer`.
+    pub fn verify_signature(&self) -> bool {
+        let mut digest = [0u8; 32];
+        let hash = self.hash.trim_start_matches("0x");
+        if hex::decode_to_slice(hash, &mut digest).is_err() {
+            return false;
+        }
+        let Ok(key_bytes) = hex::decode(&self.producer) else { return false };
+        let Ok(sig_bytes) = hex::decode(&self.signature) else { return false };
+        let Ok(key) = VerifyingKey::from_sec1_bytes(&key_bytes) else { return false };
+        let Ok(sig) = Signature::from_slice(&sig_bytes) else { return false };
+        verify_signature(&key, &digest, &sig)
+    }
 }
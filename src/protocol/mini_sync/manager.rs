@@ -1,124 +1,1565 @@
 use std::collections::HashMap;
+use std::time::Instant;
+
+use tokio::sync::broadcast;
 
 use crate::protocol::mini_sync::{
-    chain::Chain,
-    fork_choice::{choose, ForkChoiceRule},
-    message::{MiniSyncMessage, RequestHeaders, Status},
+    ancestor::AncestorSearch,
+    announce::Announcer,
+    backfill::Backfill,
+    block::{Block, Body},
+    bodies::BodySync,
+    download::SkeletonSync,
+    event::{ChainEvent, Reorg},
+    fork_choice::{choose, ForkChoiceRule, Votes},
+    header::Header,
+    orphans::Orphans,
+    message::{
+        Bodies, Hashes, Headers, MiniSyncMessage, NewHeadHashes, NewHeader, NumberHash,
+        RequestBodies, RequestHashes, RequestHeaders, Status,
+    },
+    store::{ChainStore, StoreError},
+    sync::{SyncProgress, SyncService, SyncState},
+    tree::HeaderTree,
+    validation::{unix_now, HeaderError, HeaderValidator, ImportResult, ValidationPipeline},
 };
+use crate::crypto::Keypair;
+use crate::protocol::tx::transaction::Transaction;
+use crate::fork_id::{ForkFilter, ForkId, ForkIdError, IncompatiblePeer};
+use crate::spec::{ChainSpec, Checkpoint};
+use crate::transport::quic::endpoint::peer_host;
+
+// headers this far behind the canonical head are final; forks below get pruned
+const FINALITY_DEPTH: u64 = 64;
+const MAX_HASHES_PER_RESPONSE: usize = 64;
+const MAX_HEADERS_PER_RESPONSE: u64 = 256;
+const MAX_BODIES_PER_RESPONSE: usize = 64;
+// reputation cost of a download request that timed out or came back wrong
+const SLOW_PEER_PENALTY: i32 = -5;
+const BAD_BATCH_PENALTY: i32 = -20;
+// a peer claiming a head further than this past ours is lying, not ahead
+const MAX_HEAD_LEAD: u64 = 1 << 32;
+
+/// Side effects of sync bookkeeping, carried out by the network layer.
+#[derive(Debug)]
+pub enum SyncAction {
+    Send { peer: String, msg: MiniSyncMessage },
+    Score { peer: String, delta: i32 },
+}
 
 #[derive(Debug)]
 pub struct ChainManager {
-    chains: HashMap<String, Chain>, // head_hash -> chain
-    canonical_head: String,
+    tree: HeaderTree,
+    genesis_hash: String,
+    // trusted header we were told to start from but have not fetched yet
+    awaiting_checkpoint: Option<Checkpoint>,
+    backfill: Option<Backfill>,
     rule: ForkChoiceRule,
+    network_id: u64,
+    forks: ForkFilter,
+    votes: Votes,
+    orphans: Orphans,
+    searches: HashMap<String, AncestorSearch>, // peer -> in-flight ancestor search
+    peer_heads: HashMap<String, u64>,          // peer -> last advertised head number
+    download: Option<SkeletonSync>,
+    bodies: BodySync,
+    announcer: Announcer,
+    sync: SyncService,
+    events: broadcast::Sender<ChainEvent>,
+    validator: ValidationPipeline,
+    store: Box<dyn ChainStore>,
 }
 
 impl ChainManager {
-    pub fn new(genesis_hash: String) -> Self {
-        let chain = Chain::new(genesis_hash);
-        let head = chain.head_hash();
+    /// Open the chain on top of `store`, resuming from whatever it already
+    /// holds. Fails if the store was written for a different genesis or
+    /// disagrees with the configured checkpoint.
+    ///
+    /// With a checkpoint and an empty store, the chain stays at genesis until
+    /// a peer serves the checkpoint header; it then becomes the tree's root.
+    pub fn new(spec: &ChainSpec, store: Box<dyn ChainStore>) -> Result<Self, StoreError> {
+        let genesis = spec.genesis_header();
+        let validator = ValidationPipeline::standard(spec.authorities.clone());
+        let checkpoint = spec.checkpoint.clone().filter(|cp| cp.number > 0);
 
-        let mut chains = HashMap::new();
-        chains.insert(head.clone(), chain);
+        let mut mgr = Self {
+            tree: HeaderTree::new(genesis.clone()),
+            genesis_hash: genesis.hash.clone(),
+            awaiting_checkpoint: None,
+            backfill: None,
+            rule: spec.fork_choice,
+            network_id: spec.network_id,
+            forks: ForkFilter::new(&genesis.hash, &spec.forks),
+            votes: Votes::new(),
+            orphans: Orphans::new(),
+            searches: HashMap::new(),
+            peer_heads: HashMap::new(),
+            download: None,
+            bodies: BodySync::new(),
+            announcer: Announcer::new(),
+            sync: SyncService::new(0, Instant::now()),
+            events: broadcast::channel(64).0,
+            validator,
+            store,
+        };
+
+        if let Some(cp) = &checkpoint
+            && let Some(stored) = mgr.store.canonical_hash(cp.number)
+            && stored != cp.hash
+        {
+            return Err(StoreError::CheckpointMismatch {
+                number: cp.number,
+                stored,
+                configured: cp.hash.clone(),
+            });
+        }
+
+        match (mgr.store.canonical_hash(0), mgr.store.head()) {
+            (Some(stored), _) if stored != genesis.hash => {
+                return Err(StoreError::GenesisMismatch {
+                    stored,
+                    configured: genesis.hash,
+                });
+            }
+            (None, None) if checkpoint.is_some() => {
+                let cp = checkpoint.clone().unwrap();
+                println!("[SYNC] waiting for checkpoint #{} {}", cp.number, cp.hash);
+                mgr.awaiting_checkpoint = Some(cp);
+            }
+            (None, None) => {
+                mgr.store.put_header(&genesis)?;
+                mgr.store.set_canonical(0, &genesis.hash)?;
+                mgr.store.set_head(&genesis.hash)?;
+                mgr.store.set_finalized(&genesis.hash)?;
+            }
+            _ => mgr.load()?,
+        }
 
-        Self {
-            chains,
-            canonical_head: head,
-            rule: ForkChoiceRule::LongestChain,
+        // pick up an unfinished backfill after a restart
+        if checkpoint.is_some_and(|cp| cp.backfill)
+            && mgr.awaiting_checkpoint.is_none()
+            && mgr.tree.root().number > 0
+        {
+            mgr.backfill = Some(Backfill::new(mgr.tree.root().clone(), genesis.hash));
         }
+        mgr.sync = SyncService::new(mgr.canonical_height(), Instant::now());
+        Ok(mgr)
+    }
+
+    /// Rebuild the tree from the stored canonical chain. Stored headers were
+    /// validated when first imported, so they are linked in directly.
+    ///
+    /// The root is the lowest header of the contiguous canonical run that
+    /// ends at the head: genesis, or the checkpoint (less any backfill).
+    fn load(&mut self) -> Result<(), StoreError> {
+        let head = self.store.head().and_then(|h| self.store.header(&h));
+        let head_number = head.map(|h| h.number).unwrap_or(0);
+
+        let mut low = head_number;
+        while low > 0 && self.store.canonical_hash(low - 1).is_some() {
+            low -= 1;
+        }
+        let root = self.store.canonical_hash(low).and_then(|h| self.store.header(&h));
+        let Some(root) = root else {
+            return Err(StoreError::Corrupt {
+                line: 0,
+                reason: format!("canonical header #{low} missing"),
+            });
+        };
+        self.tree = HeaderTree::new(root);
+
+        for n in low + 1..=head_number {
+            let header = self.store.canonical_hash(n).and_then(|h| self.store.header(&h));
+            let Some(header) = header else {
+                return Err(StoreError::Corrupt {
+                    line: 0,
+                    reason: format!("canonical header #{n} missing"),
+                });
+            };
+            let hash = header.hash.clone();
+            self.want_body(&header);
+            self.tree.insert(header);
+            self.tree.set_head(&hash);
+        }
+        if let Some(fin) = self.store.finalized() {
+            self.tree.finalize(&fin);
+        }
+
+        if head_number > 0 {
+            println!(
+                "[STORE] resumed at #{} {} (finalized #{}, root #{})",
+                head_number,
+                self.tree.head_hash(),
+                self.tree.finalized().number,
+                low
+            );
+        }
+        Ok(())
+    }
+
+    /// Make the trusted checkpoint header the root of the chain.
+    fn anchor(&mut self, header: Header) -> Result<(), StoreError> {
+        println!("[SYNC] anchored at checkpoint #{} {}", header.number, header.hash);
+        self.store.put_header(&header)?;
+        self.store.set_canonical(header.number, &header.hash)?;
+        self.store.set_head(&header.hash)?;
+        self.store.set_finalized(&header.hash)?;
+
+        let backfill = self.awaiting_checkpoint.take().is_some_and(|cp| cp.backfill);
+        if backfill {
+            self.backfill = Some(Backfill::new(header.clone(), self.genesis_hash.clone()));
+        }
+        self.tree = HeaderTree::new(header);
+        self.sync.observe_head(self.tree.height(), Instant::now());
+        Ok(())
+    }
+
+    /// Receive head changes and reorgs as they happen.
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
+    /// Receive every sync state change and progress update.
+    pub fn subscribe_sync(&self) -> broadcast::Receiver<SyncProgress> {
+        self.sync.subscribe()
+    }
+
+    pub fn sync_progress(&self) -> SyncProgress {
+        self.sync.progress()
     }
 
     // -------- Canonical accessors --------
 
-    pub fn canonical(&self) -> &Chain {
-        self.chains
-            .get(&self.canonical_head)
-            .expect("canonical exists")
+    pub fn tree(&self) -> &HeaderTree {
+        &self.tree
     }
 
     pub fn canonical_height(&self) -> u64 {
-        self.canonical().height()
+        self.tree.height()
     }
 
     pub fn canonical_head_hash(&self) -> String {
-        self.canonical().head_hash()
+        self.tree.head_hash().to_string()
     }
 
     pub fn genesis_hash(&self) -> String {
-        // genesis header is always headers[0]
-        self.canonical().headers[0].hash.clone()
+        self.genesis_hash.clone()
+    }
+
+    pub fn network_id(&self) -> u64 {
+        self.network_id
+    }
+
+    /// Our EIP-2124 fork id at the current canonical height.
+    pub fn fork_id(&self) -> ForkId {
+        self.forks.fork_id(self.canonical_height())
+    }
+
+    pub fn check_fork_id(&self, remote: &ForkId) -> Result<(), ForkIdError> {
+        self.forks.check(self.canonical_height(), remote)
+    }
+
+    /// Whether a peer advertising this chain identity belongs on our network.
+    pub fn check_peer(
+        &self,
+        network_id: u64,
+        genesis: &str,
+        fork_id: &ForkId,
+    ) -> Result<(), IncompatiblePeer> {
+        if network_id != self.network_id {
+            return Err(IncompatiblePeer::NetworkId {
+                local: self.network_id,
+                remote: network_id,
+            });
+        }
+        if genesis != self.genesis_hash() {
+            return Err(IncompatiblePeer::Genesis {
+                local: self.genesis_hash(),
+                remote: genesis.to_string(),
+            });
+        }
+        Ok(self.check_fork_id(fork_id)?)
     }
 
     pub fn status(&self) -> Status {
         Status {
+            network_id: self.network_id,
+            fork_id: self.fork_id(),
             genesis_hash: self.genesis_hash(),
             head_hash: self.canonical_head_hash(),
             head_number: self.canonical_height(),
         }
     }
 
-    /// Clone the canonical chain so we can extend it as a candidate fork.
-    pub fn canonical_clone(&self) -> Chain {
-        self.canonical().clone()
+    // -------- Fork mgmt --------
+
+    pub fn recompute(&mut self) {
+        let Some(new_head) = choose(&self.rule, &self.tree, &self.votes) else {
+            return;
+        };
+        if new_head == self.tree.head_hash() {
+            return;
+        }
+
+        let old_head = self.canonical_head_hash();
+        let (dropped, added) = self.tree.set_head(&new_head);
+
+        let height = self.tree.height();
+        if height > FINALITY_DEPTH {
+            let fin = self.tree.canonical_hash(height - FINALITY_DEPTH).map(str::to_string);
+            if let Some(fin) = fin {
+                self.tree.finalize(&fin);
+            }
+        }
+
+        if let Err(e) = self.persist_canonical(&added) {
+            println!("[STORE] failed to persist head {}: {}", new_head, e);
+        }
+        for hash in &added {
+            if let Some(node) = self.tree.get(hash) {
+                let header = node.header.clone();
+                self.want_body(&header);
+            }
+        }
+        self.sync.observe_head(self.tree.height(), Instant::now());
+
+        let event = if dropped.is_empty() {
+            ChainEvent::NewHead {
+                hash: new_head,
+                number: self.tree.height(),
+            }
+        } else {
+            println!(
+                "[FORK] reorg {} -> {} depth={} (height={}, rule={})",
+                old_head,
+                new_head,
+                dropped.len(),
+                self.tree.height(),
+                self.rule
+            );
+            ChainEvent::Reorg(Reorg {
+                old_head,
+                new_head,
+                depth: dropped.len() as u64,
+                dropped,
+                added,
+            })
+        };
+        // nobody listening is fine
+        let _ = self.events.send(event);
     }
 
-    // -------- Fork mgmt --------
+    /// Write the canonical index entries that changed and the head/finalized
+    /// pointers. `added` is the new canonical suffix, lowest number first.
+    fn persist_canonical(&mut self, added: &[String]) -> Result<(), StoreError> {
+        let height = self.tree.height();
+        let first = height + 1 - added.len() as u64;
+        for (i, hash) in added.iter().enumerate() {
+            self.store.set_canonical(first + i as u64, hash)?;
+        }
+        self.store.truncate_canonical(height)?;
+        self.store.set_head(self.tree.head_hash())?;
+        let finalized = &self.tree.finalized().hash;
+        if self.store.finalized().as_ref() != Some(finalized) {
+            self.store.set_finalized(finalized)?;
+        }
+        Ok(())
+    }
 
-    pub fn insert_chain(&mut self, chain: Chain) {
-        let head = chain.head_hash();
-        self.chains.insert(head, chain);
-        self.recompute();
+    /// Record `peer`'s latest head. Only GHOST looks at votes, and only the
+    /// most recent one per voter counts. A voter is the peer's IP, so more
+    /// connections from one host buy no extra weight.
+    pub fn record_vote(&mut self, peer: &str, head_hash: String) {
+        let voter = peer_host(peer);
+        if self.votes.get(&voter) == Some(&head_hash) {
+            return;
+        }
+        self.votes.insert(voter, head_hash);
+        if self.rule == ForkChoiceRule::Ghost {
+            self.recompute();
+        }
     }
 
-    pub fn recompute(&mut self) {
-        let all: Vec<Chain> = self.chains.values().cloned().collect();
-        if let Some(best) = choose(&self.rule, &all) {
-            let new_head = best.head_hash();
-            if new_head != self.canonical_head {
-                println!(
-                    "[FORK] canonical switch {} -> {} (height={})",
-                    self.canonical_head,
-                    new_head,
-                    best.height()
-                );
-                self.canonical_head = new_head;
-            }
+    /// Build a block with `transactions` on top of the canonical head
+    /// (producer nodes only). Nothing is built while syncing, and a block our
+    /// own validators reject is dropped along with its body.
+    pub fn produce_block(&mut self, keypair: &Keypair, transactions: Vec<Transaction>) -> Option<Block> {
+        if self.awaiting_checkpoint.is_some() || self.download.is_some() {
+            return None;
         }
+        let parent = self.tree.head();
+
+        let header = Header {
+            parent_hash: parent.hash.clone(),
+            number: parent.number + 1,
+            difficulty: 1,
+            timestamp: unix_now().max(parent.timestamp + 1),
+            ..Default::default()
+        };
+        let block = Block::seal(header, transactions, keypair);
+
+        let mut result = ImportResult::default();
+        self.import_one(block.header.clone(), None, &mut result);
+        if let Some((hash, err)) = result.invalid.first() {
+            println!("[MINE] own block {} rejected: {}", hash, err);
+            return None;
+        }
+        if block.header.has_body() {
+            self.store_body(block.header.number, &block.header.hash, block.transactions.clone());
+        }
+        self.recompute();
+        Some(block)
     }
 
     // -------- Sync helpers (7C integration) --------
 
+    /// Note a peer's Status (its head, and its GHOST vote) and return the
+    /// first sync message to send it, if any.
+    pub fn on_status(&mut self, peer: &str, remote: &Status) -> Option<MiniSyncMessage> {
+        if self
+            .check_peer(remote.network_id, &remote.genesis_hash, &remote.fork_id)
+            .is_err()
+        {
+            return None;
+        }
+        if !self.plausible_head(remote.head_number) {
+            return None;
+        }
+        // a peer's advertised head is its latest vote for GHOST
+        self.record_vote(peer, remote.head_hash.clone());
+        self.peer_heads.insert(peer.to_string(), remote.head_number);
+        self.sync.observe_peer_head(remote.head_number);
+
+        if let Some(cp) = &self.awaiting_checkpoint {
+            // nothing to sync against until the checkpoint header is in hand
+            return (remote.head_number >= cp.number).then_some(MiniSyncMessage::RequestHeaders(
+                RequestHeaders {
+                    start: cp.number,
+                    count: 1,
+                    skip: 0,
+                },
+            ));
+        }
+
+        if let Some(dl) = &mut self.download {
+            // already downloading: the peer gets a batch on the next tick
+            dl.add_peer(peer, remote.head_number);
+            return None;
+        }
+        let req = self
+            .should_request(remote)
+            .then(|| self.build_request(peer, remote));
+        self.settle_sync();
+        req
+    }
+
+    /// Pick the resting sync state once nothing is downloading.
+    fn settle_sync(&mut self) {
+        if self.download.is_some() {
+            return;
+        }
+        let state = if !self.searches.is_empty() {
+            SyncState::FindingAncestor
+        } else if self.sync.caught_up() {
+            SyncState::Synced
+        } else {
+            SyncState::Idle
+        };
+        self.sync.transition(state, Instant::now());
+    }
+
     pub fn should_request(&self, remote: &Status) -> bool {
-        // same network?
-        if remote.genesis_hash != self.genesis_hash() {
+        // same network, same rules?
+        if self
+            .check_peer(remote.network_id, &remote.genesis_hash, &remote.fork_id)
+            .is_err()
+        {
+            return false;
+        }
+        if !self.plausible_head(remote.head_number) {
             return false;
         }
-        remote.head_number > self.canonical_height()
+        // anything we don't already have is worth a look, even if it is not
+        // ahead of us: the fork-choice rule decides whether it wins
+        remote.head_number > self.tree.finalized().number && !self.tree.contains(&remote.head_hash)
     }
 
-    pub fn build_request(&self, remote: &Status) -> MiniSyncMessage {
-        let local_h = self.canonical_height();
-        MiniSyncMessage::RequestHeaders(RequestHeaders {
-            start: local_h + 1,
-            count: remote.head_number - local_h,
-        })
+    /// Start (or restart) locating the common ancestor with `peer`.
+    pub fn build_request(&mut self, peer: &str, remote: &Status) -> MiniSyncMessage {
+        self.start_search(peer, remote.head_number)
     }
 
-    pub fn import_headers(&mut self, headers: Vec<crate::protocol::mini_sync::header::Header>) {
-        if headers.is_empty() {
+    fn start_search(&mut self, peer: &str, remote_head: u64) -> MiniSyncMessage {
+        // nothing at or below the root can differ, so never probe there
+        let floor = self.tree.root().number;
+        let search = AncestorSearch::above(floor, self.canonical_height(), remote_head);
+        self.searches.insert(peer.to_string(), search);
+        self.advance_search(peer).expect("search was just inserted")
+    }
+
+    /// Feed a `Hashes` reply into the ancestor search for `peer` and return the
+    /// next message to send: another probe, or the header download once the
+    /// ancestor is pinned down.
+    pub fn on_hashes(&mut self, peer: &str, hashes: Hashes) -> Option<MiniSyncMessage> {
+        let search = self.searches.get_mut(peer)?;
+        if hashes.hashes.is_empty() {
+            // peer lost the height it advertised; try again on its next Status
+            self.searches.remove(peer);
+            self.settle_sync();
+            return None;
+        }
+        for nh in hashes.hashes {
+            search.on_reply(nh.number, self.tree.contains(&nh.hash));
+        }
+        let next = self.advance_search(peer);
+        self.settle_sync();
+        next
+    }
+
+    fn advance_search(&mut self, peer: &str) -> Option<MiniSyncMessage> {
+        let search = self.searches.get(peer)?;
+        if let Some(n) = search.next_probe() {
+            return Some(MiniSyncMessage::RequestHashes(RequestHashes {
+                numbers: vec![n],
+            }));
+        }
+
+        let search = self.searches.remove(peer)?;
+        let ancestor = search.ancestor()?;
+        if ancestor > 0 {
+            println!("[SYNC] common ancestor with {} at #{}", peer, ancestor);
+        }
+        if ancestor >= search.remote_head {
+            return None;
+        }
+        self.start_download(peer, ancestor + 1, search.remote_head)
+    }
+
+    /// Begin a skeleton download round led by `peer`, or enlist `peer` in
+    /// the one already running.
+    fn start_download(&mut self, peer: &str, start: u64, head: u64) -> Option<MiniSyncMessage> {
+        let now = Instant::now();
+        if let Some(dl) = &mut self.download {
+            dl.add_peer(peer, head);
+            return dl.assign(peer, now).map(MiniSyncMessage::RequestHeaders);
+        }
+
+        let (mut dl, req) = SkeletonSync::new(peer, start, head, now);
+        for (p, &h) in &self.peer_heads {
+            if p != peer && h >= start {
+                dl.add_peer(p, h);
+            }
+        }
+        println!("[SYNC] downloading #{}..#{} (skeleton from {})", start, head, peer);
+        self.download = Some(dl);
+        self.sync.transition(SyncState::Downloading, now);
+        Some(MiniSyncMessage::RequestHeaders(req))
+    }
+
+    /// Route a `Headers` reply: into the running download if it answers one of
+    /// its requests, otherwise straight into the tree.
+    pub fn on_headers(&mut self, peer: &str, headers: Vec<Header>) -> Vec<SyncAction> {
+        if self.awaiting_checkpoint.is_some() {
+            return self.on_checkpoint_header(peer, headers);
+        }
+        let below_root = headers.first().is_some_and(|h| h.number < self.tree.root().number);
+        if below_root && self.backfill.as_ref().is_some_and(|bf| bf.is_waiting_on(peer)) {
+            return self.on_backfill_headers(peer, headers);
+        }
+
+        let mut actions = vec![];
+        let Some(dl) = self.download.as_mut().filter(|dl| dl.is_waiting_on(peer)) else {
+            let result = self.import_from(Some(peer), headers);
+            actions.push(SyncAction::Score {
+                peer: peer.to_string(),
+                delta: result.score_delta(),
+            });
+            self.body_tick(&mut actions, Instant::now());
+            return actions;
+        };
+
+        if let Err(e) = dl.on_headers(peer, headers) {
+            println!("[SYNC] bad download reply from {}: {}", peer, e);
+            actions.push(SyncAction::Score {
+                peer: peer.to_string(),
+                delta: BAD_BATCH_PENALTY,
+            });
+        }
+        if let Some(req) = dl.assign(peer, Instant::now()) {
+            actions.push(SyncAction::Send {
+                peer: peer.to_string(),
+                msg: MiniSyncMessage::RequestHeaders(req),
+            });
+        }
+        self.import_ready(&mut actions);
+        self.body_tick(&mut actions, Instant::now());
+        actions
+    }
+
+    fn on_checkpoint_header(&mut self, peer: &str, headers: Vec<Header>) -> Vec<SyncAction> {
+        let Some(cp) = self.awaiting_checkpoint.clone() else { return vec![] };
+        let Some(h) = headers.into_iter().find(|h| h.number == cp.number) else {
+            return vec![];
+        };
+        let score = |delta| SyncAction::Score {
+            peer: peer.to_string(),
+            delta,
+        };
+
+        if h.hash != cp.hash || h.compute_hash() != h.hash {
+            println!("[SYNC] {} served #{} {}, not the checkpoint", peer, h.number, h.hash);
+            return vec![score(BAD_BATCH_PENALTY)];
+        }
+        if let Err(e) = self.anchor(h) {
+            println!("[STORE] failed to persist checkpoint: {}", e);
+            return vec![];
+        }
+
+        let mut actions = vec![score(1)];
+        let remote_head = self.peer_heads.get(peer).copied().unwrap_or(0);
+        if remote_head > cp.number {
+            actions.push(SyncAction::Send {
+                peer: peer.to_string(),
+                msg: self.start_search(peer, remote_head),
+            });
+        }
+        self.settle_sync();
+        actions
+    }
+
+    fn on_backfill_headers(&mut self, peer: &str, headers: Vec<Header>) -> Vec<SyncAction> {
+        let Some(bf) = self.backfill.as_mut() else { return vec![] };
+        let delta = match bf.on_headers(peer, headers, &self.validator) {
+            Ok(headers) => {
+                let n = headers.len() as i32;
+                for h in &headers {
+                    let stored = self
+                        .store
+                        .put_header(h)
+                        .and_then(|_| self.store.set_canonical(h.number, &h.hash));
+                    if let Err(e) = stored {
+                        println!("[STORE] failed to persist backfilled header: {}", e);
+                    }
+                }
+                n.min(10)
+            }
+            Err(e) => {
+                println!("[SYNC] bad backfill reply from {}: {}", peer, e);
+                BAD_BATCH_PENALTY
+            }
+        };
+
+        let mut actions = vec![SyncAction::Score {
+            peer: peer.to_string(),
+            delta,
+        }];
+        self.backfill_tick(&mut actions, Instant::now());
+        actions
+    }
+
+    /// Keep one backfill request in flight until genesis is reached.
+    fn backfill_tick(&mut self, actions: &mut Vec<SyncAction>, now: Instant) {
+        let Some(bf) = self.backfill.as_mut() else { return };
+        if bf.is_done() {
+            println!("[SYNC] backfill reached genesis");
+            self.backfill = None;
+            return;
+        }
+        if let Some(peer) = bf.expire(now) {
+            actions.push(SyncAction::Score {
+                peer,
+                delta: SLOW_PEER_PENALTY,
+            });
+        }
+        let root = self.tree.root().number;
+        let peers = self.peer_heads.iter().filter(|(_, h)| **h >= root).map(|(p, _)| p);
+        if let Some((peer, req)) = bf.next_request(peers, now) {
+            actions.push(SyncAction::Send {
+                peer,
+                msg: MiniSyncMessage::RequestHeaders(req),
+            });
+        }
+    }
+
+    /// Periodic download upkeep: time out slow peers and hand out batches.
+    pub fn download_tick(&mut self) -> Vec<SyncAction> {
+        let mut actions = vec![];
+        let now = Instant::now();
+        self.backfill_tick(&mut actions, now);
+        self.body_tick(&mut actions, now);
+        let Some(dl) = self.download.as_mut() else { return actions };
+
+        for peer in dl.expire(now) {
+            println!("[SYNC] download request to {} timed out", peer);
+            actions.push(SyncAction::Score {
+                peer,
+                delta: SLOW_PEER_PENALTY,
+            });
+        }
+        for (peer, req) in dl.assign_idle(now) {
+            actions.push(SyncAction::Send {
+                peer,
+                msg: MiniSyncMessage::RequestHeaders(req),
+            });
+        }
+        self.import_ready(&mut actions);
+        actions
+    }
+
+    /// Import completed batches in order; end the round when it is done or broken.
+    fn import_ready(&mut self, actions: &mut Vec<SyncAction>) {
+        let Some(dl) = self.download.as_mut() else { return };
+        let ready = dl.take_ready();
+        if !ready.is_empty() {
+            self.sync.transition(SyncState::Importing, Instant::now());
+        }
+
+        let mut poisoned = false;
+        for (peer, headers) in ready {
+            if poisoned {
+                break;
+            }
+            let result = self.import_from(Some(&peer), headers);
+            poisoned = !result.invalid.is_empty();
+            actions.push(SyncAction::Score {
+                peer,
+                delta: result.score_delta(),
+            });
+        }
+
+        let Some(dl) = &self.download else { return };
+        if poisoned || dl.failed() {
+            println!("[SYNC] download round aborted at #{}", dl.next_to_import());
+            self.download = None;
+        } else if dl.is_finished() {
+            println!("[SYNC] download round complete up to #{}", dl.target());
+            self.download = None;
+        } else {
+            self.sync.transition(SyncState::Downloading, Instant::now());
+        }
+        self.settle_sync();
+    }
+
+    // -------- Head announcements --------
+
+    /// Tell `peers` about our current head: the full header to a few of them,
+    /// just the hash to the rest. Quiet while catching up, when our head is
+    /// old news to everyone we are downloading from.
+    pub fn announce_head(&mut self, peers: &[String]) -> Vec<SyncAction> {
+        if self.download.is_some() || self.awaiting_checkpoint.is_some() {
+            return vec![];
+        }
+        let head = self.tree.head().clone();
+        let (push, announce) = self.announcer.plan(&head.hash, peers);
+        let hashes = vec![NumberHash {
+            number: head.number,
+            hash: head.hash.clone(),
+        }];
+
+        let push = push.into_iter().map(|peer| SyncAction::Send {
+            peer,
+            msg: MiniSyncMessage::NewHeader(NewHeader {
+                header: head.clone(),
+            }),
+        });
+        let announce = announce.into_iter().map(|peer| SyncAction::Send {
+            peer,
+            msg: MiniSyncMessage::NewHeadHashes(NewHeadHashes {
+                hashes: hashes.clone(),
+            }),
+        });
+        push.chain(announce).collect()
+    }
+
+    /// A peer pushed a header it just made canonical. Import it if we hold
+    /// its parent, otherwise go find the gap as if it had sent a Status.
+    pub fn on_new_header(&mut self, peer: &str, header: Header) -> Vec<SyncAction> {
+        let first = self.announcer.mark(peer, &header.hash);
+        if self.tree.contains(&header.hash) || !self.plausible_head(header.number) {
+            return vec![];
+        }
+        self.note_peer_head(peer, header.number);
+        if self.awaiting_checkpoint.is_some() || self.download.is_some() {
+            return vec![];
+        }
+
+        if self.tree.contains(&header.parent_hash) {
+            let hash = header.hash.clone();
+            let result = self.import_from(Some(peer), vec![header]);
+            self.record_vote(peer, hash);
+            self.settle_sync();
+            return vec![SyncAction::Score {
+                peer: peer.to_string(),
+                delta: result.score_delta(),
+            }];
+        }
+        // a repeat we still cannot place: the first copy already started a search
+        if !first {
+            return vec![];
+        }
+        self.record_vote(peer, header.hash);
+        self.catch_up(peer, header.number)
+    }
+
+    /// A peer announced new heads by hash. Fetch the highest one we have not
+    /// seen yet, or search for the ancestor if it is more than a step ahead.
+    pub fn on_new_head_hashes(&mut self, peer: &str, hashes: Vec<NumberHash>) -> Vec<SyncAction> {
+        let limit = self.head_limit();
+        let top = hashes
+            .into_iter()
+            .filter(|nh| nh.number <= limit)
+            .filter(|nh| self.announcer.mark(peer, &nh.hash) && !self.tree.contains(&nh.hash))
+            .max_by_key(|nh| nh.number);
+        let Some(top) = top else { return vec![] };
+
+        self.note_peer_head(peer, top.number);
+        self.record_vote(peer, top.hash);
+        if self.awaiting_checkpoint.is_some() || self.download.is_some() {
+            return vec![];
+        }
+        if top.number > self.canonical_height().saturating_add(1) {
+            return self.catch_up(peer, top.number);
+        }
+        vec![SyncAction::Send {
+            peer: peer.to_string(),
+            msg: MiniSyncMessage::RequestHeaders(RequestHeaders {
+                start: top.number,
+                count: 1,
+                skip: 0,
+            }),
+        }]
+    }
+
+    /// Drop what we track about a peer whose connection went away, which
+    /// includes every peer we ban.
+    pub fn on_disconnect(&mut self, peer: &str) {
+        self.peer_heads.remove(peer);
+        self.orphans.forget_peer(peer);
+        self.searches.remove(peer);
+        self.announcer.forget_peer(peer);
+        if self.votes.remove(&peer_host(peer)).is_some() && self.rule == ForkChoiceRule::Ghost {
+            self.recompute();
+        }
+        self.settle_sync();
+    }
+
+    // heads are capped before anything is planned around them, so no search
+    // or download ever spans more than MAX_HEAD_LEAD headers
+    fn head_limit(&self) -> u64 {
+        let ours = self.awaiting_checkpoint.as_ref().map_or(0, |cp| cp.number).max(self.canonical_height());
+        ours.saturating_add(MAX_HEAD_LEAD)
+    }
+
+    fn plausible_head(&self, number: u64) -> bool {
+        number <= self.head_limit()
+    }
+
+    // announcements only ever raise what we believe a peer has
+    fn note_peer_head(&mut self, peer: &str, number: u64) {
+        let head = self.peer_heads.entry(peer.to_string()).or_default();
+        *head = (*head).max(number);
+        self.sync.observe_peer_head(number);
+        if let Some(dl) = &mut self.download {
+            dl.add_peer(peer, number);
+        }
+    }
+
+    fn catch_up(&mut self, peer: &str, remote_head: u64) -> Vec<SyncAction> {
+        if remote_head <= self.tree.finalized().number || self.searches.contains_key(peer) {
+            return vec![];
+        }
+        let msg = self.start_search(peer, remote_head);
+        self.settle_sync();
+        vec![SyncAction::Send {
+            peer: peer.to_string(),
+            msg,
+        }]
+    }
+
+    // -------- Block bodies --------
+
+    fn want_body(&mut self, header: &Header) {
+        if header.has_body() && self.store.body(&header.hash).is_none() {
+            self.bodies.enqueue(header.number, &header.hash, &header.tx_root);
+        }
+    }
+
+    fn store_body(&mut self, number: u64, hash: &str, transactions: Vec<Transaction>) {
+        if let Err(e) = self.store.put_body(hash, &transactions) {
+            println!("[STORE] failed to persist body of {}: {}", hash, e);
             return;
         }
+        let _ = self.events.send(ChainEvent::Body {
+            hash: hash.to_string(),
+            number,
+            transactions,
+        });
+    }
+
+    /// Keep every idle peer busy with missing bodies it can serve.
+    fn body_tick(&mut self, actions: &mut Vec<SyncAction>, now: Instant) {
+        for peer in self.bodies.expire(now) {
+            println!("[SYNC] body request to {} timed out", peer);
+            actions.push(SyncAction::Score {
+                peer,
+                delta: SLOW_PEER_PENALTY,
+            });
+        }
+        for (peer, &head) in &self.peer_heads {
+            if let Some(req) = self.bodies.assign(peer, head, now) {
+                actions.push(SyncAction::Send {
+                    peer: peer.clone(),
+                    msg: MiniSyncMessage::RequestBodies(req),
+                });
+            }
+        }
+    }
+
+    /// Store the bodies in a `Bodies` reply once they match their headers.
+    pub fn on_bodies(&mut self, peer: &str, bodies: Vec<Body>) -> Vec<SyncAction> {
+        let delta = match self.bodies.on_bodies(peer, bodies) {
+            Ok(done) => {
+                let n = done.len() as i32;
+                for (number, hash, txs) in done {
+                    self.store_body(number, &hash, txs);
+                }
+                if self.bodies.remaining() == 0 && n > 0 {
+                    println!("[SYNC] all block bodies fetched up to #{}", self.canonical_height());
+                }
+                n.min(10)
+            }
+            Err(e) => {
+                println!("[SYNC] bad bodies from {}: {}", peer, e);
+                BAD_BATCH_PENALTY
+            }
+        };
+
+        let mut actions = vec![SyncAction::Score {
+            peer: peer.to_string(),
+            delta,
+        }];
+        self.body_tick(&mut actions, Instant::now());
+        actions
+    }
+
+    /// Header and transactions of a block, if we hold both.
+    pub fn block(&self, hash: &str) -> Option<Block> {
+        let header = match self.tree.get(hash) {
+            Some(node) => node.header.clone(),
+            None => self.store.header(hash)?,
+        };
+        let transactions = match header.has_body() {
+            true => self.store.body(hash)?,
+            false => vec![],
+        };
+        Some(Block { header, transactions })
+    }
+
+    // -------- Serving peers --------
+
+    /// Canonical hash at `number`, including backfilled history below the root.
+    pub fn canonical_hash_at(&self, number: u64) -> Option<String> {
+        match self.tree.canonical_hash(number) {
+            Some(h) => Some(h.to_string()),
+            None if number < self.tree.root().number => self.store.canonical_hash(number),
+            None => None,
+        }
+    }
+
+    fn canonical_header_at(&self, number: u64) -> Option<Header> {
+        match self.tree.canonical_header(number) {
+            Some(h) => Some(h.clone()),
+            None => self.store.header(&self.canonical_hash_at(number)?),
+        }
+    }
+
+    pub fn hashes_for(&self, req: &RequestHashes) -> Hashes {
+        let hashes = req
+            .numbers
+            .iter()
+            .take(MAX_HASHES_PER_RESPONSE)
+            .filter_map(|&number| {
+                let hash = self.canonical_hash_at(number)?;
+                Some(NumberHash { number, hash })
+            })
+            .collect();
+        Hashes { hashes }
+    }
+
+    pub fn bodies_for(&self, req: &RequestBodies) -> Bodies {
+        let bodies = req
+            .hashes
+            .iter()
+            .take(MAX_BODIES_PER_RESPONSE)
+            .filter_map(|h| self.block(h))
+            .map(|b| b.body())
+            .collect();
+        Bodies { bodies }
+    }
+
+    pub fn headers_for(&self, req: &RequestHeaders) -> Headers {
+        let count = req.count.min(MAX_HEADERS_PER_RESPONSE);
+        let step = req.skip.saturating_add(1);
+        let headers = (0..count)
+            .map_while(|i| {
+                let n = req.start.checked_add(i.checked_mul(step)?)?;
+                self.canonical_header_at(n)
+            })
+            .collect();
+        Headers { headers }
+    }
+
+    /// Validate and import headers from any branch we know the ancestor of.
+    ///
+    /// Each header hangs off whatever known header its `parent_hash` names, so
+    /// a batch that forks from a historical block becomes a side branch and
+    /// can win fork choice. Headers whose parent is unknown are parked until
+    /// the parent shows up.
+    pub fn import_headers(&mut self, headers: Vec<Header>) -> ImportResult {
+        self.import_from(None, headers)
+    }
+
+    // `peer` served the headers; it answers for the orphans among them
+    fn import_from(&mut self, peer: Option<&str>, mut headers: Vec<Header>) -> ImportResult {
+        let mut result = ImportResult::default();
+        if headers.is_empty() {
+            return result;
+        }
 
         let before = self.canonical_height();
 
-        // extend canonical as a candidate fork
-        let mut candidate = self.canonical_clone();
-        candidate.append_linear(headers);
-        self.insert_chain(candidate);
+        headers.sort_by_key(|h| h.number);
+        for h in headers {
+            self.import_one(h, peer, &mut result);
+        }
+        if !result.accepted.is_empty() {
+            self.recompute();
+        }
+        for (hash, err) in &result.invalid {
+            println!("[SYNC] rejected header {}: {}", hash, err);
+        }
 
         let after = self.canonical_height();
         if after > before {
             println!("[SYNC] advanced canonical head {} -> {}", before, after);
         }
+        result
+    }
+
+    fn import_one(&mut self, h: Header, peer: Option<&str>, result: &mut ImportResult) {
+        if self.tree.contains(&h.hash) {
+            result.known.push(h.hash);
+            return;
+        }
+        let finalized = self.tree.finalized().number;
+        if h.number <= finalized {
+            let err = HeaderError::BelowFinalized {
+                number: h.number,
+                finalized,
+            };
+            result.invalid.push((h.hash, err));
+            return;
+        }
+        let Some(parent) = self.tree.get(&h.parent_hash) else {
+            result.orphaned.push(h.hash.clone());
+            self.orphans.park(h, peer, Instant::now());
+            return;
+        };
+        if let Err(err) = self.validator.validate(&h, &parent.header) {
+            result.invalid.push((h.hash, err));
+            return;
+        }
+
+        let hash = h.hash.clone();
+        let ancestor = h.number - 1;
+        let forks_canonical = h.parent_hash != self.tree.head_hash()
+            && self.tree.canonical_hash(ancestor) == Some(h.parent_hash.as_str());
+        if !self.tree.insert(h) {
+            result.known.push(hash);
+            return;
+        }
+        if forks_canonical {
+            println!("[FORK] side branch {} from canonical ancestor #{}", hash, ancestor);
+        }
+        if let Err(e) = self.store.put_header(&self.tree.get(&hash).unwrap().header) {
+            println!("[STORE] failed to persist header {}: {}", hash, e);
+        }
+        result.accepted.push(hash.clone());
+
+        // anything that was waiting on this header can now attach too
+        for child in self.orphans.children_of(&hash) {
+            self.import_one(child, peer, result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::crypto::generate_keypair;
+    use crate::protocol::mini_sync::header::empty_tx_root;
+    use crate::protocol::mini_sync::store::{FileStore, MemoryStore};
+
+    fn manager() -> ChainManager {
+        ChainManager::new(&ChainSpec::dev(), Box::new(MemoryStore::new())).unwrap()
+    }
+
+    // `tag` goes into extra_data so different branches get different hashes
+    fn branch(parent: &Header, tag: &str, len: u64) -> Vec<Header> {
+        let kp = generate_keypair().unwrap();
+        let mut out: Vec<Header> = vec![];
+        for _ in 0..len {
+            let p = out.last().unwrap_or(parent);
+            out.push(
+                Header {
+                    parent_hash: p.hash.clone(),
+                    number: p.number + 1,
+                    difficulty: 1,
+                    timestamp: p.timestamp + 1,
+                    tx_root: empty_tx_root(),
+                    extra_data: tag.as_bytes().to_vec(),
+                    ..Default::default()
+                }
+                .seal(&kp),
+            );
+        }
+        out
+    }
+
+    fn hashes(hs: &[Header]) -> Vec<String> {
+        hs.iter().map(|h| h.hash.clone()).collect()
+    }
+
+    #[test]
+    fn longer_fork_from_history_triggers_reorg() {
+        let mut mgr = manager();
+        let genesis = mgr.tree().root().clone();
+
+        let main = branch(&genesis, "a", 3);
+        mgr.import_headers(main.clone());
+        assert_eq!(mgr.canonical_head_hash(), main[2].hash);
+
+        let mut events = mgr.subscribe();
+        // fork off main[0] with four headers at heights 2..5
+        let side = branch(&main[0], "b", 4);
+        mgr.import_headers(side.clone());
+
+        assert_eq!(mgr.canonical_head_hash(), side[3].hash);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ChainEvent::Reorg(Reorg {
+                old_head: main[2].hash.clone(),
+                new_head: side[3].hash.clone(),
+                depth: 2,
+                dropped: hashes(&main[1..]),
+                added: hashes(&side),
+            })
+        );
+    }
+
+    #[test]
+    fn out_of_order_batches_are_parked_until_parent_arrives() {
+        let mut mgr = manager();
+        let genesis = mgr.tree().root().clone();
+        let hs = branch(&genesis, "a", 4);
+
+        let parked = mgr.import_headers(hs[2..].to_vec());
+        assert_eq!(parked.orphaned, hashes(&hs[2..]));
+        assert_eq!(mgr.canonical_height(), 0);
+
+        let res = mgr.import_headers(hs[..2].to_vec());
+        assert_eq!(res.accepted.len(), 4);
+        assert_eq!(mgr.canonical_height(), 4);
+
+        // a peer's parked headers leave with it
+        let more = branch(&hs[3], "a", 2);
+        mgr.import_from(Some("p1"), vec![more[1].clone()]);
+        mgr.on_disconnect("p1");
+        mgr.import_headers(more[..1].to_vec());
+        assert_eq!(mgr.canonical_height(), 5);
+    }
+
+    #[test]
+    fn import_result_sorts_headers_by_outcome() {
+        let mut mgr = manager();
+        let genesis = mgr.tree().root().clone();
+        let hs = branch(&genesis, "a", 3);
+        mgr.import_headers(hs[..1].to_vec());
+
+        let mut bad = hs[2].clone();
+        bad.parent_hash = hs[1].hash.clone();
+        bad.timestamp = 0;
+        let res = mgr.import_headers(vec![hs[0].clone(), hs[1].clone(), bad.clone()]);
+
+        assert_eq!(res.known, hashes(&hs[..1]));
+        assert_eq!(res.accepted, hashes(&hs[1..2]));
+        assert_eq!(res.invalid.len(), 1);
+        assert!(matches!(res.invalid[0].1, HeaderError::HashMismatch { .. }));
+        assert!(res.score_delta() < 0);
+    }
+
+    #[test]
+    fn divergent_nodes_converge_through_ancestor_search() {
+        let mut a = manager();
+        let mut b = manager();
+        let genesis = a.tree().root().clone();
+
+        // shared history, then a partition: a grows 3 more, b grows 6 more
+        let shared = branch(&genesis, "s", 20);
+        a.import_headers(shared.clone());
+        b.import_headers(shared.clone());
+        a.import_headers(branch(shared.last().unwrap(), "a", 3));
+        b.import_headers(branch(shared.last().unwrap(), "b", 6));
+
+        // a hears b's Status and drives the exchange to completion
+        let remote = b.status();
+        assert!(a.should_request(&remote));
+        let mut msg = Some(a.build_request("b", &remote));
+        while let Some(m) = msg.take() {
+            msg = match m {
+                MiniSyncMessage::RequestHashes(req) => a.on_hashes("b", b.hashes_for(&req)),
+                MiniSyncMessage::RequestHeaders(req) => {
+                    assert_eq!(req.start, 21);
+                    a.import_headers(b.headers_for(&req).headers);
+                    None
+                }
+                other => panic!("unexpected {other:?}"),
+            };
+        }
+
+        assert_eq!(a.canonical_head_hash(), b.canonical_head_hash());
+    }
+
+    #[test]
+    fn long_gap_is_downloaded_from_several_peers() {
+        let mut a = manager();
+        let mut b = manager();
+        let mut c = manager();
+        let genesis = a.tree().root().clone();
+        let main = branch(&genesis, "m", 150);
+        b.import_headers(main.clone());
+        c.import_headers(main.clone());
+
+        // b leads: ancestor search, then the skeleton request
+        let mut msg = a.on_status("b", &b.status());
+        // from genesis the ancestor is known up front, so this goes straight to the download
+        assert_eq!(a.sync_progress().state, SyncState::Downloading);
+        while let Some(MiniSyncMessage::RequestHashes(req)) = &msg {
+            msg = a.on_hashes("b", b.hashes_for(req));
+        }
+        let Some(MiniSyncMessage::RequestHeaders(skel)) = msg else { panic!("no skeleton") };
+        assert_eq!((skel.start, skel.count, skel.skip), (64, 2, 63));
+        // c joins the running round instead of starting its own
+        assert!(a.on_status("c", &c.status()).is_none());
+
+        let mut queue = a.on_headers("b", b.headers_for(&skel).headers);
+        queue.extend(a.download_tick());
+        let mut served = HashMap::new();
+        while let Some(action) = queue.pop() {
+            let SyncAction::Send { peer, msg: MiniSyncMessage::RequestHeaders(req) } = action else {
+                continue;
+            };
+            *served.entry(peer.clone()).or_insert(0) += 1;
+            let server = if peer == "b" { &b } else { &c };
+            queue.extend(a.on_headers(&peer, server.headers_for(&req).headers));
+        }
+
+        assert_eq!(a.canonical_head_hash(), main.last().unwrap().hash);
+        assert!(served.contains_key("b") && served.contains_key("c"));
+        assert!(a.download.is_none());
+
+        let progress = a.sync_progress();
+        assert_eq!(progress.state, SyncState::Synced);
+        assert_eq!((progress.starting_block, progress.current_block), (0, 150));
+    }
+
+    #[test]
+    fn new_heads_spread_by_push_and_announcement() {
+        let mut a = manager();
+        let mut b = manager();
+        let genesis = a.tree().root().clone();
+        let main = branch(&genesis, "m", 12);
+
+        // a single peer always gets the full header
+        a.import_headers(main[..1].to_vec());
+        let actions = a.announce_head(&["b".to_string()]);
+        let [SyncAction::Send { msg: MiniSyncMessage::NewHeader(nh), .. }] = &actions[..] else {
+            panic!("expected a pushed header, got {actions:?}");
+        };
+        b.on_new_header("a", nh.header.clone());
+        assert_eq!(b.canonical_head_hash(), main[0].hash);
+        // b relays, but not back to where it came from
+        let relayed = b.announce_head(&["a".to_string(), "c".to_string()]);
+        assert!(matches!(&relayed[..], [SyncAction::Send { peer, .. }] if peer == "c"));
+
+        // hash only: b asks for the one header it is missing, once
+        a.import_headers(main[1..2].to_vec());
+        let ann = vec![NumberHash { number: 2, hash: main[1].hash.clone() }];
+        let actions = b.on_new_head_hashes("a", ann.clone());
+        let [SyncAction::Send { msg: MiniSyncMessage::RequestHeaders(req), .. }] = &actions[..] else {
+            panic!("expected a header request, got {actions:?}");
+        };
+        assert_eq!((req.start, req.count), (2, 1));
+        assert!(b.on_new_head_hashes("c", ann).is_empty());
+        b.on_headers("a", a.headers_for(req).headers);
+        assert_eq!(b.canonical_head_hash(), main[1].hash);
+
+        // too far ahead to import: look for the ancestor instead
+        a.import_headers(main[2..].to_vec());
+        let actions = b.on_new_header("a", main[11].clone());
+        assert!(matches!(
+            &actions[..],
+            [SyncAction::Send { msg: MiniSyncMessage::RequestHashes(_), .. }]
+        ));
+        assert_eq!(b.sync_progress().highest_block, 12);
+    }
+
+    #[test]
+    fn bodies_follow_headers_and_are_checked_against_the_root() {
+        let mut a = manager();
+        let mut b = manager();
+        let producer = generate_keypair().unwrap();
+        let sender = generate_keypair().unwrap();
+        let transfer = |nonce| {
+            Transaction {
+                nonce,
+                gas_price: 1,
+                to: "0xbeef".into(),
+                ..Default::default()
+            }
+            .sign(&sender)
+        };
+
+        let mut events = b.subscribe();
+        let full = b.produce_block(&producer, vec![transfer(0), transfer(1)]).unwrap();
+        let empty = b.produce_block(&producer, vec![]).unwrap();
+        assert!(full.header.has_body() && !empty.header.has_body());
+        assert!(matches!(events.try_recv(), Ok(ChainEvent::Body { number: 1, .. })));
+
+        let actions = a.on_status("b", &b.status()).into_iter().map(|msg| SyncAction::Send {
+            peer: "b".into(),
+            msg,
+        });
+        drive(&mut a, &b, actions.collect());
+        assert_eq!(a.canonical_head_hash(), empty.header.hash);
+        assert_eq!(a.block(&full.header.hash).unwrap().transactions, full.transactions);
+        assert_eq!(a.block(&empty.header.hash).unwrap().transactions, vec![]);
+
+        // a body that does not hash to the header's root is refused
+        let mut c = manager();
+        c.import_headers(vec![full.header.clone()]);
+        c.on_status("b", &b.status());
+        let requested = c.download_tick().into_iter().any(|a| {
+            matches!(a, SyncAction::Send { msg: MiniSyncMessage::RequestBodies(_), .. })
+        });
+        assert!(requested);
+        let forged = Body {
+            hash: full.header.hash.clone(),
+            transactions: vec![transfer(0)],
+        };
+        let actions = c.on_bodies("b", vec![forged]);
+        assert!(matches!(actions[0], SyncAction::Score { delta: BAD_BATCH_PENALTY, .. }));
+        assert!(c.block(&full.header.hash).is_none());
+    }
+
+    /// Answer every request `a` sends to `b` until `a` has nothing left to ask.
+    fn drive(a: &mut ChainManager, b: &ChainManager, mut queue: Vec<SyncAction>) {
+        while let Some(action) = queue.pop() {
+            let SyncAction::Send { peer, msg } = action else { continue };
+            queue.extend(match msg {
+                MiniSyncMessage::RequestHashes(req) => a
+                    .on_hashes(&peer, b.hashes_for(&req))
+                    .map(|msg| SyncAction::Send { peer, msg })
+                    .into_iter()
+                    .collect(),
+                MiniSyncMessage::RequestHeaders(req) => a.on_headers(&peer, b.headers_for(&req).headers),
+                MiniSyncMessage::RequestBodies(req) => a.on_bodies(&peer, b.bodies_for(&req).bodies),
+                other => panic!("unexpected {other:?}"),
+            });
+        }
+    }
+
+    #[test]
+    fn checkpoint_start_syncs_forward_then_backfills_after_restart() {
+        let dir = std::env::temp_dir().join(format!("ethnetlite-ckpt-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut b = manager();
+        let genesis = b.tree().root().clone();
+        let main = branch(&genesis, "m", 100);
+        b.import_headers(main.clone());
+
+        let open = |hash: &str, backfill: bool| {
+            let mut spec = ChainSpec::dev();
+            spec.checkpoint = Some(Checkpoint {
+                number: 80,
+                hash: hash.to_string(),
+                backfill,
+            });
+            ChainManager::new(&spec, Box::new(FileStore::open(&dir).unwrap()))
+        };
+
+        {
+            let mut a = open(&main[79].hash, false).unwrap();
+            // nothing happens until the checkpoint header itself is fetched
+            let Some(MiniSyncMessage::RequestHeaders(req)) = a.on_status("b", &b.status()) else {
+                panic!("expected checkpoint request");
+            };
+            assert_eq!((req.start, req.count), (80, 1));
+
+            let queue = a.on_headers("b", b.headers_for(&req).headers);
+            assert_eq!(a.tree().root().hash, main[79].hash);
+            drive(&mut a, &b, queue);
+            assert_eq!(a.canonical_head_hash(), b.canonical_head_hash());
+            assert_eq!(a.canonical_hash_at(10), None);
+        }
+
+        // restart with backfill on: resumes from the stored anchor
+        let mut a = open(&main[79].hash, true).unwrap();
+        assert_eq!(a.tree().root().number, 80);
+        a.on_status("b", &b.status());
+        let queue = a.download_tick();
+        drive(&mut a, &b, queue);
+        a.download_tick();
+        assert!(a.backfill.is_none());
+        assert_eq!(a.canonical_hash_at(0), Some(genesis.hash.clone()));
+        let served = a.headers_for(&RequestHeaders { start: 10, count: 2, skip: 0 });
+        assert_eq!(hashes(&served.headers), hashes(&main[9..11]));
+        drop(a);
+
+        assert!(matches!(
+            open(&main[78].hash, false),
+            Err(StoreError::CheckpointMismatch { number: 80, .. })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn status_from_other_network_or_fork_is_ignored() {
+        let mut forked = ChainSpec::dev();
+        forked.forks = vec![3];
+        let mut a = ChainManager::new(&forked, Box::new(MemoryStore::new())).unwrap();
+        let mut b = manager();
+        let genesis = b.tree().root().clone();
+        b.import_headers(branch(&genesis, "b", 6));
+
+        // before the fork both rule sets agree
+        assert!(a.should_request(&b.status()));
+
+        let mut other_net = b.status();
+        other_net.network_id += 1;
+        assert!(!a.should_request(&other_net));
+
+        // once a passes #3, b never scheduled that fork and is stale
+        a.import_headers(branch(&genesis, "a", 4));
+        assert!(matches!(
+            a.check_fork_id(&b.status().fork_id),
+            Err(ForkIdError::RemoteStale(_))
+        ));
+        assert!(!a.should_request(&b.status()));
+    }
+
+    #[test]
+    fn rejected_or_mid_sync_blocks_are_not_produced() {
+        let producer = generate_keypair().unwrap();
+        let outsider = generate_keypair().unwrap();
+        let tx = Transaction {
+            gas_price: 1,
+            to: "0xbeef".into(),
+            ..Default::default()
+        }
+        .sign(&outsider);
+
+        let mut spec = ChainSpec::dev();
+        spec.authorities = vec![hex::encode(producer.verifying_key.to_sec1_bytes())];
+        let mut a = ChainManager::new(&spec, Box::new(MemoryStore::new())).unwrap();
+        let mut events = a.subscribe();
+        assert!(a.produce_block(&outsider, vec![tx.clone()]).is_none());
+        assert!(events.try_recv().is_err());
+        assert_eq!(a.canonical_height(), 0);
+        let block = a.produce_block(&producer, vec![tx]).unwrap();
+        assert!(a.block(&block.header.hash).is_some());
+
+        // while a download is under way the chain is not ours to extend
+        let mut b = manager();
+        b.download = Some(SkeletonSync::new("c", 1, 200, Instant::now()).0);
+        assert!(b.produce_block(&producer, vec![]).is_none());
+        assert_eq!(b.canonical_height(), 0);
+    }
+
+    #[test]
+    fn ghost_votes_are_per_host_and_end_with_the_connection() {
+        let mut spec = ChainSpec::dev();
+        spec.fork_choice = ForkChoiceRule::Ghost;
+        let mut a = ChainManager::new(&spec, Box::new(MemoryStore::new())).unwrap();
+        let genesis = a.tree().root().clone();
+        let (x, y) = (branch(&genesis, "x", 2), branch(&genesis, "y", 2));
+        a.import_headers(x.clone());
+        a.import_headers(y.clone());
+        let (tied, other) = if x[0].hash < y[0].hash { (&x, &y) } else { (&y, &x) };
+        assert_eq!(a.tree().head_hash(), tied[1].hash);
+
+        a.record_vote("10.0.0.1:30303", other[1].hash.clone());
+        assert_eq!(a.tree().head_hash(), other[1].hash);
+        // a second connection from the same host only moves that host's vote
+        a.record_vote("10.0.0.1:30304", other[1].hash.clone());
+        assert_eq!(a.votes.len(), 1);
+
+        a.on_disconnect("10.0.0.1:30304");
+        assert!(a.votes.is_empty());
+        assert_eq!(a.tree().head_hash(), tied[1].hash);
+    }
+
+    #[test]
+    fn absurd_peer_heads_are_refused() {
+        let mut a = manager();
+        let mut b = manager();
+        let genesis = b.tree().root().clone();
+        let headers = branch(&genesis, "b", 3);
+        b.import_headers(headers.clone());
+
+        let mut status = b.status();
+        status.head_number = u64::MAX;
+        assert!(!a.should_request(&status));
+        assert!(a.on_status("b", &status).is_none());
+
+        let far = NumberHash {
+            number: u64::MAX,
+            hash: headers[2].hash.clone(),
+        };
+        assert!(a.on_new_head_hashes("b", vec![far]).is_empty());
+        assert_eq!(a.sync_progress().highest_block, 0);
+    }
+
+    #[test]
+    fn restart_resumes_from_store_and_checks_genesis() {
+        let dir = std::env::temp_dir().join(format!("ethnetlite-mgr-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let open = |genesis_extra: &str| {
+            let mut spec = ChainSpec::dev();
+            spec.genesis.extra_data = genesis_extra.into();
+            ChainManager::new(&spec, Box::new(FileStore::open(&dir).unwrap()))
+        };
+
+        let head = {
+            let mut mgr = open("dev").unwrap();
+            let genesis = mgr.tree().root().clone();
+            let main = branch(&genesis, "a", 5);
+            mgr.import_headers(main.clone());
+            // the reorg has to rewrite the stored canonical index too
+            mgr.import_headers(branch(&main[3], "b", 2));
+            mgr.canonical_head_hash()
+        };
+
+        let mgr = open("dev").unwrap();
+        assert_eq!(mgr.canonical_head_hash(), head);
+        assert_eq!(mgr.canonical_height(), 6);
+
+        assert!(matches!(
+            open("other"),
+            Err(StoreError::GenesisMismatch { .. })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
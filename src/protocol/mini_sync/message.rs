@@ -1,8 +1,12 @@
 use serde::{Deserialize, Serialize};
+use crate::fork_id::ForkId;
+use crate::protocol::mini_sync::block::Body;
 use crate::protocol::mini_sync::header::Header;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Status {
+    pub network_id: u64,
+    pub fork_id: ForkId,
     pub genesis_hash: String,
     pub head_hash: String,
     pub head_number: u64,
@@ -12,6 +16,9 @@ pub struct Status {
 pub struct RequestHeaders {
     pub start: u64,
     pub count: u64,
+    /// headers to skip between each one returned (skeleton requests)
+    #[serde(default)]
+    pub skip: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,12 +26,59 @@ pub struct Headers {
     pub headers: Vec<Header>,
 }
 
+/// Ask for canonical hashes only (no header bodies) at the given heights.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestHashes {
+    pub numbers: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NumberHash {
+    pub number: u64,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hashes {
+    pub hashes: Vec<NumberHash>,
+}
+
+/// Ask for the transactions of the blocks with these header hashes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestBodies {
+    pub hashes: Vec<String>,
+}
+
+/// Bodies we hold among those requested; unknown ones are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bodies {
+    pub bodies: Vec<Body>,
+}
+
+/// A header that just became canonical, pushed in full.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewHeader {
+    pub header: Header,
+}
+
+/// New canonical heads announced by hash; the peer fetches what it lacks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewHeadHashes {
+    pub hashes: Vec<NumberHash>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum MiniSyncMessage {
     Status(Status),
     RequestHeaders(RequestHeaders),
     Headers(Headers),
+    RequestHashes(RequestHashes),
+    Hashes(Hashes),
+    NewHeader(NewHeader),
+    NewHeadHashes(NewHeadHashes),
+    RequestBodies(RequestBodies),
+    Bodies(Bodies),
 }
 
 impl MiniSyncMessage {
@@ -1,6 +1,17 @@
 pub mod header;
+pub mod block;
 pub mod message;
-pub mod chain;
+pub mod event;
+pub mod tree;
+pub mod store;
+pub mod sync;
+pub mod ancestor;
+pub mod announce;
+pub mod backfill;
+pub mod bodies;
+pub mod download;
 pub mod producer;
 pub mod fork_choice;
-pub mod manager;
\ No newline at end of file
+pub mod orphans;
+pub mod validation;
+pub mod manager;
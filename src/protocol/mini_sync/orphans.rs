@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::protocol::mini_sync::header::Header;
+
+// headers whose parent we have not seen yet, waiting for the gap to fill
+const MAX_ORPHANS: usize = 256;
+// so one peer can't push everyone else's headers out
+const MAX_ORPHANS_PER_PEER: usize = 64;
+// a gap that has not filled by then is not going to
+const ORPHAN_TTL: Duration = Duration::from_secs(120);
+
+#[derive(Debug)]
+struct Orphan {
+    header: Header,
+    peer: Option<String>, // None for headers we made or were handed locally
+    at: Instant,
+}
+
+/// Headers parked until their parent arrives, oldest first. Past a cap,
+/// overall or for the peer that sent them, the oldest make room.
+#[derive(Debug, Default)]
+pub struct Orphans {
+    parked: VecDeque<Orphan>,
+}
+
+impl Orphans {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.parked.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parked.is_empty()
+    }
+
+    pub fn park(&mut self, header: Header, peer: Option<&str>, now: Instant) {
+        self.parked.retain(|o| now.duration_since(o.at) < ORPHAN_TTL);
+        if self.parked.iter().any(|o| o.header.hash == header.hash) {
+            return;
+        }
+        if let Some(peer) = peer {
+            let mut theirs = self.parked.iter().enumerate().filter(|(_, o)| o.peer.as_deref() == Some(peer));
+            if theirs.clone().count() >= MAX_ORPHANS_PER_PEER
+                && let Some((oldest, _)) = theirs.next()
+            {
+                self.parked.remove(oldest);
+            }
+        }
+        if self.parked.len() >= MAX_ORPHANS {
+            self.parked.pop_front();
+        }
+        self.parked.push_back(Orphan {
+            header,
+            peer: peer.map(str::to_string),
+            at: now,
+        });
+    }
+
+    /// Unpark the headers waiting on `parent`.
+    pub fn children_of(&mut self, parent: &str) -> Vec<Header> {
+        let (children, rest) = self.parked.drain(..).partition(|o| o.header.parent_hash == parent);
+        self.parked = rest;
+        children.into_iter().map(|o: Orphan| o.header).collect()
+    }
+
+    pub fn forget_peer(&mut self, peer: &str) {
+        self.parked.retain(|o| o.peer.as_deref() != Some(peer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orphan(n: u64) -> Header {
+        Header {
+            parent_hash: format!("p{n}"),
+            hash: format!("h{n}"),
+            number: n,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn caps_per_peer_and_overall_and_expires() {
+        let now = Instant::now();
+        let mut orphans = Orphans::new();
+
+        // a flooding peer only ever holds its latest few
+        for n in 0..MAX_ORPHANS_PER_PEER as u64 + 10 {
+            orphans.park(orphan(n), Some("spammer"), now);
+        }
+        assert_eq!(orphans.len(), MAX_ORPHANS_PER_PEER);
+        assert!(orphans.children_of("p0").is_empty());
+
+        orphans.park(orphan(1000), Some("honest"), now);
+        for n in 2000..2000 + MAX_ORPHANS as u64 {
+            orphans.park(orphan(n), None, now);
+        }
+        assert_eq!(orphans.len(), MAX_ORPHANS);
+        assert!(orphans.children_of("p1000").is_empty());
+
+        orphans.park(orphan(3000), Some("spammer"), now);
+        orphans.forget_peer("spammer");
+        assert!(orphans.children_of("p3000").is_empty());
+        assert_eq!(orphans.children_of("p2100").len(), 1);
+
+        orphans.park(orphan(4000), None, now + ORPHAN_TTL);
+        assert_eq!(orphans.len(), 1);
+    }
+}
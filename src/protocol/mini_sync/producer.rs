@@ -1,22 +1,36 @@
 use std::sync::{Arc, Mutex};
 use tokio::time::{sleep, Duration};
 
+use crate::crypto::Keypair;
 use crate::protocol::mini_sync::manager::ChainManager;
+use crate::protocol::tx::gossip::TxGossip;
 
-pub async fn start_header_producer(mgr: Arc<Mutex<ChainManager>>, interval_secs: u64) {
+// transactions taken from the pool per block
+const MAX_TXS_PER_BLOCK: usize = 256;
+
+pub async fn start_header_producer(
+    mgr: Arc<Mutex<ChainManager>>,
+    interval_secs: u64,
+    keypair: Keypair,
+    txs: Arc<Mutex<TxGossip>>,
+) {
     tokio::spawn(async move {
         loop {
             sleep(Duration::from_secs(interval_secs)).await;
 
+            // included transactions leave the pool via the chain's body event
+            let pending = txs.lock().unwrap().pool().pending(MAX_TXS_PER_BLOCK);
+            let count = pending.len();
+
             let mut m = mgr.lock().unwrap();
 
             let before = m.canonical_height();
-            let mut next = m.canonical_clone();
-            next.produce_header();
-            m.insert_chain(next);
+            if m.produce_block(&keypair, pending).is_none() {
+                continue;
+            }
             let after = m.canonical_height();
 
-            println!("[MINE] produced header {} → {}", before, after);
+            println!("[MINE] produced block {} → {} with {} txs", before, after, count);
         }
     });
 }
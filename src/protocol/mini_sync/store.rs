@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::protocol::mini_sync::header::Header;
+use crate::protocol::tx::transaction::Transaction;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("storage io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("corrupt chain store at line {line}: {reason}")]
+    Corrupt { line: usize, reason: String },
+
+    #[error("stored genesis {stored} does not match configured genesis {configured}")]
+    GenesisMismatch { stored: String, configured: String },
+
+    #[error("stored header #{number} is {stored}, but the checkpoint says {configured}")]
+    CheckpointMismatch {
+        number: u64,
+        stored: String,
+        configured: String,
+    },
+}
+
+/// Where `ChainManager` keeps headers across restarts.
+///
+/// Headers are stored by hash regardless of fork; the canonical chain is a
+/// separate number -> hash index plus head/finalized pointers.
+pub trait ChainStore: Send + std::fmt::Debug {
+    fn put_header(&mut self, header: &Header) -> Result<(), StoreError>;
+    fn header(&self, hash: &str) -> Option<Header>;
+
+    /// Transactions of the block with header `hash`.
+    fn put_body(&mut self, hash: &str, transactions: &[Transaction]) -> Result<(), StoreError>;
+    fn body(&self, hash: &str) -> Option<Vec<Transaction>>;
+
+    fn set_canonical(&mut self, number: u64, hash: &str) -> Result<(), StoreError>;
+    /// Forget canonical entries strictly above `number` (after a reorg to a shorter chain).
+    fn truncate_canonical(&mut self, number: u64) -> Result<(), StoreError>;
+    fn canonical_hash(&self, number: u64) -> Option<String>;
+
+    fn set_head(&mut self, hash: &str) -> Result<(), StoreError>;
+    fn head(&self) -> Option<String>;
+
+    fn set_finalized(&mut self, hash: &str) -> Result<(), StoreError>;
+    fn finalized(&self) -> Option<String>;
+}
+
+// -------- in-memory (tests, throwaway nodes) --------
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    headers: HashMap<String, Header>,
+    bodies: HashMap<String, Vec<Transaction>>,
+    canonical: HashMap<u64, String>,
+    head: Option<String>,
+    finalized: Option<String>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChainStore for MemoryStore {
+    fn put_header(&mut self, header: &Header) -> Result<(), StoreError> {
+        self.headers.insert(header.hash.clone(), header.clone());
+        Ok(())
+    }
+
+    fn header(&self, hash: &str) -> Option<Header> {
+        self.headers.get(hash).cloned()
+    }
+
+    fn put_body(&mut self, hash: &str, transactions: &[Transaction]) -> Result<(), StoreError> {
+        self.bodies.insert(hash.to_string(), transactions.to_vec());
+        Ok(())
+    }
+
+    fn body(&self, hash: &str) -> Option<Vec<Transaction>> {
+        self.bodies.get(hash).cloned()
+    }
+
+    fn set_canonical(&mut self, number: u64, hash: &str) -> Result<(), StoreError> {
+        self.canonical.insert(number, hash.to_string());
+        Ok(())
+    }
+
+    fn truncate_canonical(&mut self, number: u64) -> Result<(), StoreError> {
+        self.canonical.retain(|n, _| *n <= number);
+        Ok(())
+    }
+
+    fn canonical_hash(&self, number: u64) -> Option<String> {
+        self.canonical.get(&number).cloned()
+    }
+
+    fn set_head(&mut self, hash: &str) -> Result<(), StoreError> {
+        self.head = Some(hash.to_string());
+        Ok(())
+    }
+
+    fn head(&self) -> Option<String> {
+        self.head.clone()
+    }
+
+    fn set_finalized(&mut self, hash: &str) -> Result<(), StoreError> {
+        self.finalized = Some(hash.to_string());
+        Ok(())
+    }
+
+    fn finalized(&self) -> Option<String> {
+        self.finalized.clone()
+    }
+}
+
+// -------- on-disk --------
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", content = "data")]
+enum Record {
+    Header(Header),
+    Body { hash: String, transactions: Vec<Transaction> },
+    Canonical { number: u64, hash: String },
+    Truncate { number: u64 },
+    Head(String),
+    Finalized(String),
+}
+
+/// Append-only log of store operations in `<dir>/chain.log`, one JSON record
+/// per line.
+///
+/// Reads are served from an in-memory copy rebuilt by replaying the log on
+/// open; the log is then compacted so it doesn't grow forever across restarts.
+#[derive(Debug)]
+pub struct FileStore {
+    mem: MemoryStore,
+    path: PathBuf,
+    log: File,
+}
+
+impl FileStore {
+    pub fn open(dir: &Path) -> Result<Self, StoreError> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join("chain.log");
+
+        let mut mem = MemoryStore::new();
+        if path.exists() {
+            let reader = BufReader::new(File::open(&path)?);
+            for (i, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let rec: Record = match serde_json::from_str(&line) {
+                    Ok(r) => r,
+                    // a torn final write from a crash; everything before it is good
+                    Err(_) if is_last_line(&path, i)? => break,
+                    Err(e) => {
+                        return Err(StoreError::Corrupt {
+                            line: i + 1,
+                            reason: e.to_string(),
+                        });
+                    }
+                };
+                apply(&mut mem, rec)?;
+            }
+        }
+
+        compact(&path, &mem)?;
+        let log = OpenOptions::new().append(true).open(&path)?;
+        Ok(Self { mem, path, log })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn append(&mut self, rec: &Record) -> Result<(), StoreError> {
+        let mut line = serde_json::to_vec(rec).expect("serialize store record");
+        line.push(b'\n');
+        self.log.write_all(&line)?;
+        Ok(())
+    }
+}
+
+fn apply(mem: &mut MemoryStore, rec: Record) -> Result<(), StoreError> {
+    match rec {
+        Record::Header(h) => mem.put_header(&h),
+        Record::Body { hash, transactions } => mem.put_body(&hash, &transactions),
+        Record::Canonical { number, hash } => mem.set_canonical(number, &hash),
+        Record::Truncate { number } => mem.truncate_canonical(number),
+        Record::Head(h) => mem.set_head(&h),
+        Record::Finalized(h) => mem.set_finalized(&h),
+    }
+}
+
+fn is_last_line(path: &Path, index: usize) -> Result<bool, StoreError> {
+    let total = BufReader::new(File::open(path)?).lines().count();
+    Ok(index + 1 == total)
+}
+
+/// Rewrite the log as just the canonical chain (headers and bodies) and the
+/// two pointers. Side forks are not worth keeping across a restart; peers
+/// will resend them.
+fn compact(path: &Path, mem: &MemoryStore) -> Result<(), StoreError> {
+    let tmp = path.with_extension("log.tmp");
+    {
+        let mut out = std::io::BufWriter::new(File::create(&tmp)?);
+        let mut write = |rec: Record| -> Result<(), StoreError> {
+            serde_json::to_writer(&mut out, &rec).expect("serialize store record");
+            out.write_all(b"\n")?;
+            Ok(())
+        };
+
+        let mut numbers: Vec<_> = mem.canonical.keys().copied().collect();
+        numbers.sort_unstable();
+        for n in numbers {
+            let hash = &mem.canonical[&n];
+            if let Some(h) = mem.headers.get(hash) {
+                write(Record::Header(h.clone()))?;
+            }
+            if let Some(txs) = mem.bodies.get(hash) {
+                write(Record::Body {
+                    hash: hash.clone(),
+                    transactions: txs.clone(),
+                })?;
+            }
+            write(Record::Canonical {
+                number: n,
+                hash: hash.clone(),
+            })?;
+        }
+        if let Some(h) = &mem.head {
+            write(Record::Head(h.clone()))?;
+        }
+        if let Some(h) = &mem.finalized {
+            write(Record::Finalized(h.clone()))?;
+        }
+        out.flush()?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+impl ChainStore for FileStore {
+    fn put_header(&mut self, header: &Header) -> Result<(), StoreError> {
+        self.append(&Record::Header(header.clone()))?;
+        self.mem.put_header(header)
+    }
+
+    fn header(&self, hash: &str) -> Option<Header> {
+        self.mem.header(hash)
+    }
+
+    fn put_body(&mut self, hash: &str, transactions: &[Transaction]) -> Result<(), StoreError> {
+        self.append(&Record::Body {
+            hash: hash.to_string(),
+            transactions: transactions.to_vec(),
+        })?;
+        self.mem.put_body(hash, transactions)
+    }
+
+    fn body(&self, hash: &str) -> Option<Vec<Transaction>> {
+        self.mem.body(hash)
+    }
+
+    fn set_canonical(&mut self, number: u64, hash: &str) -> Result<(), StoreError> {
+        self.append(&Record::Canonical {
+            number,
+            hash: hash.to_string(),
+        })?;
+        self.mem.set_canonical(number, hash)
+    }
+
+    fn truncate_canonical(&mut self, number: u64) -> Result<(), StoreError> {
+        self.append(&Record::Truncate { number })?;
+        self.mem.truncate_canonical(number)
+    }
+
+    fn canonical_hash(&self, number: u64) -> Option<String> {
+        self.mem.canonical_hash(number)
+    }
+
+    fn set_head(&mut self, hash: &str) -> Result<(), StoreError> {
+        self.append(&Record::Head(hash.to_string()))?;
+        self.mem.set_head(hash)
+    }
+
+    fn head(&self) -> Option<String> {
+        self.mem.head()
+    }
+
+    fn set_finalized(&mut self, hash: &str) -> Result<(), StoreError> {
+        self.append(&Record::Finalized(hash.to_string()))?;
+        self.mem.set_finalized(hash)
+    }
+
+    fn finalized(&self) -> Option<String> {
+        self.mem.finalized()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(n: u64) -> Header {
+        Header {
+            parent_hash: format!("0x{:02x}", n.wrapping_sub(1)),
+            hash: format!("0x{n:02x}"),
+            number: n,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn file_store_survives_reopen_and_drops_torn_tail() {
+        let dir = std::env::temp_dir().join(format!("ethnetlite-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        {
+            let mut st = FileStore::open(&dir).unwrap();
+            for n in 0..4 {
+                st.put_header(&header(n)).unwrap();
+                st.set_canonical(n, &header(n).hash).unwrap();
+            }
+            st.truncate_canonical(2).unwrap();
+            st.set_head("0x02").unwrap();
+            st.set_finalized("0x01").unwrap();
+        }
+        // simulate a crash mid-write
+        let mut f = OpenOptions::new().append(true).open(dir.join("chain.log")).unwrap();
+        f.write_all(b"{\"op\":\"Head\",\"da").unwrap();
+
+        let st = FileStore::open(&dir).unwrap();
+        assert_eq!(st.head().as_deref(), Some("0x02"));
+        assert_eq!(st.finalized().as_deref(), Some("0x01"));
+        assert_eq!(st.canonical_hash(2).as_deref(), Some("0x02"));
+        assert_eq!(st.canonical_hash(3), None);
+        assert_eq!(st.header("0x01").unwrap().number, 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+use std::fmt;
+use std::time::Instant;
+
+use tokio::sync::broadcast;
+
+/// Where the node is in catching up with its peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncState {
+    /// no peer has told us about anything we lack, or the last round ended early
+    Idle,
+    FindingAncestor,
+    Downloading,
+    Importing,
+    /// caught up with the highest head any peer advertised
+    Synced,
+}
+
+impl fmt::Display for SyncState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SyncState::Idle => "idle",
+            SyncState::FindingAncestor => "finding-ancestor",
+            SyncState::Downloading => "downloading",
+            SyncState::Importing => "importing",
+            SyncState::Synced => "synced",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Snapshot in the spirit of `eth_syncing`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncProgress {
+    pub state: SyncState,
+    /// canonical height when the current catch-up began
+    pub starting_block: u64,
+    pub current_block: u64,
+    pub highest_block: u64,
+    /// estimated seconds to reach `highest_block` at the rate seen so far
+    pub eta_secs: Option<u64>,
+}
+
+/// Owns the sync state machine and publishes every change to subscribers.
+///
+/// `ChainManager` drives it from the sync flow; it only decides what the
+/// transitions mean for the reported numbers.
+#[derive(Debug)]
+pub struct SyncService {
+    progress: SyncProgress,
+    // when the current catch-up began
+    started: Instant,
+    updates: broadcast::Sender<SyncProgress>,
+}
+
+impl SyncService {
+    pub fn new(current_block: u64, now: Instant) -> Self {
+        Self {
+            progress: SyncProgress {
+                state: SyncState::Idle,
+                starting_block: current_block,
+                current_block,
+                highest_block: current_block,
+                eta_secs: None,
+            },
+            started: now,
+            updates: broadcast::channel(64).0,
+        }
+    }
+
+    pub fn progress(&self) -> SyncProgress {
+        self.progress.clone()
+    }
+
+    pub fn state(&self) -> SyncState {
+        self.progress.state
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SyncProgress> {
+        self.updates.subscribe()
+    }
+
+    pub fn transition(&mut self, state: SyncState, now: Instant) {
+        if state == self.progress.state {
+            return;
+        }
+        // a fresh catch-up starts counting from here
+        let resting = matches!(self.progress.state, SyncState::Idle | SyncState::Synced);
+        if resting && matches!(state, SyncState::FindingAncestor | SyncState::Downloading) {
+            self.progress.starting_block = self.progress.current_block;
+            self.started = now;
+        }
+        self.progress.state = state;
+        self.refresh_eta(now);
+        self.publish();
+    }
+
+    /// A peer advertised a head at `number`.
+    pub fn observe_peer_head(&mut self, number: u64) {
+        if number > self.progress.highest_block {
+            self.progress.highest_block = number;
+            self.publish();
+        }
+    }
+
+    /// Our canonical head moved to `number`.
+    pub fn observe_head(&mut self, number: u64, now: Instant) {
+        if number == self.progress.current_block {
+            return;
+        }
+        self.progress.current_block = number;
+        self.progress.highest_block = self.progress.highest_block.max(number);
+        self.refresh_eta(now);
+        self.publish();
+    }
+
+    pub fn caught_up(&self) -> bool {
+        self.progress.current_block >= self.progress.highest_block
+    }
+
+    fn refresh_eta(&mut self, now: Instant) {
+        let p = &mut self.progress;
+        let done = p.current_block.saturating_sub(p.starting_block);
+        let left = p.highest_block.saturating_sub(p.current_block);
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+
+        p.eta_secs = if p.state == SyncState::Synced || left == 0 {
+            Some(0)
+        } else if done == 0 || elapsed <= 0.0 {
+            None
+        } else {
+            Some((left as f64 * elapsed / done as f64).ceil() as u64)
+        };
+    }
+
+    fn publish(&self) {
+        // nobody listening is fine
+        let _ = self.updates.send(self.progress.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn reports_progress_and_eta_through_a_catch_up() {
+        let t0 = Instant::now();
+        let mut sync = SyncService::new(10, t0);
+        let mut updates = sync.subscribe();
+
+        sync.observe_peer_head(110);
+        sync.transition(SyncState::FindingAncestor, t0);
+        sync.transition(SyncState::Downloading, t0);
+        assert_eq!(sync.progress().eta_secs, None);
+
+        // 40 headers in 4s, 60 to go
+        sync.observe_head(50, t0 + Duration::from_secs(4));
+        let p = sync.progress();
+        assert_eq!((p.starting_block, p.current_block, p.highest_block), (10, 50, 110));
+        assert_eq!(p.eta_secs, Some(6));
+
+        sync.observe_head(110, t0 + Duration::from_secs(10));
+        assert!(sync.caught_up());
+        sync.transition(SyncState::Synced, t0 + Duration::from_secs(10));
+
+        let mut states = vec![];
+        while let Ok(p) = updates.try_recv() {
+            if states.last() != Some(&p.state) {
+                states.push(p.state);
+            }
+        }
+        assert_eq!(
+            states,
+            vec![
+                SyncState::Idle,
+                SyncState::FindingAncestor,
+                SyncState::Downloading,
+                SyncState::Synced
+            ]
+        );
+
+        // the next catch-up counts from where this one ended
+        sync.observe_peer_head(120);
+        sync.transition(SyncState::Downloading, t0 + Duration::from_secs(20));
+        assert_eq!(sync.progress().starting_block, 110);
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::protocol::mini_sync::header::Header;
+
+#[derive(Debug, Clone)]
+pub struct TreeNode {
+    pub header: Header,
+    pub children: Vec<String>,
+    // sum of difficulty from genesis up to and including this header
+    pub total_difficulty: u64,
+    // difficulty of this header and everything built on it; only kept up to
+    // date above the finalized header, the part fork choice looks at
+    pub subtree_difficulty: u64,
+}
+
+/// Every header we know about, indexed by hash, with parent/child links.
+///
+/// The canonical chain is not stored separately: it is just `head` plus a
+/// number -> hash index that gets rewritten along the diverging suffix when
+/// the head moves.
+///
+/// The root is usually genesis, but a node started from a checkpoint roots
+/// the tree there; history below the root lives only in the store.
+#[derive(Debug)]
+pub struct HeaderTree {
+    nodes: HashMap<String, TreeNode>,
+    by_number: BTreeMap<u64, Vec<String>>,
+    tips: HashSet<String>,  // headers without children
+    base: u64,              // number of the root header
+    canonical: Vec<String>, // canonical[n - base] = hash of canonical header #n
+    head: String,
+    finalized: String,
+}
+
+impl HeaderTree {
+    /// A tree holding just `root`, which is canonical and final.
+    pub fn new(root: Header) -> Self {
+        let hash = root.hash.clone();
+        let base = root.number;
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            hash.clone(),
+            TreeNode {
+                total_difficulty: root.difficulty,
+                subtree_difficulty: root.difficulty,
+                header: root,
+                children: vec![],
+            },
+        );
+
+        let mut by_number = BTreeMap::new();
+        by_number.insert(base, vec![hash.clone()]);
+
+        Self {
+            nodes,
+            by_number,
+            tips: HashSet::from([hash.clone()]),
+            base,
+            canonical: vec![hash.clone()],
+            head: hash.clone(),
+            finalized: hash,
+        }
+    }
+
+    // -------- lookups --------
+
+    pub fn get(&self, hash: &str) -> Option<&TreeNode> {
+        self.nodes.get(hash)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.nodes.contains_key(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Lowest header in the tree: genesis, or the checkpoint we started from.
+    pub fn root(&self) -> &Header {
+        &self.nodes[&self.canonical[0]].header
+    }
+
+    pub fn head(&self) -> &Header {
+        &self.nodes[&self.head].header
+    }
+
+    pub fn head_hash(&self) -> &str {
+        &self.head
+    }
+
+    pub fn height(&self) -> u64 {
+        self.head().number
+    }
+
+    pub fn finalized(&self) -> &Header {
+        &self.nodes[&self.finalized].header
+    }
+
+    /// Hash of the canonical header at `number`, if we have one.
+    pub fn canonical_hash(&self, number: u64) -> Option<&str> {
+        let idx = number.checked_sub(self.base)?;
+        self.canonical.get(idx as usize).map(String::as_str)
+    }
+
+    pub fn canonical_header(&self, number: u64) -> Option<&Header> {
+        self.canonical_hash(number).map(|h| &self.nodes[h].header)
+    }
+
+    /// All known headers (on any fork) at `number`.
+    pub fn at_number(&self, number: u64) -> &[String] {
+        self.by_number.get(&number).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Fork tips: headers nobody builds on yet.
+    pub fn leaves(&self) -> impl Iterator<Item = &TreeNode> {
+        self.tips.iter().map(|h| &self.nodes[h])
+    }
+
+    // -------- mutation --------
+
+    /// Link `header` under its parent. Returns false if it is already known,
+    /// its parent is not in the tree, or it would fork below the finalized header.
+    pub fn insert(&mut self, header: Header) -> bool {
+        if self.nodes.contains_key(&header.hash) || header.number <= self.finalized().number {
+            return false;
+        }
+        let Some(parent) = self.nodes.get_mut(&header.parent_hash) else {
+            return false;
+        };
+        if header.number != parent.header.number + 1 {
+            return false;
+        }
+
+        parent.children.push(header.hash.clone());
+        let total_difficulty = parent.total_difficulty.saturating_add(header.difficulty);
+        self.tips.remove(&header.parent_hash);
+        self.tips.insert(header.hash.clone());
+
+        let finalized = self.finalized().number;
+        let mut cur = header.parent_hash.clone();
+        while let Some(node) = self.nodes.get_mut(&cur).filter(|n| n.header.number > finalized) {
+            node.subtree_difficulty = node.subtree_difficulty.saturating_add(header.difficulty);
+            cur = node.header.parent_hash.clone();
+        }
+
+        self.by_number
+            .entry(header.number)
+            .or_default()
+            .push(header.hash.clone());
+        self.nodes.insert(
+            header.hash.clone(),
+            TreeNode {
+                subtree_difficulty: header.difficulty,
+                header,
+                children: vec![],
+                total_difficulty,
+            },
+        );
+        true
+    }
+
+    /// Point the canonical head at `hash` and rewrite the number index from
+    /// the common ancestor up. Returns `(dropped, added)` canonical hashes,
+    /// both ordered by ascending number.
+    pub fn set_head(&mut self, hash: &str) -> (Vec<String>, Vec<String>) {
+        if hash == self.head || !self.nodes.contains_key(hash) {
+            return (vec![], vec![]);
+        }
+
+        // walk back from the new head until we land on the canonical chain
+        let mut added = vec![];
+        let mut cur = hash.to_string();
+        loop {
+            let n = &self.nodes[&cur].header;
+            if self.canonical_hash(n.number) == Some(cur.as_str()) {
+                break;
+            }
+            added.push(cur.clone());
+            cur = n.parent_hash.clone();
+        }
+        added.reverse();
+
+        let fork_point = (self.nodes[&cur].header.number - self.base) as usize;
+        let dropped = self.canonical.split_off(fork_point + 1);
+        self.canonical.extend(added.iter().cloned());
+        self.head = hash.to_string();
+
+        (dropped, added)
+    }
+
+    /// Mark a canonical header as final and drop every fork that branches off
+    /// below it. Anything on another branch can never become canonical again.
+    pub fn finalize(&mut self, hash: &str) {
+        let Some(node) = self.nodes.get(hash) else { return };
+        let number = node.header.number;
+        if self.canonical_hash(number) != Some(hash) || number <= self.finalized().number {
+            return;
+        }
+        self.finalized = hash.to_string();
+        self.prune();
+    }
+
+    fn prune(&mut self) {
+        let fin_number = self.finalized().number;
+
+        // at or below the finalized height only the canonical header survives
+        let mut doomed: Vec<String> = vec![];
+        for (&n, hashes) in self.by_number.range(..=fin_number) {
+            let keep = &self.canonical[(n - self.base) as usize];
+            doomed.extend(hashes.iter().filter(|h| *h != keep).cloned());
+        }
+
+        // ...and everything hanging off those side branches goes with them
+        let mut i = 0;
+        while i < doomed.len() {
+            doomed.extend(self.nodes[&doomed[i]].children.iter().cloned());
+            i += 1;
+        }
+
+        for h in doomed {
+            if let Some(node) = self.nodes.remove(&h) {
+                let n = node.header.number;
+                if let Some(v) = self.by_number.get_mut(&n) {
+                    v.retain(|x| *x != h);
+                }
+                self.tips.remove(&h);
+                if let Some(parent) = self.nodes.get_mut(&node.header.parent_hash) {
+                    parent.children.retain(|x| *x != h);
+                    if parent.children.is_empty() {
+                        self.tips.insert(node.header.parent_hash);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(parent: &str, hash: &str, number: u64) -> Header {
+        Header {
+            parent_hash: parent.to_string(),
+            hash: hash.to_string(),
+            number,
+            difficulty: 1,
+            ..Default::default()
+        }
+    }
+
+    fn tree() -> HeaderTree {
+        // g - a1 - a2 - a3
+        //       \
+        //        b2 - b3 - b4
+        let mut t = HeaderTree::new(header("0x00", "g", 0));
+        for h in [
+            header("g", "a1", 1),
+            header("a1", "a2", 2),
+            header("a2", "a3", 3),
+            header("a1", "b2", 2),
+            header("b2", "b3", 3),
+            header("b3", "b4", 4),
+        ] {
+            assert!(t.insert(h));
+        }
+        t.set_head("a3");
+        t
+    }
+
+    #[test]
+    fn rejects_orphans_and_duplicates() {
+        let mut t = tree();
+        assert!(!t.insert(header("nope", "x", 5)));
+        assert!(!t.insert(header("g", "a1", 1)));
+        assert_eq!(t.at_number(2), ["a2", "b2"]);
+        assert_eq!(t.leaves().count(), 2);
+    }
+
+    #[test]
+    fn set_head_rewrites_index_from_fork_point() {
+        let mut t = tree();
+        let (dropped, added) = t.set_head("b4");
+        assert_eq!(dropped, ["a2", "a3"]);
+        assert_eq!(added, ["b2", "b3", "b4"]);
+        assert_eq!(t.canonical_hash(1), Some("a1"));
+        assert_eq!(t.canonical_hash(3), Some("b3"));
+        assert_eq!(t.height(), 4);
+    }
+
+    #[test]
+    fn finalize_prunes_side_branches() {
+        let mut t = tree();
+        t.set_head("b4");
+        t.finalize("b2");
+        assert!(!t.contains("a2"));
+        assert!(!t.contains("a3"));
+        assert!(t.contains("b4"));
+        assert_eq!(t.len(), 5);
+        assert_eq!(t.get("a1").unwrap().children, ["b2"]);
+        assert_eq!(t.leaves().map(|n| n.header.hash.as_str()).collect::<Vec<_>>(), ["b4"]);
+    }
+
+    #[test]
+    fn tracks_subtree_difficulty_above_finality() {
+        let mut t = tree();
+        assert_eq!(t.get("a1").unwrap().subtree_difficulty, 6);
+        assert_eq!(t.get("b2").unwrap().subtree_difficulty, 3);
+        assert!(t.insert(header("b4", "b5", 5)));
+        assert_eq!(t.get("a1").unwrap().subtree_difficulty, 7);
+        assert_eq!(t.get("b3").unwrap().subtree_difficulty, 3);
+
+        let mut leaves: Vec<&str> = t.leaves().map(|n| n.header.hash.as_str()).collect();
+        leaves.sort();
+        assert_eq!(leaves, ["a3", "b5"]);
+    }
+}
@@ -0,0 +1,337 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use crate::protocol::mini_sync::header::Header;
+
+pub const MAX_EXTRA_DATA: usize = 32;
+pub const MAX_FUTURE_DRIFT_SECS: u64 = 15;
+// keeps total difficulty far from u64::MAX however long the chain grows
+pub const MAX_DIFFICULTY: u64 = 1 << 32;
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum HeaderError {
+    #[error("hash mismatch: header says {claimed}, contents hash to {computed}")]
+    HashMismatch { claimed: String, computed: String },
+
+    #[error("header #{number} is at or below finalized #{finalized}")]
+    BelowFinalized { number: u64, finalized: u64 },
+
+    #[error("number {got} does not follow parent {parent}")]
+    BadNumber { parent: u64, got: u64 },
+
+    #[error("timestamp {got} not after parent timestamp {parent}")]
+    TimestampNotIncreasing { parent: u64, got: u64 },
+
+    #[error("timestamp {got} is more than {max_drift}s ahead of local clock {now}")]
+    TooFarInFuture { got: u64, now: u64, max_drift: u64 },
+
+    #[error("extra_data is {len} bytes, max {max}")]
+    ExtraDataTooLarge { len: usize, max: usize },
+
+    #[error("difficulty {got} outside 1..={max}")]
+    BadDifficulty { got: u64, max: u64 },
+
+    #[error("invalid producer signature")]
+    BadSignature,
+
+    #[error("producer {0} is not an authority")]
+    UnauthorizedProducer(String),
+}
+
+/// One rule a header must satisfy before it is linked into the tree.
+pub trait HeaderValidator: Send + Sync {
+    fn validate(&self, header: &Header, parent: &Header) -> Result<(), HeaderError>;
+}
+
+/// `hash` really is the hash of the header contents.
+pub struct HashIntegrity;
+
+impl HeaderValidator for HashIntegrity {
+    fn validate(&self, header: &Header, parent: &Header) -> Result<(), HeaderError> {
+        if header.number != parent.number + 1 {
+            return Err(HeaderError::BadNumber {
+                parent: parent.number,
+                got: header.number,
+            });
+        }
+        let computed = header.compute_hash();
+        if computed != header.hash {
+            return Err(HeaderError::HashMismatch {
+                claimed: header.hash.clone(),
+                computed,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub struct MonotonicTimestamp;
+
+impl HeaderValidator for MonotonicTimestamp {
+    fn validate(&self, header: &Header, parent: &Header) -> Result<(), HeaderError> {
+        if header.timestamp <= parent.timestamp {
+            return Err(HeaderError::TimestampNotIncreasing {
+                parent: parent.timestamp,
+                got: header.timestamp,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub struct FutureDrift {
+    pub max_drift: u64,
+}
+
+impl HeaderValidator for FutureDrift {
+    fn validate(&self, header: &Header, _parent: &Header) -> Result<(), HeaderError> {
+        let now = unix_now();
+        if header.timestamp > now + self.max_drift {
+            return Err(HeaderError::TooFarInFuture {
+                got: header.timestamp,
+                now,
+                max_drift: self.max_drift,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub struct ExtraDataSize {
+    pub max: usize,
+}
+
+impl HeaderValidator for ExtraDataSize {
+    fn validate(&self, header: &Header, _parent: &Header) -> Result<(), HeaderError> {
+        if header.extra_data.len() > self.max {
+            return Err(HeaderError::ExtraDataTooLarge {
+                len: header.extra_data.len(),
+                max: self.max,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Difficulty is at least 1 and at most `max`.
+pub struct Difficulty {
+    pub max: u64,
+}
+
+impl HeaderValidator for Difficulty {
+    fn validate(&self, header: &Header, _parent: &Header) -> Result<(), HeaderError> {
+        if !(1..=self.max).contains(&header.difficulty) {
+            return Err(HeaderError::BadDifficulty {
+                got: header.difficulty,
+                max: self.max,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Signature must verify; if `authorities` is non-empty the signer must be in it.
+pub struct ProducerSignature {
+    pub authorities: Vec<String>,
+}
+
+impl HeaderValidator for ProducerSignature {
+    fn validate(&self, header: &Header, _parent: &Header) -> Result<(), HeaderError> {
+        if !header.verify_signature() {
+            return Err(HeaderError::BadSignature);
+        }
+        if !self.authorities.is_empty() && !self.authorities.contains(&header.producer) {
+            return Err(HeaderError::UnauthorizedProducer(header.producer.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// Runs its checks in order and stops at the first failure.
+pub struct ValidationPipeline {
+    checks: Vec<Box<dyn HeaderValidator>>,
+}
+
+impl ValidationPipeline {
+    pub fn empty() -> Self {
+        Self { checks: vec![] }
+    }
+
+    pub fn with(mut self, check: impl HeaderValidator + 'static) -> Self {
+        self.checks.push(Box::new(check));
+        self
+    }
+}
+
+impl ValidationPipeline {
+    /// Every built-in check, with signatures restricted to `authorities`
+    /// (empty = any producer). Authority chains seal every block at
+    /// difficulty 1.
+    pub fn standard(authorities: Vec<String>) -> Self {
+        let max_difficulty = if authorities.is_empty() { MAX_DIFFICULTY } else { 1 };
+        Self::empty()
+            .with(HashIntegrity)
+            .with(MonotonicTimestamp)
+            .with(FutureDrift {
+                max_drift: MAX_FUTURE_DRIFT_SECS,
+            })
+            .with(ExtraDataSize {
+                max: MAX_EXTRA_DATA,
+            })
+            .with(Difficulty { max: max_difficulty })
+            .with(ProducerSignature { authorities })
+    }
+}
+
+impl Default for ValidationPipeline {
+    fn default() -> Self {
+        Self::standard(vec![])
+    }
+}
+
+impl std::fmt::Debug for ValidationPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ValidationPipeline({} checks)", self.checks.len())
+    }
+}
+
+impl HeaderValidator for ValidationPipeline {
+    fn validate(&self, header: &Header, parent: &Header) -> Result<(), HeaderError> {
+        self.checks
+            .iter()
+            .try_for_each(|c| c.validate(header, parent))
+    }
+}
+
+/// What happened to each header handed to `ChainManager::import_headers`.
+#[derive(Debug, Default)]
+pub struct ImportResult {
+    pub accepted: Vec<String>,
+    pub known: Vec<String>,
+    /// parent unknown; parked until it arrives
+    pub orphaned: Vec<String>,
+    pub invalid: Vec<(String, HeaderError)>,
+}
+
+impl ImportResult {
+    /// Reputation change for the peer that served these headers: useful data
+    /// earns a little, anything invalid costs a lot more.
+    pub fn score_delta(&self) -> i32 {
+        let good = self.accepted.len().min(10) as i32;
+        let bad = self.invalid.len() as i32 * 20;
+        good - bad
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_keypair;
+
+    fn child_of(parent: &Header) -> Header {
+        Header {
+            parent_hash: parent.hash.clone(),
+            number: parent.number + 1,
+            difficulty: 1,
+            timestamp: parent.timestamp + 2,
+            ..Default::default()
+        }
+    }
+
+    fn genesis() -> Header {
+        Header {
+            parent_hash: "0x00".into(),
+            hash: "0xgenesis".into(),
+            number: 0,
+            difficulty: 1,
+            timestamp: unix_now() - 100,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sealed_header_passes_default_pipeline() {
+        let kp = generate_keypair().unwrap();
+        let g = genesis();
+        let h = child_of(&g).seal(&kp);
+        assert_eq!(ValidationPipeline::default().validate(&h, &g), Ok(()));
+    }
+
+    #[test]
+    fn each_check_reports_its_own_error() {
+        let kp = generate_keypair().unwrap();
+        let g = genesis();
+        let pipeline = ValidationPipeline::default();
+
+        let mut tampered = child_of(&g).seal(&kp);
+        tampered.difficulty = 9;
+        assert!(matches!(
+            pipeline.validate(&tampered, &g),
+            Err(HeaderError::HashMismatch { .. })
+        ));
+
+        let mut stale = child_of(&g);
+        stale.timestamp = g.timestamp;
+        assert!(matches!(
+            pipeline.validate(&stale.seal(&kp), &g),
+            Err(HeaderError::TimestampNotIncreasing { .. })
+        ));
+
+        let mut future = child_of(&g);
+        future.timestamp = unix_now() + 3600;
+        assert!(matches!(
+            pipeline.validate(&future.seal(&kp), &g),
+            Err(HeaderError::TooFarInFuture { .. })
+        ));
+
+        let mut fat = child_of(&g);
+        fat.extra_data = vec![0; MAX_EXTRA_DATA + 1];
+        assert!(matches!(
+            pipeline.validate(&fat.seal(&kp), &g),
+            Err(HeaderError::ExtraDataTooLarge { .. })
+        ));
+
+        for difficulty in [0, MAX_DIFFICULTY + 1] {
+            let mut heavy = child_of(&g);
+            heavy.difficulty = difficulty;
+            assert!(matches!(
+                pipeline.validate(&heavy.seal(&kp), &g),
+                Err(HeaderError::BadDifficulty { .. })
+            ));
+        }
+
+        let mut forged = child_of(&g).seal(&kp);
+        forged.producer = hex::encode(generate_keypair().unwrap().verifying_key.to_sec1_bytes());
+        forged.hash = forged.compute_hash();
+        assert_eq!(pipeline.validate(&forged, &g), Err(HeaderError::BadSignature));
+    }
+
+    #[test]
+    fn authority_set_rejects_outsiders() {
+        let kp = generate_keypair().unwrap();
+        let check = ProducerSignature {
+            authorities: vec!["02deadbeef".into()],
+        };
+        let g = genesis();
+        let h = child_of(&g).seal(&kp);
+        assert!(matches!(
+            check.validate(&h, &g),
+            Err(HeaderError::UnauthorizedProducer(_))
+        ));
+
+        let mut heavy = child_of(&g);
+        heavy.difficulty = 2;
+        assert_eq!(
+            ValidationPipeline::standard(vec![h.producer.clone()]).validate(&heavy.seal(&kp), &g),
+            Err(HeaderError::BadDifficulty { got: 2, max: 1 })
+        );
+    }
+}
@@ -1,2 +1,4 @@
 pub mod envelope;
-pub mod mini_sync;
\ No newline at end of file
+pub mod gossip;
+pub mod mini_sync;
+pub mod tx;
\ No newline at end of file
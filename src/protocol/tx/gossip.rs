@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::protocol::mini_sync::announce::Announcer;
+use crate::protocol::tx::message::TxMessage;
+use crate::protocol::tx::pool::{PoolError, TxPool};
+use crate::protocol::tx::transaction::Transaction;
+
+// hashes asked for (or served) in one request
+const MAX_REQUEST_HASHES: usize = 256;
+// after this long, another announcer may be asked for the same hash
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+// reputation cost of each transaction with a bad hash or signature
+const INVALID_TX_PENALTY: i32 = -20;
+
+/// Side effects of transaction gossip, carried out by the network layer.
+#[derive(Debug)]
+pub enum TxAction {
+    Send { peer: String, msg: TxMessage },
+    Score { peer: String, delta: i32 },
+}
+
+/// The transaction pool plus the eth/68-style exchange that fills it.
+#[derive(Debug)]
+pub struct TxGossip {
+    pool: TxPool,
+    announcer: Announcer,
+    fetching: HashMap<String, Instant>, // hash -> when we asked for it
+}
+
+impl TxGossip {
+    pub fn new(pool: TxPool) -> Self {
+        Self {
+            pool,
+            announcer: Announcer::new(),
+            fetching: HashMap::new(),
+        }
+    }
+
+    pub fn pool(&self) -> &TxPool {
+        &self.pool
+    }
+
+    pub fn pool_mut(&mut self) -> &mut TxPool {
+        &mut self.pool
+    }
+
+    /// Announce everything we hold to a newly connected peer.
+    pub fn on_connect(&mut self, peer: &str) -> Vec<TxAction> {
+        let (mut sizes, mut hashes) = (vec![], vec![]);
+        for tx in self.pool.iter() {
+            if !self.announcer.peer_knows(peer, &tx.hash) {
+                sizes.push(tx.size());
+                hashes.push(tx.hash.clone());
+            }
+        }
+        for hash in &hashes {
+            self.announcer.mark(peer, hash);
+        }
+        if hashes.is_empty() {
+            return vec![];
+        }
+        vec![TxAction::Send {
+            peer: peer.to_string(),
+            msg: TxMessage::NewPooledTransactionHashes { sizes, hashes },
+        }]
+    }
+
+    pub fn forget_peer(&mut self, peer: &str) {
+        self.announcer.forget_peer(peer);
+    }
+
+    /// Pool a locally submitted transaction and spread it to `peers`.
+    pub fn submit(&mut self, tx: Transaction, peers: &[String]) -> Result<Vec<TxAction>, PoolError> {
+        self.pool.add(tx.clone())?;
+        Ok(self.broadcast(&[tx], peers))
+    }
+
+    /// Push each transaction in full to about sqrt(n) of the `peers` that
+    /// lack it and announce its hash to the others.
+    pub fn broadcast(&mut self, txs: &[Transaction], peers: &[String]) -> Vec<TxAction> {
+        let mut full: HashMap<String, Vec<Transaction>> = HashMap::new();
+        let mut announced: HashMap<String, (Vec<u64>, Vec<String>)> = HashMap::new();
+        for tx in txs {
+            let (push, announce) = self.announcer.plan(&tx.hash, peers);
+            for peer in push {
+                full.entry(peer).or_default().push(tx.clone());
+            }
+            for peer in announce {
+                let (sizes, hashes) = announced.entry(peer).or_default();
+                sizes.push(tx.size());
+                hashes.push(tx.hash.clone());
+            }
+        }
+
+        let full = full.into_iter().map(|(peer, txs)| TxAction::Send {
+            peer,
+            msg: TxMessage::Transactions { txs },
+        });
+        let announced = announced.into_iter().map(|(peer, (sizes, hashes))| TxAction::Send {
+            peer,
+            msg: TxMessage::NewPooledTransactionHashes { sizes, hashes },
+        });
+        full.chain(announced).collect()
+    }
+
+    /// Handle a message from `peer`; newly pooled transactions are relayed
+    /// on to the other `peers`.
+    pub fn handle(&mut self, peer: &str, msg: TxMessage, peers: &[String], now: Instant) -> Vec<TxAction> {
+        match msg {
+            TxMessage::Transactions { txs } | TxMessage::PooledTransactions { txs } => {
+                self.receive(peer, txs, peers)
+            }
+            TxMessage::NewPooledTransactionHashes { hashes, .. } => {
+                self.fetching.retain(|_, asked| now.duration_since(*asked) < FETCH_TIMEOUT);
+                let mut wanted = vec![];
+                for hash in hashes {
+                    self.announcer.mark(peer, &hash);
+                    let unknown = !self.pool.contains(&hash) && !self.fetching.contains_key(&hash);
+                    if unknown && wanted.len() < MAX_REQUEST_HASHES {
+                        self.fetching.insert(hash.clone(), now);
+                        wanted.push(hash);
+                    }
+                }
+                if wanted.is_empty() {
+                    return vec![];
+                }
+                vec![TxAction::Send {
+                    peer: peer.to_string(),
+                    msg: TxMessage::GetPooledTransactions { hashes: wanted },
+                }]
+            }
+            TxMessage::GetPooledTransactions { hashes } => {
+                let txs = hashes
+                    .iter()
+                    .take(MAX_REQUEST_HASHES)
+                    .filter_map(|h| self.pool.get(h).cloned())
+                    .collect();
+                vec![TxAction::Send {
+                    peer: peer.to_string(),
+                    msg: TxMessage::PooledTransactions { txs },
+                }]
+            }
+        }
+    }
+
+    fn receive(&mut self, peer: &str, txs: Vec<Transaction>, peers: &[String]) -> Vec<TxAction> {
+        let mut accepted = vec![];
+        let mut invalid = 0;
+        for tx in txs {
+            self.announcer.mark(peer, &tx.hash);
+            self.fetching.remove(&tx.hash);
+            match self.pool.add(tx.clone()) {
+                Ok(()) => accepted.push(tx),
+                Err(PoolError::BadHash | PoolError::InvalidSignature) => invalid += 1,
+                // stale, underpriced or already known: normal churn
+                Err(_) => {}
+            }
+        }
+
+        let mut actions = vec![];
+        if invalid > 0 {
+            println!("[TX] {} sent {} invalid transactions", peer, invalid);
+            actions.push(TxAction::Score {
+                peer: peer.to_string(),
+                delta: INVALID_TX_PENALTY * invalid,
+            });
+        }
+        actions.extend(self.broadcast(&accepted, peers));
+        actions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_keypair;
+    use crate::protocol::tx::pool::PoolConfig;
+
+    fn sends(actions: Vec<TxAction>) -> Vec<(String, TxMessage)> {
+        actions
+            .into_iter()
+            .filter_map(|a| match a {
+                TxAction::Send { peer, msg } => Some((peer, msg)),
+                TxAction::Score { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn announced_transactions_are_fetched_once_and_relayed() {
+        let now = Instant::now();
+        let key = generate_keypair().unwrap();
+        let tx = Transaction {
+            gas_price: 10,
+            to: "0xbeef".into(),
+            ..Default::default()
+        }
+        .sign(&key);
+
+        let mut a = TxGossip::new(TxPool::new(PoolConfig::default()));
+        let mut b = TxGossip::new(TxPool::new(PoolConfig::default()));
+        let peers: Vec<String> = ["b", "c", "d", "e", "f"].iter().map(|p| p.to_string()).collect();
+
+        // 5 peers: 3 get the transaction, 2 only its hash
+        let out = sends(a.submit(tx.clone(), &peers).unwrap());
+        let full = out.iter().filter(|(_, m)| matches!(m, TxMessage::Transactions { .. })).count();
+        assert_eq!((full, out.len()), (3, 5));
+
+        let ann = TxMessage::NewPooledTransactionHashes {
+            sizes: vec![tx.size()],
+            hashes: vec![tx.hash.clone()],
+        };
+        let [(_, get)] = &sends(b.handle("a", ann.clone(), &[], now))[..] else {
+            panic!("expected a request")
+        };
+        // a second announcer is not asked while the first request is pending
+        assert!(b.handle("c", ann, &[], now).is_empty());
+
+        let [(_, reply)] = &sends(a.handle("b", get.clone(), &[], now))[..] else {
+            panic!("expected a reply")
+        };
+        let relay = sends(b.handle("a", reply.clone(), &["a".into(), "c".into(), "g".into()], now));
+        assert!(b.pool().contains(&tx.hash));
+        // a and c already have it
+        assert_eq!(relay.len(), 1);
+        assert_eq!(relay[0].0, "g");
+
+        let mut forged = Transaction { nonce: 1, ..tx.clone() }.sign(&key);
+        forged.value = 5;
+        let actions = b.handle("g", TxMessage::Transactions { txs: vec![forged] }, &[], now);
+        assert!(matches!(&actions[..], [TxAction::Score { delta: -20, .. }]));
+    }
+}
@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::tx::transaction::Transaction;
+
+/// Transaction exchange in the style of eth/68: full transactions are
+/// pushed to a few peers, the rest get hashes and pull what they lack.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum TxMessage {
+    Transactions { txs: Vec<Transaction> },
+    /// `sizes[i]` is the encoded size of `hashes[i]`
+    NewPooledTransactionHashes { sizes: Vec<u64>, hashes: Vec<String> },
+    GetPooledTransactions { hashes: Vec<String> },
+    PooledTransactions { txs: Vec<Transaction> },
+}
+
+impl TxMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("serialize tx msg")
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Option<Self> {
+        serde_json::from_slice(b).ok()
+    }
+}
@@ -0,0 +1,4 @@
+pub mod transaction;
+pub mod message;
+pub mod pool;
+pub mod gossip;
@@ -0,0 +1,377 @@
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+
+use thiserror::Error;
+
+use crate::protocol::tx::transaction::Transaction;
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum PoolError {
+    #[error("transaction {0} is already pooled")]
+    AlreadyKnown(String),
+
+    #[error("hash does not match the transaction contents")]
+    BadHash,
+
+    #[error("invalid signature")]
+    InvalidSignature,
+
+    #[error("nonce {got} is below the sender's next nonce {expected}")]
+    NonceTooLow { expected: u64, got: u64 },
+
+    #[error("sender already has {0} transactions pooled or queued ahead")]
+    SenderLimit(usize),
+
+    #[error("replacing a pooled transaction needs a gas price of at least {min}")]
+    ReplacementUnderpriced { min: u64 },
+
+    #[error("the pooled transaction pays too much to be outbid")]
+    CannotOutbid,
+
+    #[error("gas price {0} is too low to enter the pool")]
+    Underpriced(u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub capacity: usize,
+    pub max_per_sender: usize,
+    /// how much more a replacement for the same sender and nonce must pay
+    pub price_bump_percent: u64,
+    pub min_gas_price: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 4096,
+            max_per_sender: 16,
+            price_bump_percent: 10,
+            min_gas_price: 1,
+        }
+    }
+}
+
+/// Pending transactions, grouped per sender and ordered by nonce.
+///
+/// A sender's transactions are executable ("pending") while their nonces
+/// run without gaps from the sender's next nonce; anything after a gap is
+/// queued. Once full, the pool makes room by dropping the cheapest
+/// transaction that is last in its sender's run, so eviction never opens
+/// a new gap.
+#[derive(Debug)]
+pub struct TxPool {
+    config: PoolConfig,
+    all: HashMap<String, Transaction>,
+    by_sender: HashMap<String, BTreeMap<u64, String>>, // sender -> nonce -> hash
+    next_nonce: HashMap<String, u64>,                  // sender -> first nonce not yet included
+}
+
+impl TxPool {
+    pub fn new(config: PoolConfig) -> Self {
+        Self {
+            config,
+            all: HashMap::new(),
+            by_sender: HashMap::new(),
+            next_nonce: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.all.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.all.is_empty()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.all.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&Transaction> {
+        self.all.get(hash)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.all.values()
+    }
+
+    pub fn next_nonce(&self, sender: &str) -> u64 {
+        self.next_nonce.get(sender).copied().unwrap_or(0)
+    }
+
+    pub fn add(&mut self, tx: Transaction) -> Result<(), PoolError> {
+        if self.all.contains_key(&tx.hash) {
+            return Err(PoolError::AlreadyKnown(tx.hash));
+        }
+        if tx.compute_hash() != tx.hash {
+            return Err(PoolError::BadHash);
+        }
+        if !tx.verify_signature() {
+            return Err(PoolError::InvalidSignature);
+        }
+        if tx.gas_price < self.config.min_gas_price {
+            return Err(PoolError::Underpriced(tx.gas_price));
+        }
+        let expected = self.next_nonce(&tx.from);
+        if tx.nonce < expected {
+            return Err(PoolError::NonceTooLow {
+                expected,
+                got: tx.nonce,
+            });
+        }
+
+        let limit = self.config.max_per_sender;
+        let pooled = self.by_sender.get(&tx.from);
+        if let Some(old) = pooled.and_then(|p| p.get(&tx.nonce)).cloned() {
+            return self.replace(&old, tx);
+        }
+        if tx.nonce - expected >= limit as u64 || pooled.is_some_and(|p| p.len() >= limit) {
+            return Err(PoolError::SenderLimit(limit));
+        }
+
+        if self.all.len() >= self.config.capacity {
+            let cheapest = self.cheapest_tail(&tx).filter(|t| t.gas_price < tx.gas_price);
+            let Some(victim) = cheapest.map(|t| t.hash.clone()) else {
+                return Err(PoolError::Underpriced(tx.gas_price));
+            };
+            self.remove(&victim);
+        }
+        self.insert(tx);
+        Ok(())
+    }
+
+    /// Executable transactions, best price first, each sender's in nonce order.
+    pub fn pending(&self, limit: usize) -> Vec<Transaction> {
+        let mut runs: Vec<VecDeque<&Transaction>> = self
+            .by_sender
+            .iter()
+            .map(|(sender, txs)| {
+                let mut expect = self.next_nonce(sender);
+                txs.iter()
+                    .map_while(|(&nonce, hash)| {
+                        (nonce == expect).then(|| {
+                            expect = expect.saturating_add(1);
+                            &self.all[hash]
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // always take the best-paying head among all senders
+        let mut heads: BinaryHeap<(u64, usize)> = runs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, run)| Some((run.front()?.gas_price, i)))
+            .collect();
+        let mut out = vec![];
+        while out.len() < limit
+            && let Some((_, i)) = heads.pop()
+        {
+            let tx = runs[i].pop_front().expect("heads only hold non-empty runs");
+            out.push(tx.clone());
+            if let Some(next) = runs[i].front() {
+                heads.push((next.gas_price, i));
+            }
+        }
+        out
+    }
+
+    /// Forget transactions that made it into a block and anything they
+    /// made stale, and move each sender's next nonce past them.
+    pub fn remove_included(&mut self, txs: &[Transaction]) {
+        for tx in txs {
+            let next = self.next_nonce.entry(tx.from.clone()).or_default();
+            *next = (*next).max(tx.nonce.saturating_add(1));
+            let next = *next;
+            // at the last nonce `next` can't move past it
+            self.remove(&tx.hash);
+
+            let stale: Vec<String> = self
+                .by_sender
+                .get(&tx.from)
+                .map(|p| p.range(..next).map(|(_, h)| h.clone()).collect())
+                .unwrap_or_default();
+            for hash in stale {
+                self.remove(&hash);
+            }
+        }
+    }
+
+    fn replace(&mut self, old: &str, tx: Transaction) -> Result<(), PoolError> {
+        let old_price = self.all[old].gas_price;
+        let bump = 100u64.saturating_add(self.config.price_bump_percent);
+        let min = old_price
+            .checked_mul(bump)
+            .map(|p| p / 100)
+            .zip(old_price.checked_add(1))
+            .map(|(bumped, next)| bumped.max(next))
+            .ok_or(PoolError::CannotOutbid)?;
+        if tx.gas_price < min {
+            return Err(PoolError::ReplacementUnderpriced { min });
+        }
+        self.remove(old);
+        self.insert(tx);
+        Ok(())
+    }
+
+    // the last transaction of every sender is fair game, except one of the
+    // newcomer's own that it would then sit behind a gap from; take the cheapest
+    fn cheapest_tail(&self, incoming: &Transaction) -> Option<&Transaction> {
+        self.by_sender
+            .values()
+            .filter_map(|txs| txs.values().next_back())
+            .map(|hash| &self.all[hash])
+            .filter(|tail| tail.from != incoming.from || tail.nonce > incoming.nonce)
+            .min_by_key(|tx| tx.gas_price)
+    }
+
+    fn insert(&mut self, tx: Transaction) {
+        self.by_sender
+            .entry(tx.from.clone())
+            .or_default()
+            .insert(tx.nonce, tx.hash.clone());
+        self.all.insert(tx.hash.clone(), tx);
+    }
+
+    fn remove(&mut self, hash: &str) {
+        let Some(tx) = self.all.remove(hash) else { return };
+        if let Some(txs) = self.by_sender.get_mut(&tx.from) {
+            txs.remove(&tx.nonce);
+            if txs.is_empty() {
+                self.by_sender.remove(&tx.from);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{generate_keypair, sign_message, Keypair};
+
+    fn tx(key: &Keypair, nonce: u64, gas_price: u64) -> Transaction {
+        Transaction {
+            nonce,
+            gas_price,
+            gas_limit: 21_000,
+            to: "0xbeef".into(),
+            value: 1,
+            ..Default::default()
+        }
+        .sign(key)
+    }
+
+    fn nonces(txs: &[Transaction]) -> Vec<(u64, u64)> {
+        txs.iter().map(|t| (t.nonce, t.gas_price)).collect()
+    }
+
+    #[test]
+    fn orders_by_nonce_then_price_and_replaces_with_a_bump() {
+        let (alice, bob) = (generate_keypair().unwrap(), generate_keypair().unwrap());
+        let mut pool = TxPool::new(PoolConfig::default());
+
+        pool.add(tx(&alice, 1, 50)).unwrap();
+        // nonce 1 waits behind the missing nonce 0
+        assert!(pool.pending(10).is_empty());
+        pool.add(tx(&alice, 0, 5)).unwrap();
+        pool.add(tx(&bob, 0, 20)).unwrap();
+        assert_eq!(nonces(&pool.pending(10)), vec![(0, 20), (0, 5), (1, 50)]);
+
+        assert_eq!(
+            pool.add(tx(&alice, 0, 5)).unwrap_err(),
+            PoolError::AlreadyKnown(tx(&alice, 0, 5).hash)
+        );
+        // same sender and nonce: a replacement has to pay 10% more
+        pool.add(tx(&alice, 0, 30)).unwrap();
+        assert_eq!(
+            pool.add(tx(&alice, 0, 32)),
+            Err(PoolError::ReplacementUnderpriced { min: 33 })
+        );
+        assert_eq!(pool.len(), 3);
+
+        let mut forged = tx(&bob, 1, 20);
+        forged.value = 1_000;
+        assert_eq!(pool.add(forged), Err(PoolError::BadHash));
+
+        let included = pool.pending(1);
+        pool.remove_included(&included);
+        assert_eq!(pool.next_nonce(&included[0].from), 1);
+        assert_eq!(
+            pool.add(tx(&alice, 0, 99)),
+            Err(PoolError::NonceTooLow { expected: 1, got: 0 })
+        );
+    }
+
+    #[test]
+    fn prices_and_nonces_near_the_limit_do_not_overflow() {
+        let key = generate_keypair().unwrap();
+        let mut pool = TxPool::new(PoolConfig::default());
+
+        pool.add(tx(&key, 0, u64::MAX / 10)).unwrap();
+        assert_eq!(pool.add(tx(&key, 0, u64::MAX)), Err(PoolError::CannotOutbid));
+
+        pool.remove_included(&[tx(&key, u64::MAX - 1, 1)]);
+        let last = tx(&key, u64::MAX, 1);
+        pool.add(last.clone()).unwrap();
+        assert_eq!(pool.pending(10), vec![last.clone()]);
+        pool.remove_included(std::slice::from_ref(&last));
+        assert_eq!(pool.next_nonce(&last.from), u64::MAX);
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn limits_senders_and_evicts_the_cheapest_tail() {
+        let keys: Vec<Keypair> = (0..3).map(|_| generate_keypair().unwrap()).collect();
+        let mut pool = TxPool::new(PoolConfig {
+            capacity: 4,
+            max_per_sender: 2,
+            ..Default::default()
+        });
+
+        pool.add(tx(&keys[0], 0, 10)).unwrap();
+        pool.add(tx(&keys[0], 1, 10)).unwrap();
+        assert_eq!(pool.add(tx(&keys[0], 2, 10)), Err(PoolError::SenderLimit(2)));
+
+        pool.add(tx(&keys[1], 0, 3)).unwrap();
+        pool.add(tx(&keys[1], 1, 1)).unwrap();
+        // full: a cheaper newcomer bounces, a pricier one evicts keys[1]'s last
+        assert_eq!(pool.add(tx(&keys[2], 0, 1)), Err(PoolError::Underpriced(1)));
+        pool.add(tx(&keys[2], 0, 7)).unwrap();
+        assert_eq!(pool.len(), 4);
+        assert!(!pool.contains(&tx(&keys[1], 1, 1).hash));
+        assert!(pool.contains(&tx(&keys[1], 0, 3).hash));
+
+        // keys[1]'s own tail is now the cheapest, but dropping it would
+        // strand the newcomer behind a gap, so keys[2] pays instead
+        pool.add(tx(&keys[1], 1, 8)).unwrap();
+        assert!(pool.contains(&tx(&keys[1], 0, 3).hash));
+        assert!(!pool.contains(&tx(&keys[2], 0, 7).hash));
+    }
+
+    #[test]
+    fn senders_must_spell_their_key_one_way() {
+        let key = generate_keypair().unwrap();
+        let mut pool = TxPool::new(PoolConfig::default());
+        let resigned = |from: String| {
+            let mut t = Transaction {
+                from,
+                ..tx(&key, 0, 10)
+            };
+            t.hash = t.compute_hash();
+            let mut digest = [0u8; 32];
+            hex::decode_to_slice(&t.hash[2..], &mut digest).unwrap();
+            t.signature = hex::encode(sign_message(&key.signing_key, &digest).unwrap().to_bytes());
+            t
+        };
+
+        let canonical = tx(&key, 0, 10);
+        assert_eq!(resigned(canonical.from.clone()), canonical);
+        let upper = resigned(canonical.from.to_uppercase());
+        assert_eq!(pool.add(upper), Err(PoolError::InvalidSignature));
+        let uncompressed = hex::encode(key.verifying_key.to_encoded_point(false).as_bytes());
+        assert_eq!(pool.add(resigned(uncompressed)), Err(PoolError::InvalidSignature));
+        pool.add(canonical).unwrap();
+    }
+}
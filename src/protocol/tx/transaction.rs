@@ -0,0 +1,75 @@
+use k256::ecdsa::{Signature, VerifyingKey};
+use rlp::RlpStream;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{keccak256, sign_message, verify_signature, Keypair};
+
+/// A minimal value transfer. There is no account state behind it yet, so
+/// only the signature, nonce and price mean anything to the node.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    pub nonce: u64,
+    pub gas_price: u64,
+    pub gas_limit: u64,
+    pub to: String,
+    pub value: u64,
+    #[serde(default)]
+    pub data: Vec<u8>,
+    pub from: String, // hex SEC1-compressed secp256k1 pubkey of the sender
+    pub hash: String,
+    pub signature: String, // hex r||s over `hash`
+}
+
+impl Transaction {
+    /// keccak256 over the RLP of every field except `hash` and `signature`.
+    pub fn compute_hash(&self) -> String {
+        format!("0x{}", hex::encode(self.signing_digest()))
+    }
+
+    fn signing_digest(&self) -> [u8; 32] {
+        let mut s = RlpStream::new_list(7);
+        s.append(&self.nonce);
+        s.append(&self.gas_price);
+        s.append(&self.gas_limit);
+        s.append(&self.to);
+        s.append(&self.value);
+        s.append(&self.data);
+        s.append(&self.from);
+        keccak256(&s.out())
+    }
+
+    /// Fill in `from`, `hash` and `signature` using the sender's key.
+    pub fn sign(mut self, keypair: &Keypair) -> Self {
+        self.from = hex::encode(keypair.verifying_key.to_sec1_bytes());
+        let digest = self.signing_digest();
+        let sig = sign_message(&keypair.signing_key, &digest).expect("tx signing failed");
+        self.hash = format!("0x{}", hex::encode(digest));
+        self.signature = hex::encode(sig.to_bytes());
+        self
+    }
+
+    /// Check `signature` over `hash` against `from`, which must be the
+    /// lowercase hex of the compressed key.
+    pub fn verify_signature(&self) -> bool {
+        let mut digest = [0u8; 32];
+        let hash = self.hash.trim_start_matches("0x");
+        if hex::decode_to_slice(hash, &mut digest).is_err() {
+            return false;
+        }
+        let Ok(key_bytes) = hex::decode(&self.from) else { return false };
+        let Ok(sig_bytes) = hex::decode(&self.signature) else { return false };
+        let Ok(key) = VerifyingKey::from_sec1_bytes(&key_bytes) else { return false };
+        // one spelling per sender, or per-sender limits could be dodged by
+        // re-encoding the same key
+        if hex::encode(key.to_sec1_bytes()) != self.from {
+            return false;
+        }
+        let Ok(sig) = Signature::from_slice(&sig_bytes) else { return false };
+        verify_signature(&key, &digest, &sig)
+    }
+
+    /// Encoded size, as announced to peers.
+    pub fn size(&self) -> u64 {
+        serde_json::to_vec(self).map(|b| b.len() as u64).unwrap_or(0)
+    }
+}
@@ -1,4 +1,6 @@
-use crate::session::message::{Hello, HelloAck, SessionMessage};
+use crate::fork_id::IncompatiblePeer;
+use crate::protocol::envelope::MAX_FRAME_SIZE;
+use crate::session::message::{ChainInfo, Hello, HelloAck, SessionMessage};
 use crate::session::state::PeerSession;
 use quinn::Connection;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -24,24 +26,43 @@ async fn send_frame(conn: &Connection, payload: &[u8]) -> Result<(), ()> {
 
 /// Read one framed message from an accepted bi-stream
 async fn read_frame(recv: &mut quinn::RecvStream) -> Result<Vec<u8>, ()> {
-    let len = recv.read_u32().await.map_err(|_| ())?;
-    let mut data = vec![0u8; len as usize];
+    let len = recv.read_u32().await.map_err(|_| ())? as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(());
+    }
+    let mut data = vec![0u8; len];
     recv.read_exact(&mut data).await.map_err(|_| ())?;
     Ok(data)
 }
 
+/// Drop the connection if the peer's chain fails `check`.
+fn reject_if_incompatible(
+    conn: &Connection,
+    node_id: &str,
+    remote: &ChainInfo,
+    check: impl Fn(&ChainInfo) -> Result<(), IncompatiblePeer>,
+) -> Result<(), ()> {
+    if let Err(e) = check(remote) {
+        println!("[SESS] rejecting {}: {}", node_id, e);
+        conn.close(1u32.into(), b"incompatible chain");
+        return Err(());
+    }
+    Ok(())
+}
+
 /// Outbound handshake: we dial someone and initiate Hello, wait for HelloAck.
 pub async fn outbound_handshake(
     conn: &Connection,
     local_node_id: &str,
     local_caps: &[String],
+    local_chain: &ChainInfo,
+    check: impl Fn(&ChainInfo) -> Result<(), IncompatiblePeer>,
 ) -> Result<PeerSession, ()> {
     let hello = Hello {
         node_id: local_node_id.to_string(),
         protocol_version: 1,
         capabilities: local_caps.to_vec(),
-        genesis: "0xgenesis".to_string(),
-        head_height: 0,
+        chain: local_chain.clone(),
     };
 
     send_frame(conn, &SessionMessage::Hello(hello).to_bytes()).await?;
@@ -51,10 +72,13 @@ pub async fn outbound_handshake(
     let data = read_frame(&mut recv).await?;
 
     match SessionMessage::from_bytes(&data) {
-        Some(SessionMessage::HelloAck(ack)) => Ok(PeerSession {
-            remote_node_id: ack.node_id,
-            agreed_caps: ack.agreed_capabilities,
-        }),
+        Some(SessionMessage::HelloAck(ack)) => {
+            reject_if_incompatible(conn, &ack.node_id, &ack.chain, check)?;
+            Ok(PeerSession {
+                remote_node_id: ack.node_id,
+                agreed_caps: ack.agreed_capabilities,
+            })
+        }
         _ => Err(()),
     }
 }
@@ -64,6 +88,8 @@ pub async fn inbound_handshake(
     conn: &Connection,
     local_node_id: &str,
     local_caps: &[String],
+    local_chain: &ChainInfo,
+    check: impl Fn(&ChainInfo) -> Result<(), IncompatiblePeer>,
 ) -> Result<PeerSession, ()> {
     // Wait for their Hello
     let (_send, mut recv) = conn.accept_bi().await.map_err(|_| ())?;
@@ -73,6 +99,7 @@ pub async fn inbound_handshake(
         Some(SessionMessage::Hello(h)) => h,
         _ => return Err(()),
     };
+    reject_if_incompatible(conn, &remote_hello.node_id, &remote_hello.chain, check)?;
 
     let agreed = intersect_caps(local_caps, &remote_hello.capabilities);
 
@@ -80,6 +107,7 @@ pub async fn inbound_handshake(
     let ack = HelloAck {
         node_id: local_node_id.to_string(),
         agreed_capabilities: agreed.clone(),
+        chain: local_chain.clone(),
     };
 
     send_frame(conn, &SessionMessage::HelloAck(ack).to_bytes()).await?;
@@ -1,18 +1,38 @@
 use serde::{Deserialize, Serialize};
 
+use crate::fork_id::ForkId;
+
+/// Which chain a node is on; both sides send it and both sides check it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainInfo {
+    pub network_id: u64,
+    pub genesis: String,          // hex hash of the genesis header
+    pub head_height: u64,
+    pub fork_id: ForkId,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Hello {
     pub node_id: String,          // hex string (same style as ENR)
     pub protocol_version: u32,    // for MiniEthNet session layer
     pub capabilities: Vec<String>,// e.g. ["discv-lite/0.1", "mini-sync/0.1"]
-    pub genesis: String,          // hex hash (placeholder for now)
-    pub head_height: u64,         // placeholder for now
+    #[serde(flatten)]
+    pub chain: ChainInfo,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HelloAck {
     pub node_id: String,
     pub agreed_capabilities: Vec<String>,
+    #[serde(flatten)]
+    pub chain: ChainInfo,
+}
+
+/// Sent instead of an answer when a peer asks faster than we serve it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Throttled {
+    pub kind: String, // the message type that went over its limit
+    pub retry_after_ms: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +40,7 @@ pub struct HelloAck {
 pub enum SessionMessage {
     Hello(Hello),
     HelloAck(HelloAck),
+    Throttled(Throttled),
 }
 
 impl SessionMessage {
@@ -1,3 +1,5 @@
 pub mod message;
 pub mod state;
 pub mod handshake;
+pub mod score;
+pub mod ratelimit;
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// bucket key for every kind without a limit of its own, so made-up kinds
+// cannot make us hold a bucket each
+const DEFAULT_KIND: &str = "*";
+
+/// How many requests of one kind a peer may send at once, and how fast
+/// that allowance refills.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub burst: u32,
+    pub per_sec: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// Token buckets per peer and message kind. Kinds without a limit of
+/// their own share one bucket under the default limit.
+#[derive(Debug)]
+pub struct RateLimiter {
+    default: Limit,
+    limits: HashMap<String, Limit>,
+    buckets: HashMap<(String, String), Bucket>, // (peer, kind)
+}
+
+impl RateLimiter {
+    pub fn new(default: Limit) -> Self {
+        Self {
+            default,
+            limits: HashMap::new(),
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn with_limit(mut self, kind: &str, limit: Limit) -> Self {
+        self.limits.insert(kind.to_string(), limit);
+        self
+    }
+
+    /// Take a token for a `kind` message from `peer`; when none is left,
+    /// how long until the next one.
+    pub fn check(&mut self, peer: &str, kind: &str, now: Instant) -> Result<(), Duration> {
+        let (kind, limit) = match self.limits.get_key_value(kind) {
+            Some((kind, limit)) => (kind.as_str(), *limit),
+            None => (DEFAULT_KIND, self.default),
+        };
+        let bucket = self
+            .buckets
+            .entry((peer.to_string(), kind.to_string()))
+            .or_insert(Bucket {
+                tokens: limit.burst as f64,
+                last: now,
+            });
+        let refill = now.duration_since(bucket.last).as_secs_f64() * limit.per_sec as f64;
+        bucket.tokens = (bucket.tokens + refill).min(limit.burst as f64);
+        bucket.last = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / limit.per_sec.max(1) as f64))
+    }
+
+    /// Drop a disconnected peer's buckets.
+    pub fn forget(&mut self, peer: &str) {
+        self.buckets.retain(|(p, _), _| p != peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_per_peer_and_kind_and_refill() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(Limit { burst: 100, per_sec: 100 })
+            .with_limit("RequestHeaders", Limit { burst: 2, per_sec: 4 });
+
+        assert!(limiter.check("a", "RequestHeaders", now).is_ok());
+        assert!(limiter.check("a", "RequestHeaders", now).is_ok());
+        let wait = limiter.check("a", "RequestHeaders", now).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(250));
+
+        // other peers and other kinds have buckets of their own
+        assert!(limiter.check("b", "RequestHeaders", now).is_ok());
+        assert!(limiter.check("a", "FindNodes", now).is_ok());
+
+        // kinds without a limit all draw from one shared bucket
+        for n in 0..99 {
+            assert!(limiter.check("a", &format!("Junk{n}"), now).is_ok());
+        }
+        assert!(limiter.check("a", "Junk", now).is_err());
+        assert_eq!(limiter.buckets.len(), 3);
+
+        assert!(limiter.check("a", "RequestHeaders", now + wait).is_ok());
+        assert!(limiter.check("a", "RequestHeaders", now + wait).is_err());
+        limiter.forget("a");
+        assert!(limiter.check("a", "RequestHeaders", now + wait).is_ok());
+    }
+}
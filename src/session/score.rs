@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use crate::transport::quic::endpoint::peer_host;
+
+// peers at or below this are disconnected and refused
+pub const BAN_THRESHOLD: i32 = -100;
+const MAX_SCORE: i32 = 100;
+
+/// Running reputation per peer. Peers are scored by host, so a banned
+/// peer stays banned when it reconnects from another port.
+#[derive(Debug, Default)]
+pub struct PeerScores {
+    scores: HashMap<String, i32>, // host -> score
+}
+
+impl PeerScores {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply `delta` and return the new score.
+    pub fn adjust(&mut self, peer: &str, delta: i32) -> i32 {
+        let s = self.scores.entry(peer_host(peer)).or_default();
+        *s = (*s + delta).clamp(BAN_THRESHOLD, MAX_SCORE);
+        *s
+    }
+
+    pub fn score(&self, peer: &str) -> i32 {
+        self.scores.get(&peer_host(peer)).copied().unwrap_or(0)
+    }
+
+    pub fn is_banned(&self, peer: &str) -> bool {
+        self.score(peer) <= BAN_THRESHOLD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_follow_the_host_across_ports() {
+        let mut scores = PeerScores::new();
+        scores.adjust("10.0.0.1:30303", 2 * BAN_THRESHOLD);
+        assert!(scores.is_banned("10.0.0.1:30303"));
+        assert!(scores.is_banned("10.0.0.1:40404"));
+        assert!(scores.is_banned("[::ffff:10.0.0.1]:50505"));
+        assert!(!scores.is_banned("10.0.0.2:30303"));
+        assert_eq!(scores.score("10.0.0.1:1"), BAN_THRESHOLD);
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::protocol::mini_sync::fork_choice::ForkChoiceRule;
+use crate::protocol::mini_sync::header::{empty_tx_root, Header};
+use crate::protocol::mini_sync::validation::{MAX_DIFFICULTY, MAX_EXTRA_DATA};
+
+#[derive(Debug, Error)]
+pub enum SpecError {
+    #[error("failed to read chain spec: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid chain spec: {0}")]
+    Parse(#[from] serde_json::Error),
+
+    #[error("invalid chain spec: {0}")]
+    Invalid(String),
+}
+
+/// Fields of the genesis header. Its hash is derived, never written down.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenesisSpec {
+    #[serde(default)]
+    pub timestamp: u64,
+    #[serde(default = "one")]
+    pub difficulty: u64,
+    // plain text, stored as the header's extra_data bytes
+    #[serde(default)]
+    pub extra_data: String,
+}
+
+/// A header the operator trusts without verifying its history
+/// (weak-subjectivity sync). The node anchors its chain here instead of genesis.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub number: u64,
+    pub hash: String,
+    /// also fetch the headers below the checkpoint, back to genesis
+    #[serde(default)]
+    pub backfill: bool,
+}
+
+impl FromStr for Checkpoint {
+    type Err = SpecError;
+
+    /// `<number>:<hash>`, as given on the command line.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || SpecError::Invalid(format!("checkpoint {s:?} is not <number>:<hash>"));
+        let (number, hash) = s.split_once(':').ok_or_else(bad)?;
+        let number = number.parse().map_err(|_| bad())?;
+        if hex::decode(hash.trim_start_matches("0x")).is_err() {
+            return Err(bad());
+        }
+        Ok(Self {
+            number,
+            hash: hash.to_string(),
+            backfill: false,
+        })
+    }
+}
+
+/// Everything that defines a network, loaded from a JSON file via `--chain`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainSpec {
+    pub name: String,
+    pub network_id: u64,
+    pub genesis: GenesisSpec,
+    #[serde(default)]
+    pub fork_choice: ForkChoiceRule,
+    /// hex SEC1 pubkeys allowed to produce headers; empty = anyone
+    #[serde(default)]
+    pub authorities: Vec<String>,
+    #[serde(default = "default_slot_secs")]
+    pub slot_secs: u64,
+    /// nodes to dial on startup: "host:port", "enr:...", "enr-lite:..." or
+    /// an "enrtree://" DNS tree
+    #[serde(default)]
+    pub bootnodes: Vec<String>,
+    /// heights at which the rules change; peers must agree on the ones passed
+    #[serde(default)]
+    pub forks: Vec<u64>,
+    /// start from this trusted header instead of syncing from genesis
+    #[serde(default)]
+    pub checkpoint: Option<Checkpoint>,
+}
+
+fn one() -> u64 {
+    1
+}
+
+fn default_slot_secs() -> u64 {
+    2
+}
+
+impl ChainSpec {
+    /// Built-in single-machine dev network.
+    pub fn dev() -> Self {
+        Self {
+            name: "dev".to_string(),
+            network_id: 1337,
+            genesis: GenesisSpec {
+                timestamp: 0,
+                difficulty: 1,
+                extra_data: "ethnetlite dev".to_string(),
+            },
+            fork_choice: ForkChoiceRule::default(),
+            authorities: vec![],
+            slot_secs: default_slot_secs(),
+            bootnodes: vec![],
+            forks: vec![],
+            checkpoint: None,
+        }
+    }
+
+    /// `dev` for the built-in spec, otherwise a path to a JSON file.
+    pub fn load(arg: &str) -> Result<Self, SpecError> {
+        if arg == "dev" {
+            return Ok(Self::dev());
+        }
+        Self::from_file(Path::new(arg))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, SpecError> {
+        let spec: Self = serde_json::from_slice(&fs::read(path)?)?;
+        spec.check()?;
+        Ok(spec)
+    }
+
+    fn check(&self) -> Result<(), SpecError> {
+        if self.slot_secs == 0 {
+            return Err(SpecError::Invalid("slot_secs must be > 0".into()));
+        }
+        if self.genesis.extra_data.len() > MAX_EXTRA_DATA {
+            return Err(SpecError::Invalid("genesis extra_data too long".into()));
+        }
+        if !(1..=MAX_DIFFICULTY).contains(&self.genesis.difficulty) {
+            return Err(SpecError::Invalid(format!(
+                "genesis difficulty must be between 1 and {MAX_DIFFICULTY}"
+            )));
+        }
+        if let Some(bad) = self.authorities.iter().find(|a| hex::decode(a).is_err()) {
+            return Err(SpecError::Invalid(format!("authority {bad} is not hex")));
+        }
+        Ok(())
+    }
+
+    pub fn genesis_header(&self) -> Header {
+        let mut h = Header {
+            parent_hash: "0x00".to_string(),
+            number: 0,
+            difficulty: self.genesis.difficulty,
+            timestamp: self.genesis.timestamp,
+            tx_root: empty_tx_root(),
+            extra_data: self.genesis.extra_data.as_bytes().to_vec(),
+            ..Default::default()
+        };
+        h.hash = h.compute_hash();
+        h
+    }
+
+    pub fn genesis_hash(&self) -> String {
+        self.genesis_header().hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn genesis_hash_is_derived_from_spec_fields() {
+        let a = ChainSpec::dev();
+        let mut b = ChainSpec::dev();
+        assert_eq!(a.genesis_hash(), b.genesis_hash());
+
+        b.genesis.extra_data = "testnet-2".into();
+        assert_ne!(a.genesis_hash(), b.genesis_hash());
+    }
+
+    #[test]
+    fn parses_minimal_json() {
+        let spec: ChainSpec = serde_json::from_str(
+            r#"{"name":"t","network_id":7,"genesis":{"timestamp":5},"fork_choice":"ghost"}"#,
+        )
+        .unwrap();
+        assert_eq!(spec.fork_choice, ForkChoiceRule::Ghost);
+        assert_eq!(spec.slot_secs, 2);
+        assert_eq!(spec.genesis.difficulty, 1);
+    }
+
+    #[test]
+    fn genesis_difficulty_must_be_one_validators_accept() {
+        let mut spec = ChainSpec::dev();
+        assert!(spec.check().is_ok());
+        for bad in [0, MAX_DIFFICULTY + 1] {
+            spec.genesis.difficulty = bad;
+            let Err(SpecError::Invalid(msg)) = spec.check() else { panic!("difficulty {bad} passed") };
+            assert!(msg.contains("genesis difficulty"));
+        }
+    }
+
+    #[test]
+    fn parses_checkpoint_argument() {
+        let cp: Checkpoint = "1200:0xabcd".parse().unwrap();
+        assert_eq!((cp.number, cp.hash.as_str(), cp.backfill), (1200, "0xabcd", false));
+        assert!("0xabcd".parse::<Checkpoint>().is_err());
+        assert!("12:nothex".parse::<Checkpoint>().is_err());
+    }
+
+    #[test]
+    fn shipped_dev_spec_matches_builtin() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("specs/dev.json");
+        let spec = ChainSpec::from_file(&path).unwrap();
+        assert_eq!(spec.genesis_hash(), ChainSpec::dev().genesis_hash());
+    }
+}
@@ -1,11 +1,11 @@
-use quinn::{ClientConfig, ServerConfig};
+use quinn::{ClientConfig, ServerConfig, TransportConfig};
 use rcgen::generate_simple_self_signed;
 use rustls::{
     Certificate, PrivateKey,
     client::{ServerCertVerified, ServerCertVerifier},
 };
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 /// DEV ONLY — skip cert verification
 struct SkipServerVerification;
@@ -40,5 +40,11 @@ pub fn client_config() -> ClientConfig {
         .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
         .with_no_client_auth();
 
-    ClientConfig::new(Arc::new(tls))
+    // sessions stay open between head announcements instead of idling out
+    let mut transport = TransportConfig::default();
+    transport.keep_alive_interval(Some(Duration::from_secs(10)));
+
+    let mut config = ClientConfig::new(Arc::new(tls));
+    config.transport_config(Arc::new(transport));
+    config
 }
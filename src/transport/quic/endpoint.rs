@@ -1,13 +1,57 @@
 use crate::transport::quic::config::{client_config, server_config};
-use quinn::Endpoint;
-use std::net::SocketAddr;
+use quinn::{Endpoint, EndpointConfig, TokioRuntime};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
 
-pub fn start_endpoint(port: u16) -> Endpoint {
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+/// Listen on `addr`. An IPv6 address takes IPv4 traffic too (as mapped
+/// addresses) unless `ipv6_only` is set.
+pub fn start_endpoint(addr: SocketAddr, ipv6_only: bool) -> io::Result<Endpoint> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    socket.bind(&addr.into())?;
 
-    let mut endpoint = Endpoint::server(server_config(), addr).unwrap();
+    let mut endpoint = Endpoint::new(
+        EndpointConfig::default(),
+        Some(server_config()),
+        socket.into(),
+        Arc::new(TokioRuntime),
+    )?;
     endpoint.set_default_client_config(client_config());
 
-    println!("[QUIC] listening on {}", addr);
-    endpoint
+    let stack = match (addr.is_ipv6(), ipv6_only) {
+        (false, _) => "ipv4",
+        (true, true) => "ipv6",
+        (true, false) => "dual-stack",
+    };
+    println!("[QUIC] listening on {} ({})", addr, stack);
+    Ok(endpoint)
+}
+
+/// Dial-only endpoint on an ephemeral port, for one-off queries to `remote`.
+pub fn client_endpoint(remote: SocketAddr) -> io::Result<Endpoint> {
+    let any: SocketAddr = match remote {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let mut endpoint = Endpoint::client(any)?;
+    endpoint.set_default_client_config(client_config());
+    Ok(endpoint)
+}
+
+/// The address a peer is known by. A dual-stack socket reports IPv4 peers
+/// as IPv4-mapped IPv6 addresses; map those back so each peer has one name.
+pub fn peer_addr(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// The host behind a name from `peer_addr`, shared by all its connections.
+/// Names that are not addresses stand for themselves.
+pub fn peer_host(peer: &str) -> String {
+    peer.parse::<SocketAddr>()
+        .map(|addr| addr.ip().to_canonical().to_string())
+        .unwrap_or_else(|_| peer.to_string())
 }
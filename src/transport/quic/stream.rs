@@ -1,6 +1,6 @@
 use bytes::{BufMut, BytesMut};
 use quinn::Connection;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncReadExt;
 
 pub async fn send(conn: &Connection, msg: &[u8]) {
     let (mut send, _) = conn.open_bi().await.unwrap();